@@ -25,7 +25,7 @@ export function usePlayerSkin(uuid?: string) {
     () => async (uuid: string) => {
       const [face, skin] = await Promise.all([getPlayerFace(uuid, 8), getPlayerSkin(uuid)]);
 
-      return { face, skin };
+      return { face, skin: skin.skin };
     },
     [],
   );
@@ -1,10 +1,10 @@
 import { invoke } from "@tauri-apps/api/core";
 
-type AccountRecord = {
+type AccountSummary = {
   uuid: string;
   username: string;
 };
 
-export async function authenticateAccount(): Promise<AccountRecord> {
+export async function authenticateAccount(): Promise<AccountSummary> {
   return await invoke("plugin:account|authenticate");
 }
@@ -1,7 +1,15 @@
 import { invoke } from "@tauri-apps/api/core";
 
+export type SkinModel = "classic" | "slim";
+
+export type PlayerSkin = {
+  skin: string;
+  model: SkinModel;
+  legacy: boolean;
+};
+
 export async function getPlayerSkin(uuid: string, scale: number = 1) {
-  return await invoke<string>("player_skin", { uuid, scale });
+  return await invoke<PlayerSkin>("player_skin", { uuid, scale });
 }
 
 export async function getPlayerFace(uuid: string, scale: number = 1) {
@@ -8,9 +8,18 @@ fn main() {
                 InlinedPlugin::new()
                     .commands(&[
                         "authenticate",
+                        "authenticate_device_code",
+                        "authenticate_browser",
+                        "authenticate_yggdrasil",
+                        "create_offline_account",
+                        "create_profile",
+                        "check_profile_name",
+                        "get_name_change_info",
+                        "change_profile_name",
                         "get_all",
                         "get_active",
                         "set_active",
+                        "refresh",
                         "remove",
                     ])
                     .default_permission(DefaultPermissionRule::AllowAllCommands),
@@ -25,6 +34,39 @@ fn main() {
                         "get_instances",
                         "rename_instance",
                         "update_instance_icon",
+                        "update_instance_settings",
+                        "launch_instance",
+                        "get_running_instances",
+                    ])
+                    .default_permission(DefaultPermissionRule::AllowAllCommands),
+            )
+            .plugin(
+                "java",
+                InlinedPlugin::new()
+                    .commands(&["list_java_runtimes", "validate_java_path"])
+                    .default_permission(DefaultPermissionRule::AllowAllCommands),
+            )
+            .plugin(
+                "settings",
+                InlinedPlugin::new()
+                    .commands(&["get_settings", "update_settings"])
+                    .default_permission(DefaultPermissionRule::AllowAllCommands),
+            )
+            .plugin(
+                "version",
+                InlinedPlugin::new()
+                    .commands(&["list_versions", "get_version"])
+                    .default_permission(DefaultPermissionRule::AllowAllCommands),
+            )
+            .plugin(
+                "wardrobe",
+                InlinedPlugin::new()
+                    .commands(&[
+                        "list_skins",
+                        "import_skin",
+                        "update_skin",
+                        "remove_skin",
+                        "apply_skin",
                     ])
                     .default_permission(DefaultPermissionRule::AllowAllCommands),
             ),
@@ -1,120 +1,550 @@
-use anyhow::Context;
 use reqwest::Client;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::io::Cursor;
+use tauri::{AppHandle, Runtime};
 
 use base64::Engine;
-use image::{ImageBuffer, RgbaImage};
+use image::{GenericImageView, ImageBuffer, ImageFormat, Pixel, RgbaImage};
 
+use crate::utils::auth_error::AuthError;
+use crate::utils::minecraft_auth;
+use crate::utils::minecraft_services::{
+    MinecraftProfile, MinecraftServices, ProfileCape, SkinVariant,
+};
+use crate::utils::player_lookup::{self, LookupError, PlayerProfile};
+use crate::utils::texture_cache;
+
+/// Skins are tiny; anything bigger than this is not worth downloading.
+const MAX_SKIN_SIZE: usize = 1024 * 1024;
+
+/// A player's skin texture in the 64x64 layout, with the model it is drawn for.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerSkin {
+    /// The texture as a PNG data URL.
+    pub skin: String,
+    pub model: SkinVariant,
+    /// The player uses a legacy 64x32 skin, converted for this response.
+    pub legacy: bool,
+}
+
+/// Resolves several usernames at once; names nobody uses are left out.
+#[tauri::command]
+pub async fn lookup_players<R: Runtime>(
+    app: AppHandle<R>,
+    names: Vec<String>,
+) -> Result<Vec<PlayerProfile>, LookupError> {
+    let profiles = player_lookup::lookup_players(&app, &names).await?;
+    Ok(profiles.into_values().collect())
+}
+
+/// The commands rendering a player take either a UUID, with or without dashes, or a username
+/// in `uuid`.
+#[tauri::command]
+pub async fn player_skin<R: Runtime>(
+    app: AppHandle<R>,
+    uuid: String,
+    scale: u32,
+) -> Result<PlayerSkin, LookupError> {
+    let skin = load_player_skin(&app, &uuid).await?;
+
+    let img = image::DynamicImage::ImageRgba8(skin.image);
+    // Scale full skin if needed
+    let final_img = if scale > 1 {
+        img.resize(64 * scale, 64 * scale, image::imageops::FilterType::Nearest)
+    } else {
+        img
+    };
+
+    // Convert to PNG bytes
+    let mut buf = Vec::new();
+    final_img
+        .write_to(&mut Cursor::new(&mut buf), image::ImageOutputFormat::Png)
+        .map_err(|e| LookupError::Other(format!("failed to encode PNG: {}", e)))?;
+
+    Ok(PlayerSkin {
+        skin: png_data_url(&buf),
+        model: skin.variant,
+        legacy: skin.legacy,
+    })
+}
+
+#[tauri::command]
+pub async fn player_face<R: Runtime>(
+    app: AppHandle<R>,
+    uuid: String,
+    scale: u32,
+) -> Result<String, LookupError> {
+    let skin = load_player_skin(&app, &uuid).await?;
+    let face_png = crop_face(&image::DynamicImage::ImageRgba8(skin.image), scale)
+        .map_err(LookupError::Other)?;
+    Ok(png_data_url(&face_png))
+}
+
+/// An isometric view of the head: top, front and left side, with the hat layer.
+#[tauri::command]
+pub async fn player_head<R: Runtime>(
+    app: AppHandle<R>,
+    uuid: String,
+    scale: u32,
+) -> Result<String, LookupError> {
+    let skin = load_player_skin(&app, &uuid).await?;
+    let png =
+        encode_png(render_isometric_head(&skin.image, scale.max(1))).map_err(LookupError::Other)?;
+    Ok(png_data_url(&png))
+}
+
+/// The whole player seen flat from the front, or from behind when `back` is set.
+#[tauri::command]
+pub async fn player_body<R: Runtime>(
+    app: AppHandle<R>,
+    uuid: String,
+    scale: u32,
+    back: Option<bool>,
+) -> Result<String, LookupError> {
+    let skin = load_player_skin(&app, &uuid).await?;
+    let view = if back.unwrap_or(false) {
+        BodyView::Back
+    } else {
+        BodyView::Front
+    };
+    let png = encode_scaled_png(render_body(&skin.image, skin.is_slim(), view), scale)
+        .map_err(LookupError::Other)?;
+    Ok(png_data_url(&png))
+}
+
+/// Front and back views next to each other.
+#[tauri::command]
+pub async fn player_paperdoll<R: Runtime>(
+    app: AppHandle<R>,
+    uuid: String,
+    scale: u32,
+) -> Result<String, LookupError> {
+    let skin = load_player_skin(&app, &uuid).await?;
+    let png = encode_scaled_png(render_paperdoll(&skin.image, skin.is_slim()), scale)
+        .map_err(LookupError::Other)?;
+    Ok(png_data_url(&png))
+}
+
+/// Uploads `data`, a PNG as a data URL or plain base64, as the active account's skin.
+#[tauri::command]
+pub async fn upload_skin<R: Runtime>(
+    app: AppHandle<R>,
+    data: String,
+    variant: SkinVariant,
+) -> Result<MinecraftProfile, String> {
+    let png = decode_skin_data(&data)?;
+    upload_skin_bytes(&app, png, variant).await
+}
+
+/// Downloads the skin at `url` and uploads it, so a bad link is caught before Mojang sees it.
+#[tauri::command]
+pub async fn set_skin_from_url<R: Runtime>(
+    app: AppHandle<R>,
+    url: String,
+    variant: SkinVariant,
+) -> Result<MinecraftProfile, String> {
+    let png = download_skin(&url).await?;
+    upload_skin_bytes(&app, png, variant).await
+}
+
+/// Puts the active account back on its default skin.
 #[tauri::command]
-pub async fn player_skin(uuid: String, scale: u32) -> Result<String, String> {
-    fetch_skin_inner(&uuid, false, scale).await
+pub async fn reset_skin<R: Runtime>(app: AppHandle<R>) -> Result<MinecraftProfile, String> {
+    let account = minecraft_auth::active_microsoft_account(&app)
+        .await
+        .map_err(skin_error)?;
+
+    let profile = MinecraftServices::from_env()
+        .reset_skin(&account.minecraft.access_token)
+        .await
+        .map_err(skin_error)?;
+
+    forget_cached_textures(&app, &account.uuid);
+    Ok(profile)
+}
+
+/// A cape the active account owns, with a preview of its front at `scale`.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CapeSummary {
+    pub id: String,
+    pub alias: Option<String>,
+    pub url: String,
+    pub active: bool,
+    pub preview: Option<String>,
 }
 
 #[tauri::command]
-pub async fn player_face(uuid: String, scale: u32) -> Result<String, String> {
-    fetch_skin_inner(&uuid, true, scale).await
-}
-
-async fn fetch_skin_inner(uuid: &str, face_only: bool, scale: u32) -> Result<String, String> {
-    let client = Client::new();
-
-    // let uuid = if is_uuid(uuid_or_name) {
-    //     uuid_or_name.to_string()
-    // } else {
-    //     match username_to_uuid(&client, uuid_or_name).await {
-    //         Ok(u) => u,
-    //         Err(e) => return Err(format!("failed to resolve username: {}", e)),
-    //     }
-    // };
-
-    let texture_url = match get_skin_url_from_session(&client, &uuid).await {
-        Ok(Some(url)) => url,
-        Ok(None) => return Err("no skin URL found for player".to_string()),
-        Err(e) => return Err(format!("failed to fetch texture info: {}", e)),
+pub async fn list_capes<R: Runtime>(
+    app: AppHandle<R>,
+    scale: u32,
+) -> Result<Vec<CapeSummary>, String> {
+    let account = minecraft_auth::active_microsoft_account(&app)
+        .await
+        .map_err(skin_error)?;
+
+    let profile = MinecraftServices::from_env()
+        .profile(&account.minecraft.access_token)
+        .await
+        .map_err(skin_error)?
+        .ok_or_else(|| AuthError::NoProfile.to_string())?;
+
+    let previews = futures::future::join_all(
+        profile
+            .capes
+            .iter()
+            .map(|cape| cape_preview(&app, cape, scale)),
+    )
+    .await;
+
+    Ok(profile
+        .capes
+        .into_iter()
+        .zip(previews)
+        .map(|(cape, preview)| CapeSummary {
+            active: cape.is_active(),
+            id: cape.id,
+            alias: cape.alias,
+            url: cape.url,
+            preview,
+        })
+        .collect())
+}
+
+#[tauri::command]
+pub async fn set_active_cape<R: Runtime>(
+    app: AppHandle<R>,
+    cape_id: String,
+) -> Result<MinecraftProfile, String> {
+    let account = minecraft_auth::active_microsoft_account(&app)
+        .await
+        .map_err(skin_error)?;
+
+    let profile = MinecraftServices::from_env()
+        .set_active_cape(&account.minecraft.access_token, &cape_id)
+        .await
+        .map_err(skin_error)?;
+
+    forget_cached_textures(&app, &account.uuid);
+    Ok(profile)
+}
+
+#[tauri::command]
+pub async fn hide_cape<R: Runtime>(app: AppHandle<R>) -> Result<MinecraftProfile, String> {
+    let account = minecraft_auth::active_microsoft_account(&app)
+        .await
+        .map_err(skin_error)?;
+
+    let profile = MinecraftServices::from_env()
+        .hide_cape(&account.minecraft.access_token)
+        .await
+        .map_err(skin_error)?;
+
+    forget_cached_textures(&app, &account.uuid);
+    Ok(profile)
+}
+
+/// A missing preview should not hide the cape, so failures are only logged.
+async fn cape_preview<R: Runtime>(
+    app: &AppHandle<R>,
+    cape: &ProfileCape,
+    scale: u32,
+) -> Option<String> {
+    let result = async {
+        let bytes = texture_cache::texture(app, &cape.url)
+            .await
+            .map_err(|e| format!("{:#}", e))?;
+        let img =
+            image::load_from_memory(&bytes).map_err(|e| format!("failed to load image: {}", e))?;
+        crop_cape_front(&img, scale)
+    }
+    .await;
+
+    match result {
+        Ok(png) => Some(png_data_url(&png)),
+        Err(err) => {
+            log::warn!("failed to render preview of cape {}: {}", cape.id, err);
+            None
+        }
+    }
+}
+
+/// Decodes a PNG sent by the frontend as a data URL or plain base64.
+pub fn decode_skin_data(data: &str) -> Result<Vec<u8>, String> {
+    let base64_str = match data.find(',') {
+        Some(idx) => &data[idx + 1..],
+        None => data,
     };
+    base64::engine::general_purpose::STANDARD
+        .decode(base64_str.trim())
+        .map_err(|e| format!("failed to decode skin: {}", e))
+}
 
-    let bytes = client
-        .get(&texture_url)
+/// Downloads the file at `url`, refusing anything that cannot be a skin by its size.
+pub async fn download_skin(url: &str) -> Result<Vec<u8>, String> {
+    let url = url::Url::parse(url.trim()).map_err(|e| format!("invalid skin URL: {}", e))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err("skin URLs must use http or https".to_string());
+    }
+
+    let mut response = Client::new()
+        .get(url)
         .send()
         .await
-        .context("failed to GET skin url")
-        .map_err(|e| e.to_string())?
-        .bytes()
+        .and_then(|resp| resp.error_for_status())
+        .map_err(|e| format!("failed to download skin: {}", e))?;
+
+    // the header may be missing or wrong, so the body is capped while reading as well
+    let too_large = || "the file at this URL is too large to be a skin".to_string();
+    if response
+        .content_length()
+        .is_some_and(|length| length > MAX_SKIN_SIZE as u64)
+    {
+        return Err(too_large());
+    }
+
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
         .await
-        .map_err(|e| e.to_string())?;
+        .map_err(|e| format!("failed to download skin: {}", e))?
+    {
+        if bytes.len() + chunk.len() > MAX_SKIN_SIZE {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
 
-    // Load and process the image
-    let img =
-        image::load_from_memory(&bytes).map_err(|e| format!("failed to load image: {}", e))?;
+    Ok(bytes)
+}
 
-    if face_only {
-        // Crop and scale face
-        let face_png = crop_face(&img, scale)?;
-        let b64 = base64::engine::general_purpose::STANDARD.encode(&face_png);
-        Ok(format!("data:image/png;base64,{}", b64))
+/// The skin texture of player `uuid` as stored by Mojang, with the model it is drawn for.
+pub async fn player_skin_bytes<R: Runtime>(
+    app: &AppHandle<R>,
+    uuid: &str,
+) -> Result<(Vec<u8>, SkinVariant), LookupError> {
+    let textures = texture_cache::profile_textures(app, uuid)
+        .await
+        .map_err(LookupError::texture)?
+        .ok_or_else(|| LookupError::NotFound {
+            player: uuid.to_string(),
+        })?;
+    let texture_url = textures.skin_url.ok_or_else(|| LookupError::NoSkin {
+        player: uuid.to_string(),
+    })?;
+
+    let bytes = texture_cache::texture(app, &texture_url)
+        .await
+        .map_err(LookupError::texture)?;
+
+    let variant = if textures.slim {
+        SkinVariant::Slim
     } else {
-        // Scale full skin if needed
-        let final_img = if scale > 1 {
-            img.resize(64 * scale, 64 * scale, image::imageops::FilterType::Nearest)
-        } else {
-            img
-        };
+        SkinVariant::Classic
+    };
+    Ok((bytes, variant))
+}
 
-        // Convert to PNG bytes
-        let mut buf = Vec::new();
-        final_img
-            .write_to(&mut Cursor::new(&mut buf), image::ImageOutputFormat::Png)
-            .map_err(|e| format!("failed to encode PNG: {}", e))?;
+/// Small renders of a skin file for lists: the face and the front of the body.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SkinPreview {
+    pub face: String,
+    pub body: String,
+}
 
-        let b64 = base64::engine::general_purpose::STANDARD.encode(&buf);
-        Ok(format!("data:image/png;base64,{}", b64))
+pub fn render_preview(png: &[u8], variant: SkinVariant, scale: u32) -> Result<SkinPreview, String> {
+    let skin = decode_skin(png, variant)?;
+    let face = crop_face(&image::DynamicImage::ImageRgba8(skin.image.clone()), scale)?;
+    let body = encode_scaled_png(
+        render_body(&skin.image, skin.is_slim(), BodyView::Front),
+        scale,
+    )?;
+
+    Ok(SkinPreview {
+        face: png_data_url(&face),
+        body: png_data_url(&body),
+    })
+}
+
+/// Validates `png` and uploads it as the active account's skin.
+pub async fn upload_skin_bytes<R: Runtime>(
+    app: &AppHandle<R>,
+    png: Vec<u8>,
+    variant: SkinVariant,
+) -> Result<MinecraftProfile, String> {
+    validate_skin_png(&png)?;
+
+    let account = minecraft_auth::active_microsoft_account(app)
+        .await
+        .map_err(skin_error)?;
+
+    let profile = MinecraftServices::from_env()
+        .upload_skin(&account.minecraft.access_token, png, variant)
+        .await
+        .map_err(skin_error)?;
+
+    forget_cached_textures(app, &account.uuid);
+    Ok(profile)
+}
+
+/// The session server serves the old skin for a while after a change, but our own renders
+/// should not.
+fn forget_cached_textures<R: Runtime>(app: &AppHandle<R>, uuid: &str) {
+    if let Err(err) = texture_cache::forget_profile(app, uuid) {
+        log::warn!("failed to clear cached textures of {}: {:#}", uuid, err);
     }
 }
 
-async fn get_skin_url_from_session(client: &Client, uuid: &str) -> anyhow::Result<Option<String>> {
-    // session server profile which contains base64 textures property
-    let url = format!(
-        "https://sessionserver.mojang.com/session/minecraft/profile/{}?unsigned=false",
-        uuid
-    );
-    let resp = client.get(url).send().await?;
-    if !resp.status().is_success() {
-        anyhow::bail!("session server returned non-success status")
+/// Checks that `bytes` is a PNG Minecraft accepts as a skin: 64x64, or the legacy 64x32.
+pub fn validate_skin_png(bytes: &[u8]) -> Result<(), String> {
+    if !matches!(image::guess_format(bytes), Ok(ImageFormat::Png)) {
+        return Err("skins must be PNG images".to_string());
     }
 
-    #[derive(Deserialize)]
-    struct ProfileProperty {
-        name: String,
-        value: String,
+    let img = image::load_from_memory_with_format(bytes, ImageFormat::Png)
+        .map_err(|e| format!("failed to load image: {}", e))?;
+
+    match img.dimensions() {
+        (64, 64) | (64, 32) => Ok(()),
+        (width, height) => Err(format!(
+            "skins must be 64x64 or 64x32 pixels, this one is {}x{}",
+            width, height
+        )),
     }
+}
+
+fn skin_error(err: anyhow::Error) -> String {
+    log::error!("skin request failed: {:#}", err);
+    AuthError::from(err).to_string()
+}
+
+/// A skin as loaded for rendering, always in the 64x64 layout.
+struct LoadedSkin {
+    image: RgbaImage,
+    variant: SkinVariant,
+    legacy: bool,
+}
 
-    #[derive(Deserialize)]
-    struct ProfileResp {
-        id: String,
-        name: String,
-        properties: Vec<ProfileProperty>,
+impl LoadedSkin {
+    fn is_slim(&self) -> bool {
+        self.variant == SkinVariant::Slim
     }
+}
+
+/// Loads the skin of `player`, a UUID or a username, through the texture cache. The model
+/// comes from the `model` metadata of the textures property, and legacy skins are converted
+/// to the 64x64 layout.
+async fn load_player_skin<R: Runtime>(
+    app: &AppHandle<R>,
+    player: &str,
+) -> Result<LoadedSkin, LookupError> {
+    let uuid = player_lookup::resolve_player(app, player).await?;
+    let (bytes, variant) = player_skin_bytes(app, &uuid).await?;
+    decode_skin(&bytes, variant).map_err(|message| LookupError::Texture { message })
+}
 
-    let parsed: ProfileResp = resp.json().await?;
+fn decode_skin(png: &[u8], variant: SkinVariant) -> Result<LoadedSkin, String> {
+    let img = image::load_from_memory(png).map_err(|e| format!("failed to load image: {}", e))?;
 
-    // find textures property and decode base64
-    if let Some(prop) = parsed.properties.into_iter().find(|p| p.name == "textures") {
-        let decoded = base64::engine::general_purpose::STANDARD
-            .decode(prop.value)
-            .context("failed to decode textures property")?;
-        let v: serde_json::Value = serde_json::from_slice(&decoded)?;
-        if let Some(skin_url) = v
-            .get("textures")
-            .and_then(|t| t.get("SKIN"))
-            .and_then(|s| s.get("url"))
-            .and_then(|u| u.as_str())
-        {
-            return Ok(Some(skin_url.to_string()));
+    let image = img.to_rgba8();
+    let legacy = is_legacy_skin(&image);
+    // HD skins are scaled down to 64x64 below, which only works out evenly for these sizes
+    let modern = is_skin_width(image.width()) && image.width() == image.height();
+    if !legacy && !modern {
+        return Err(format!(
+            "skins must be 64x64 or 64x32 pixels, or a multiple of that, this one is {}x{}",
+            image.width(),
+            image.height()
+        ));
+    }
+
+    let image = if legacy {
+        convert_legacy_skin(&image)
+    } else {
+        image
+    };
+    // the renderers read the regions of a 64x64 texture
+    let image = if image.width() > 64 {
+        image::imageops::resize(&image, 64, 64, image::imageops::FilterType::Nearest)
+    } else {
+        image
+    };
+
+    Ok(LoadedSkin {
+        image,
+        variant,
+        legacy,
+    })
+}
+
+/// Skins from before 1.8 are half as tall as they are wide (64x32, or a multiple for HD
+/// skins) and have no separate left limbs or second layer for body and limbs.
+fn is_legacy_skin(skin: &RgbaImage) -> bool {
+    is_skin_width(skin.width()) && skin.width() == skin.height() * 2
+}
+
+/// Skins are 64 pixels wide, or a multiple of that for HD skins.
+fn is_skin_width(width: u32) -> bool {
+    width >= 64 && width.is_multiple_of(64)
+}
+
+/// Converts a legacy skin to the 64x64 layout the way the game does: the right leg and arm
+/// are mirrored into the left limb slots, and a hat layer that is fully opaque is cleared,
+/// since old skins often filled it with a solid colour instead of leaving it empty.
+fn convert_legacy_skin(legacy: &RgbaImage) -> RgbaImage {
+    // (source x, source y, offset x, offset y, width, height) in 64x32 units, from vanilla
+    const MIRRORED: [(i32, i32, i32, i32, u32, u32); 12] = [
+        (4, 16, 16, 32, 4, 4),
+        (8, 16, 16, 32, 4, 4),
+        (0, 20, 24, 32, 4, 12),
+        (4, 20, 16, 32, 4, 12),
+        (8, 20, 8, 32, 4, 12),
+        (12, 20, 16, 32, 4, 12),
+        (44, 16, -8, 32, 4, 4),
+        (48, 16, -8, 32, 4, 4),
+        (40, 20, 0, 32, 4, 12),
+        (44, 20, -8, 32, 4, 12),
+        (48, 20, -16, 32, 4, 12),
+        (52, 20, -8, 32, 4, 12),
+    ];
+
+    let unit = legacy.width() / 64;
+    let mut skin = RgbaImage::new(legacy.width(), legacy.width());
+    image::imageops::replace(&mut skin, legacy, 0, 0);
+
+    for (x, y, dx, dy, width, height) in MIRRORED {
+        let (x, y) = (x as u32 * unit, y as u32 * unit);
+        let (width, height) = (width * unit, height * unit);
+        let target_x = (x as i32 + dx * unit as i32) as u32;
+        let target_y = (y as i32 + dy * unit as i32) as u32;
+
+        for yy in 0..height {
+            for xx in 0..width {
+                let pixel = *legacy.get_pixel(x + xx, y + yy);
+                skin.put_pixel(target_x + width - 1 - xx, target_y + yy, pixel);
+            }
         }
     }
 
-    Ok(None)
+    clear_if_opaque(&mut skin, (32 * unit, 0, 32 * unit, 16 * unit));
+    skin
+}
+
+/// Makes the `(x, y, width, height)` region fully transparent when none of its pixels are.
+fn clear_if_opaque(skin: &mut RgbaImage, (x, y, width, height): (u32, u32, u32, u32)) {
+    let opaque =
+        (y..y + height).all(|yy| (x..x + width).all(|xx| skin.get_pixel(xx, yy)[3] >= 128));
+    if !opaque {
+        return;
+    }
+
+    for yy in y..y + height {
+        for xx in x..x + width {
+            skin.get_pixel_mut(xx, yy)[3] = 0;
+        }
+    }
 }
 
 fn crop_face(img: &image::DynamicImage, scale: u32) -> Result<Vec<u8>, String> {
@@ -158,15 +588,309 @@ fn crop_face(img: &image::DynamicImage, scale: u32) -> Result<Vec<u8>, String> {
         }
     }
 
+    encode_scaled_png(final_img, scale)
+}
+
+/// Crops the front of the cape (the 10x16 region at (1, 1) of a 64x32 texture) for previews.
+/// HD capes are handled by scaling the region with the texture width.
+fn crop_cape_front(img: &image::DynamicImage, scale: u32) -> Result<Vec<u8>, String> {
+    let factor = (img.width() / 64).max(1);
+    let (x, y, width, height) = (factor, factor, 10 * factor, 16 * factor);
+
+    if x + width > img.width() || y + height > img.height() {
+        return Err(format!(
+            "cape texture is too small ({}x{})",
+            img.width(),
+            img.height()
+        ));
+    }
+
+    let front = img.crop_imm(x, y, width, height).to_rgba8();
+    // bring HD capes back to the vanilla resolution so `scale` means the same for every cape
+    let front = if factor > 1 {
+        image::imageops::resize(&front, 10, 16, image::imageops::FilterType::Nearest)
+    } else {
+        front
+    };
+
+    encode_scaled_png(front, scale)
+}
+
+/// A face of a box in the player model, named from the player's point of view.
+#[derive(Debug, Clone, Copy)]
+enum Face {
+    Top,
+    Front,
+    Left,
+    Back,
+}
+
+/// A box of the player model in skin texture coordinates. `base` and `overlay` are the top
+/// left corners of the unfolded nets of both layers; `size` is width, height and depth.
+#[derive(Debug, Clone, Copy)]
+struct ModelPart {
+    base: (u32, u32),
+    overlay: (u32, u32),
+    size: (u32, u32, u32),
+}
+
+impl ModelPart {
+    const fn new(base: (u32, u32), overlay: (u32, u32), size: (u32, u32, u32)) -> Self {
+        Self {
+            base,
+            overlay,
+            size,
+        }
+    }
+
+    /// The `(x, y, width, height)` of `face` in the net starting at `origin`.
+    fn face(&self, origin: (u32, u32), face: Face) -> (u32, u32, u32, u32) {
+        let (x, y) = origin;
+        let (w, h, d) = self.size;
+        match face {
+            Face::Top => (x + d, y, w, d),
+            Face::Front => (x + d, y + d, w, h),
+            Face::Left => (x + d + w, y + d, d, h),
+            Face::Back => (x + 2 * d + w, y + d, w, h),
+        }
+    }
+}
+
+const HEAD: ModelPart = ModelPart::new((0, 0), (32, 0), (8, 8, 8));
+const BODY: ModelPart = ModelPart::new((16, 16), (16, 32), (8, 12, 4));
+const RIGHT_LEG: ModelPart = ModelPart::new((0, 16), (0, 32), (4, 12, 4));
+const LEFT_LEG: ModelPart = ModelPart::new((16, 48), (0, 48), (4, 12, 4));
+
+/// The arms are 4 pixels wide, or 3 for the slim model.
+fn arms(slim: bool) -> (ModelPart, ModelPart) {
+    let width = if slim { 3 } else { 4 };
+    (
+        ModelPart::new((40, 16), (40, 32), (width, 12, 4)),
+        ModelPart::new((32, 48), (48, 48), (width, 12, 4)),
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BodyView {
+    Front,
+    Back,
+}
+
+/// Draws the player flat onto a 16x32 canvas. The player's right side is on the left when
+/// seen from the front and on the right when seen from behind.
+fn render_body(skin: &RgbaImage, slim: bool, view: BodyView) -> RgbaImage {
+    let (right_arm, left_arm) = arms(slim);
+    let arm_width = right_arm.size.0;
+
+    let (face, near_arm, far_arm, near_leg, far_leg) = match view {
+        BodyView::Front => (Face::Front, right_arm, left_arm, RIGHT_LEG, LEFT_LEG),
+        BodyView::Back => (Face::Back, left_arm, right_arm, LEFT_LEG, RIGHT_LEG),
+    };
+
+    let placements = [
+        (HEAD, (4, 0)),
+        (BODY, (4, 8)),
+        (near_arm, (4 - arm_width, 8)),
+        (far_arm, (12, 8)),
+        (near_leg, (4, 20)),
+        (far_leg, (8, 20)),
+    ];
+
+    let mut canvas = RgbaImage::new(16, 32);
+    // every base layer first, so no overlay ends up underneath a neighbouring part
+    for origin in [Layer::Base, Layer::Overlay] {
+        for (part, (x, y)) in placements {
+            let region = part.face(origin.of(&part), face);
+            paste_region(&mut canvas, skin, region, x, y);
+        }
+    }
+    canvas
+}
+
+/// The front and back views side by side with a gap between them.
+fn render_paperdoll(skin: &RgbaImage, slim: bool) -> RgbaImage {
+    let mut canvas = RgbaImage::new(36, 32);
+    image::imageops::overlay(&mut canvas, &render_body(skin, slim, BodyView::Front), 0, 0);
+    image::imageops::overlay(&mut canvas, &render_body(skin, slim, BodyView::Back), 20, 0);
+    canvas
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Layer {
+    Base,
+    Overlay,
+}
+
+impl Layer {
+    fn of(self, part: &ModelPart) -> (u32, u32) {
+        match self {
+            Layer::Base => part.base,
+            Layer::Overlay => part.overlay,
+        }
+    }
+}
+
+/// Blends the `(x, y, width, height)` region of `skin` onto `canvas` at `(dx, dy)`. Parts of
+/// the region outside the skin are left empty.
+fn paste_region(
+    canvas: &mut RgbaImage,
+    skin: &RgbaImage,
+    (x, y, width, height): (u32, u32, u32, u32),
+    dx: u32,
+    dy: u32,
+) {
+    for yy in 0..height {
+        for xx in 0..width {
+            let (sx, sy) = (x + xx, y + yy);
+            if sx >= skin.width() || sy >= skin.height() {
+                continue;
+            }
+            let (tx, ty) = (dx + xx, dy + yy);
+            if tx < canvas.width() && ty < canvas.height() {
+                canvas.get_pixel_mut(tx, ty).blend(skin.get_pixel(sx, sy));
+            }
+        }
+    }
+}
+
+/// Renders the head as an isometric cube showing its top, front and left side, with the hat
+/// layer drawn slightly larger around it like in game. One texel is `scale` pixels wide.
+fn render_isometric_head(skin: &RgbaImage, scale: u32) -> RgbaImage {
+    const SIZE: f32 = 8.0;
+    const HAT_EXPANSION: f32 = 9.0 / 8.0;
+
+    let cos = 30f32.to_radians().cos();
+    let scale = scale as f32;
+    let width = (2.0 * SIZE * cos * HAT_EXPANSION * scale).ceil() as u32;
+    let height = (2.0 * SIZE * HAT_EXPANSION * scale).ceil() as u32;
+    let center = (width as f32 / 2.0, height as f32 / 2.0);
+
+    let mut canvas = RgbaImage::new(width, height);
+
+    for (layer, expansion) in [(Layer::Base, 1.0), (Layer::Overlay, HAT_EXPANSION)] {
+        let unit = scale * expansion;
+        // unit cube coordinates have the cube centred on (SIZE * cos, SIZE)
+        let project = |x: f32, y: f32| {
+            (
+                center.0 + (x - SIZE * cos) * unit,
+                center.1 + (y - SIZE) * unit,
+            )
+        };
+        let origin = layer.of(&HEAD);
+
+        // (face, top left corner, step along a texture row, step down a column, shade)
+        let faces = [
+            (Face::Top, (SIZE * cos, 0.0), (cos, 0.5), (-cos, 0.5), 1.0),
+            (Face::Front, (0.0, SIZE / 2.0), (cos, 0.5), (0.0, 1.0), 0.85),
+            (Face::Left, (SIZE * cos, SIZE), (cos, -0.5), (0.0, 1.0), 0.7),
+        ];
+
+        for (face, corner, row, column, shade) in faces {
+            draw_projected_face(
+                &mut canvas,
+                skin,
+                HEAD.face(origin, face),
+                project(corner.0, corner.1),
+                (row.0 * unit, row.1 * unit),
+                (column.0 * unit, column.1 * unit),
+                shade,
+            );
+        }
+    }
+
+    canvas
+}
+
+/// Draws a texture region onto the parallelogram spanned by `row` and `column` (the on-screen
+/// size of one texel step in each direction) from `corner`, sampling the nearest texel for
+/// every pixel inside and darkening it by `shade`.
+fn draw_projected_face(
+    canvas: &mut RgbaImage,
+    skin: &RgbaImage,
+    (x, y, width, height): (u32, u32, u32, u32),
+    corner: (f32, f32),
+    row: (f32, f32),
+    column: (f32, f32),
+    shade: f32,
+) {
+    let determinant = row.0 * column.1 - row.1 * column.0;
+    if determinant.abs() < f32::EPSILON {
+        return;
+    }
+
+    let (w, h) = (width as f32, height as f32);
+    let corners = [
+        corner,
+        (corner.0 + row.0 * w, corner.1 + row.1 * w),
+        (corner.0 + column.0 * h, corner.1 + column.1 * h),
+        (
+            corner.0 + row.0 * w + column.0 * h,
+            corner.1 + row.1 * w + column.1 * h,
+        ),
+    ];
+    let min_x = corners
+        .iter()
+        .map(|p| p.0)
+        .fold(f32::MAX, f32::min)
+        .floor()
+        .max(0.0) as u32;
+    let max_x = corners.iter().map(|p| p.0).fold(f32::MIN, f32::max).ceil() as u32;
+    let min_y = corners
+        .iter()
+        .map(|p| p.1)
+        .fold(f32::MAX, f32::min)
+        .floor()
+        .max(0.0) as u32;
+    let max_y = corners.iter().map(|p| p.1).fold(f32::MIN, f32::max).ceil() as u32;
+
+    // a little slack along the edges keeps neighbouring faces from showing a seam
+    const SLACK: f32 = 0.01;
+
+    for py in min_y..max_y.min(canvas.height()) {
+        for px in min_x..max_x.min(canvas.width()) {
+            let dx = px as f32 + 0.5 - corner.0;
+            let dy = py as f32 + 0.5 - corner.1;
+            let u = (dx * column.1 - dy * column.0) / determinant;
+            let v = (row.0 * dy - row.1 * dx) / determinant;
+            if u < -SLACK || v < -SLACK || u >= w + SLACK || v >= h + SLACK {
+                continue;
+            }
+
+            let sx = x + (u.max(0.0) as u32).min(width - 1);
+            let sy = y + (v.max(0.0) as u32).min(height - 1);
+            if sx >= skin.width() || sy >= skin.height() {
+                continue;
+            }
+
+            let mut texel = *skin.get_pixel(sx, sy);
+            if texel[3] == 0 {
+                continue;
+            }
+            for channel in 0..3 {
+                texel[channel] = (texel[channel] as f32 * shade).round() as u8;
+            }
+            canvas.get_pixel_mut(px, py).blend(&texel);
+        }
+    }
+}
+
+fn encode_png(img: RgbaImage) -> Result<Vec<u8>, String> {
+    encode_scaled_png(img, 1)
+}
+
+/// Scales `img` up by an integer factor without smoothing and encodes it as PNG.
+fn encode_scaled_png(img: RgbaImage, scale: u32) -> Result<Vec<u8>, String> {
+    let (width, height) = img.dimensions();
+
     // Scale if needed
     let final_img = if scale > 1 {
-        image::DynamicImage::ImageRgba8(final_img).resize(
-            8 * scale,
-            8 * scale,
+        image::DynamicImage::ImageRgba8(img).resize(
+            width * scale,
+            height * scale,
             image::imageops::FilterType::Nearest,
         )
     } else {
-        image::DynamicImage::ImageRgba8(final_img)
+        image::DynamicImage::ImageRgba8(img)
     };
 
     // encode as PNG
@@ -177,3 +901,298 @@ fn crop_face(img: &image::DynamicImage, scale: u32) -> Result<Vec<u8>, String> {
 
     Ok(buf)
 }
+
+fn png_data_url(png: &[u8]) -> String {
+    let b64 = base64::engine::general_purpose::STANDARD.encode(png);
+    format!("data:image/png;base64,{}", b64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_server::{block_on, Response, TestServer};
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut buf = Vec::new();
+        RgbaImage::new(width, height)
+            .write_to(&mut Cursor::new(&mut buf), image::ImageOutputFormat::Png)
+            .unwrap();
+        buf
+    }
+
+    /// A cape texture of `factor` times the vanilla 64x32 whose front is red and everything
+    /// else blue.
+    fn cape(factor: u32) -> image::DynamicImage {
+        let front = |x: u32, y: u32| (1..11).contains(&x) && (1..17).contains(&y);
+        image::DynamicImage::ImageRgba8(RgbaImage::from_fn(64 * factor, 32 * factor, |x, y| {
+            if front(x / factor, y / factor) {
+                image::Rgba([255, 0, 0, 255])
+            } else {
+                image::Rgba([0, 0, 255, 255])
+            }
+        }))
+    }
+
+    #[test]
+    fn cape_front_is_cropped_at_the_vanilla_size() {
+        for factor in [1, 2, 4] {
+            for scale in [1, 3] {
+                let png = crop_cape_front(&cape(factor), scale).unwrap();
+                let front = image::load_from_memory(&png).unwrap().to_rgba8();
+
+                assert_eq!(front.dimensions(), (10 * scale, 16 * scale));
+                assert!(
+                    front.pixels().all(|pixel| pixel.0 == [255, 0, 0, 255]),
+                    "factor {} scale {}",
+                    factor,
+                    scale
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn capes_too_small_for_a_front_are_rejected() {
+        let tiny = image::DynamicImage::ImageRgba8(RgbaImage::new(8, 8));
+
+        let err = crop_cape_front(&tiny, 1).unwrap_err();
+
+        assert!(err.contains("too small (8x8)"), "{}", err);
+    }
+
+    #[test]
+    fn modern_and_legacy_skins_are_valid() {
+        assert!(validate_skin_png(&png(64, 64)).is_ok());
+        assert!(validate_skin_png(&png(64, 32)).is_ok());
+    }
+
+    #[test]
+    fn other_sizes_are_rejected() {
+        for (width, height) in [(32, 32), (128, 128), (64, 48)] {
+            let err = validate_skin_png(&png(width, height)).unwrap_err();
+            assert!(err.contains(&format!("{}x{}", width, height)), "{}", err);
+        }
+    }
+
+    #[test]
+    fn non_png_files_are_rejected() {
+        let mut bmp = Vec::new();
+        RgbaImage::new(64, 64)
+            .write_to(&mut Cursor::new(&mut bmp), image::ImageOutputFormat::Bmp)
+            .unwrap();
+
+        assert_eq!(
+            validate_skin_png(&bmp).unwrap_err(),
+            "skins must be PNG images"
+        );
+        assert!(validate_skin_png(b"not an image").is_err());
+
+        // a PNG signature alone does not make a skin
+        let mut truncated = png(64, 64);
+        truncated.truncate(40);
+        assert!(validate_skin_png(&truncated).is_err());
+    }
+
+    #[test]
+    fn downloaded_skins_are_returned() {
+        let skin = png(64, 64);
+        let body = skin.clone();
+        let server = TestServer::start(move |_| Response::ok(body.clone()));
+
+        let bytes = block_on(download_skin(&format!("{}/skin.png", server.url()))).unwrap();
+        assert_eq!(bytes, skin);
+    }
+
+    #[test]
+    fn oversized_downloads_are_rejected() {
+        let server = TestServer::start(|_| Response::ok(vec![0; MAX_SKIN_SIZE + 1]));
+
+        let err = block_on(download_skin(&format!("{}/skin.png", server.url()))).unwrap_err();
+        assert_eq!(err, "the file at this URL is too large to be a skin");
+    }
+
+    #[test]
+    fn only_http_urls_are_downloaded() {
+        let err = block_on(download_skin("file:///etc/passwd")).unwrap_err();
+        assert_eq!(err, "skin URLs must use http or https");
+    }
+
+    fn fixture(name: &str) -> std::path::PathBuf {
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/skins")
+            .join(name)
+    }
+
+    /// Compares `png` with the golden file `name`. Run with `UPDATE_GOLDEN=1` to write the
+    /// current renders instead, after checking them by eye.
+    fn assert_golden(name: &str, png: &[u8]) {
+        let path = fixture("golden").join(name);
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, png).unwrap();
+            return;
+        }
+
+        let golden = std::fs::read(&path).unwrap_or_else(|err| panic!("{:?}: {}", path, err));
+        assert!(golden == png, "render differs from {:?}", path);
+    }
+
+    #[test]
+    fn renders_match_the_golden_images() {
+        for (name, variant) in [
+            ("classic", SkinVariant::Classic),
+            ("slim", SkinVariant::Slim),
+        ] {
+            let png = std::fs::read(fixture(&format!("{}.png", name))).unwrap();
+            assert_renders_match(name, &decode_skin(&png, variant).unwrap());
+        }
+    }
+
+    #[test]
+    fn hd_skins_render_like_their_64_pixel_original() {
+        let original = image::load_from_memory(&std::fs::read(fixture("classic.png")).unwrap())
+            .unwrap()
+            .to_rgba8();
+        let hd = image::imageops::resize(&original, 128, 128, image::imageops::FilterType::Nearest);
+
+        let skin = decode_skin(&encode_png(hd).unwrap(), SkinVariant::Classic).unwrap();
+
+        assert_eq!(skin.image.dimensions(), (64, 64));
+        assert_renders_match("classic", &skin);
+    }
+
+    fn assert_renders_match(name: &str, skin: &LoadedSkin) {
+        assert!(!skin.legacy);
+
+        for scale in [1, 3] {
+            let golden = |render: &str| format!("{}-{}-x{}.png", name, render, scale);
+
+            let head = encode_png(render_isometric_head(&skin.image, scale)).unwrap();
+            assert_golden(&golden("head"), &head);
+
+            for (view, render) in [(BodyView::Front, "front"), (BodyView::Back, "back")] {
+                let body = render_body(&skin.image, skin.is_slim(), view);
+                assert_golden(&golden(render), &encode_scaled_png(body, scale).unwrap());
+            }
+
+            let paperdoll = render_paperdoll(&skin.image, skin.is_slim());
+            assert_golden(
+                &golden("paperdoll"),
+                &encode_scaled_png(paperdoll, scale).unwrap(),
+            );
+        }
+    }
+
+    #[test]
+    fn slim_arms_leave_the_outer_columns_empty() {
+        let png = std::fs::read(fixture("classic.png")).unwrap();
+        let skin = decode_skin(&png, SkinVariant::Classic).unwrap();
+        let outer_columns_opaque = |body: &RgbaImage| {
+            (8..20).all(|y| body.get_pixel(0, y)[3] == 255 && body.get_pixel(15, y)[3] == 255)
+        };
+        let outer_columns_empty = |body: &RgbaImage| {
+            (0..32).all(|y| body.get_pixel(0, y)[3] == 0 && body.get_pixel(15, y)[3] == 0)
+        };
+
+        for view in [BodyView::Front, BodyView::Back] {
+            assert!(outer_columns_opaque(&render_body(&skin.image, false, view)));
+            assert!(outer_columns_empty(&render_body(&skin.image, true, view)));
+        }
+    }
+
+    /// A legacy skin of `unit` times the normal size in which every pixel differs.
+    fn legacy_pattern(unit: u32) -> RgbaImage {
+        RgbaImage::from_fn(64 * unit, 32 * unit, |x, y| {
+            image::Rgba([
+                (x % 256) as u8,
+                (y % 256) as u8,
+                (x / 256 + y / 256) as u8,
+                255,
+            ])
+        })
+    }
+
+    #[test]
+    fn legacy_limbs_are_mirrored_like_vanilla() {
+        // (target x, y, source x, y, width, height) in 64x64 units, following the copyRect
+        // calls of vanilla's processLegacySkin, which all mirror horizontally
+        const SLOTS: [(u32, u32, u32, u32, u32, u32); 12] = [
+            (20, 48, 4, 16, 4, 4),
+            (24, 48, 8, 16, 4, 4),
+            (24, 52, 0, 20, 4, 12),
+            (20, 52, 4, 20, 4, 12),
+            (16, 52, 8, 20, 4, 12),
+            (28, 52, 12, 20, 4, 12),
+            (36, 48, 44, 16, 4, 4),
+            (40, 48, 48, 16, 4, 4),
+            (40, 52, 40, 20, 4, 12),
+            (36, 52, 44, 20, 4, 12),
+            (32, 52, 48, 20, 4, 12),
+            (44, 52, 52, 20, 4, 12),
+        ];
+
+        for unit in [1, 2] {
+            let legacy = legacy_pattern(unit);
+            let skin = convert_legacy_skin(&legacy);
+            assert_eq!(skin.dimensions(), (64 * unit, 64 * unit));
+
+            for (tx, ty, sx, sy, width, height) in SLOTS {
+                let (width, height) = (width * unit, height * unit);
+                for y in 0..height {
+                    for x in 0..width {
+                        assert_eq!(
+                            skin.get_pixel(tx * unit + x, ty * unit + y),
+                            legacy.get_pixel(sx * unit + width - 1 - x, sy * unit + y),
+                            "slot ({}, {}) at unit {}",
+                            tx,
+                            ty,
+                            unit
+                        );
+                    }
+                }
+            }
+
+            // the top half is kept as it was
+            for (x, y, pixel) in legacy.enumerate_pixels() {
+                if !(32 * unit..64 * unit).contains(&x) || y >= 16 * unit {
+                    assert_eq!(skin.get_pixel(x, y), pixel);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn opaque_legacy_hats_are_cleared() {
+        let skin = convert_legacy_skin(&legacy_pattern(1));
+        assert!((32..64).all(|x| (0..16).all(|y| skin.get_pixel(x, y)[3] == 0)));
+
+        let mut legacy = legacy_pattern(1);
+        legacy.get_pixel_mut(40, 8)[3] = 0;
+        let skin = convert_legacy_skin(&legacy);
+        assert_eq!(skin.get_pixel(41, 8), legacy.get_pixel(41, 8));
+    }
+
+    #[test]
+    fn legacy_skins_are_detected_by_size() {
+        assert!(is_legacy_skin(&RgbaImage::new(64, 32)));
+        assert!(is_legacy_skin(&RgbaImage::new(128, 64)));
+        assert!(!is_legacy_skin(&RgbaImage::new(64, 64)));
+        assert!(!is_legacy_skin(&RgbaImage::new(32, 16)));
+        assert!(!is_legacy_skin(&RgbaImage::new(96, 48)));
+    }
+
+    #[test]
+    fn unsupported_sizes_fail_to_decode() {
+        for (width, height) in [(32, 16), (96, 48), (32, 32), (64, 48)] {
+            let err = match decode_skin(&png(width, height), SkinVariant::Classic) {
+                Ok(_) => panic!("{}x{} decoded", width, height),
+                Err(err) => err,
+            };
+            assert!(err.contains(&format!("{}x{}", width, height)), "{}", err);
+        }
+
+        let legacy = decode_skin(&png(64, 32), SkinVariant::Classic).unwrap();
+        assert!(legacy.legacy);
+        assert_eq!(legacy.image.dimensions(), (64, 64));
+    }
+}
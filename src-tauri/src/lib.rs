@@ -11,6 +11,7 @@ pub fn run() {
 
     builder = builder
         .plugin(tauri_plugin_log::Builder::new().build())
+        .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_single_instance::init(|app, _args, _cwd| {
             if let Some(window) = app.get_webview_window("launcher") {
                 let _ = window.show();
@@ -25,13 +26,32 @@ pub fn run() {
         );
 
     builder = builder
+        .setup(|app| {
+            app.manage(utils::texture_cache::TextureCache::default());
+            app.manage(utils::player_lookup::PlayerLookup::default());
+            Ok(())
+        })
         .plugin(plugins::account::init())
         .plugin(plugins::instance::init())
+        .plugin(plugins::java::init())
+        .plugin(plugins::settings::init())
+        .plugin(plugins::version::init())
+        .plugin(plugins::wardrobe::init())
         .invoke_handler(generate_handler![
             utils::restart_app,
             utils::show_launcher_window,
+            commands::skin::lookup_players,
             commands::skin::player_skin,
             commands::skin::player_face,
+            commands::skin::player_head,
+            commands::skin::player_body,
+            commands::skin::player_paperdoll,
+            commands::skin::upload_skin,
+            commands::skin::set_skin_from_url,
+            commands::skin::reset_skin,
+            commands::skin::list_capes,
+            commands::skin::set_active_cape,
+            commands::skin::hide_cape,
         ]);
 
     let app = builder.build(generate_context!());
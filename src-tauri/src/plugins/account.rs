@@ -1,36 +1,61 @@
 use std::sync::{Arc, Mutex};
 
-use crate::utils::minecraft_auth::{self, AccountRecord};
+use crate::utils::auth_error::AuthError;
+use crate::utils::loopback::LoopbackListener;
+use crate::utils::minecraft_auth::{
+    self, AccountRecord, AccountType, RefreshLocks, ValidatedTokens,
+};
+use crate::utils::minecraft_services::{NameAvailability, NameChangeInfo};
+use crate::utils::secret_store;
+use crate::utils::settings;
+use crate::utils::yggdrasil;
 use anyhow::{anyhow, Context};
-use serde::Serialize;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
 use tauri::{
     generate_handler,
     plugin::{self, TauriPlugin},
-    AppHandle, Runtime, WebviewUrl, WebviewWindowBuilder,
+    AppHandle, Emitter, Manager, Runtime, WebviewUrl, WebviewWindowBuilder,
 };
 
+use tauri_plugin_opener::OpenerExt;
 use tokio::sync::oneshot;
 use url::form_urlencoded;
 
 pub fn init<R: Runtime>() -> TauriPlugin<R> {
     plugin::Builder::<R>::new("account")
+        .setup(|app, _api| {
+            app.manage(secret_store::open(app)?);
+            app.manage(RefreshLocks::default());
+            app.manage(ValidatedTokens::default());
+            Ok(())
+        })
         .invoke_handler(generate_handler![
             authenticate,
+            authenticate_device_code,
+            authenticate_browser,
+            authenticate_yggdrasil,
+            create_offline_account,
+            create_profile,
+            check_profile_name,
+            get_name_change_info,
+            change_profile_name,
             get_all,
             get_active,
             set_active,
+            refresh,
             remove
         ])
         .build()
 }
 
 #[tauri::command]
-async fn authenticate<R: Runtime>(app: AppHandle<R>) -> Result<AccountRecord, String> {
+async fn authenticate<R: Runtime>(app: AppHandle<R>) -> Result<AccountSummary, AuthError> {
     match authenticate_impl(&app).await {
-        Ok(account) => Ok(account),
+        Ok(account) => Ok(AccountSummary::from(account)),
         Err(err) => {
             log::error!("minecraft authentication failed: {:#}", err);
-            Err(format!("{:#}", err))
+            Err(AuthError::from(err))
         }
     }
 }
@@ -126,7 +151,7 @@ async fn authenticate_impl<R: Runtime>(app: &AppHandle<R>) -> anyhow::Result<Acc
         }
         Err(_) => {
             let _ = auth_window.close();
-            return Err(anyhow!("authentication window closed before completion"));
+            return Err(AuthError::Cancelled.into());
         }
     };
 
@@ -143,10 +168,220 @@ async fn authenticate_impl<R: Runtime>(app: &AppHandle<R>) -> anyhow::Result<Acc
     Ok(account)
 }
 
+/// Signs in without the embedded webview: emits `account-device-code` with the code the user
+/// enters at the verification page, then waits until they have done so.
+#[tauri::command]
+async fn authenticate_device_code<R: Runtime>(
+    app: AppHandle<R>,
+) -> Result<AccountSummary, AuthError> {
+    match authenticate_device_code_impl(&app).await {
+        Ok(account) => Ok(AccountSummary::from(account)),
+        Err(err) => {
+            log::error!("device code authentication failed: {:#}", err);
+            Err(AuthError::from(err))
+        }
+    }
+}
+
+async fn authenticate_device_code_impl<R: Runtime>(
+    app: &AppHandle<R>,
+) -> anyhow::Result<AccountRecord> {
+    let flow = minecraft_auth::init_device_code().await?;
+
+    app.emit("account-device-code", flow.prompt())
+        .context("failed to emit device code")?;
+
+    let account = flow
+        .exchange()
+        .await
+        .context("failed to complete Microsoft authentication flow")?;
+
+    minecraft_auth::save_account(app, &account)
+        .context("failed to persist authenticated account")?;
+
+    Ok(account)
+}
+
+/// Signs in through the system browser, receiving the redirect on a temporary listener on
+/// `127.0.0.1`.
+#[tauri::command]
+async fn authenticate_browser<R: Runtime>(app: AppHandle<R>) -> Result<AccountSummary, AuthError> {
+    match authenticate_browser_impl(&app).await {
+        Ok(account) => Ok(AccountSummary::from(account)),
+        Err(err) => {
+            log::error!("browser authentication failed: {:#}", err);
+            Err(AuthError::from(err))
+        }
+    }
+}
+
+async fn authenticate_browser_impl<R: Runtime>(
+    app: &AppHandle<R>,
+) -> anyhow::Result<AccountRecord> {
+    let listener = LoopbackListener::bind().await?;
+    let flow = minecraft_auth::init_with_redirect(&listener.redirect_url())
+        .context("failed to initialise Minecraft auth flow")?;
+
+    app.opener()
+        .open_url(flow.authorize_url().as_str(), None::<&str>)
+        .map_err(|err| anyhow!(err))
+        .context("failed to open the system browser")?;
+
+    let auth_code = listener.wait_for_code(flow.csrf_secret()).await?;
+
+    let account = flow
+        .exchange(&auth_code)
+        .await
+        .context("failed to complete Microsoft authentication flow")?;
+
+    minecraft_auth::save_account(app, &account)
+        .context("failed to persist authenticated account")?;
+
+    Ok(account)
+}
+
+/// Signs in with a username and password at a Yggdrasil-compatible server (e.g. a skin
+/// site), falling back to the server configured in the settings.
+#[tauri::command]
+async fn authenticate_yggdrasil<R: Runtime>(
+    app: AppHandle<R>,
+    request: YggdrasilLogin,
+) -> Result<AccountSummary, AuthError> {
+    match authenticate_yggdrasil_impl(&app, request).await {
+        Ok(account) => Ok(AccountSummary::from(account)),
+        Err(err) => {
+            log::error!("yggdrasil authentication failed: {:#}", err);
+            Err(AuthError::from(err))
+        }
+    }
+}
+
+async fn authenticate_yggdrasil_impl<R: Runtime>(
+    app: &AppHandle<R>,
+    request: YggdrasilLogin,
+) -> anyhow::Result<AccountRecord> {
+    let server_url = match request.server_url.filter(|url| !url.trim().is_empty()) {
+        Some(url) => url,
+        None => settings::read_settings(app)?
+            .accounts
+            .yggdrasil_server
+            .ok_or_else(|| anyhow!("no authentication server was given"))?,
+    };
+
+    let client = Client::new();
+    let api_root = yggdrasil::resolve_api_root(&client, &server_url).await?;
+
+    let account = yggdrasil::authenticate(
+        &client,
+        &api_root,
+        &request.username,
+        &request.password,
+        request.profile.as_deref(),
+    )
+    .await
+    .context("failed to complete Yggdrasil authentication")?;
+
+    minecraft_auth::save_account(app, &account)
+        .context("failed to persist authenticated account")?;
+
+    Ok(account)
+}
+
+#[tauri::command]
+async fn create_offline_account<R: Runtime>(
+    app: AppHandle<R>,
+    username: String,
+) -> Result<AccountSummary, String> {
+    if !offline_accounts_enabled(&app) {
+        return Err("offline accounts are disabled in the settings".to_string());
+    }
+
+    let account = minecraft_auth::offline_account(&username).map_err(|err| err.to_string())?;
+
+    minecraft_auth::save_account(&app, &account).map_err(|err| {
+        log::error!("failed to save offline account: {:#}", err);
+        err.to_string()
+    })?;
+
+    Ok(AccountSummary::from(account))
+}
+
+/// Picks the profile name of a signed-in account that owns the game but has no profile yet.
+#[tauri::command]
+async fn create_profile<R: Runtime>(
+    app: AppHandle<R>,
+    uuid: String,
+    name: String,
+) -> Result<AccountSummary, AuthError> {
+    minecraft_auth::create_profile(&app, &uuid, &name)
+        .await
+        .map(AccountSummary::from)
+        .map_err(|err| {
+            log::error!("failed to create profile for account {}: {:#}", uuid, err);
+            AuthError::from(err)
+        })
+}
+
+#[tauri::command]
+async fn check_profile_name<R: Runtime>(
+    app: AppHandle<R>,
+    uuid: String,
+    name: String,
+) -> Result<NameAvailability, AuthError> {
+    minecraft_auth::profile_name_availability(&app, &uuid, &name)
+        .await
+        .map_err(|err| {
+            log::error!(
+                "failed to check name {} for account {}: {:#}",
+                name,
+                uuid,
+                err
+            );
+            AuthError::from(err)
+        })
+}
+
+/// When the profile of account `uuid` was last renamed and when it may be renamed again.
+#[tauri::command]
+async fn get_name_change_info<R: Runtime>(
+    app: AppHandle<R>,
+    uuid: String,
+) -> Result<NameChangeInfo, AuthError> {
+    minecraft_auth::name_change_info(&app, &uuid)
+        .await
+        .map_err(|err| {
+            log::error!("failed to fetch name change info for {}: {:#}", uuid, err);
+            AuthError::from(err)
+        })
+}
+
+#[tauri::command]
+async fn change_profile_name<R: Runtime>(
+    app: AppHandle<R>,
+    uuid: String,
+    name: String,
+) -> Result<AccountSummary, AuthError> {
+    minecraft_auth::change_profile_name(&app, &uuid, &name)
+        .await
+        .map(AccountSummary::from)
+        .map_err(|err| {
+            log::error!("failed to rename profile of account {}: {:#}", uuid, err);
+            AuthError::from(err)
+        })
+}
+
 #[tauri::command]
 async fn get_all<R: Runtime>(app: AppHandle<R>) -> Result<Vec<AccountSummary>, String> {
+    let show_offline = offline_accounts_enabled(&app);
+
     minecraft_auth::read_accounts(&app)
-        .map(|accounts| accounts.into_iter().map(AccountSummary::from).collect())
+        .map(|accounts| {
+            accounts
+                .into_iter()
+                .filter(|account| show_offline || account.account_type != AccountType::Offline)
+                .map(AccountSummary::from)
+                .collect()
+        })
         .map_err(|err| {
             log::error!("failed to read accounts: {:#}", err);
             err.to_string()
@@ -160,10 +395,35 @@ async fn get_active<R: Runtime>(app: AppHandle<R>) -> Result<Option<AccountSumma
         err.to_string()
     })?;
 
-    Ok(accounts
-        .into_iter()
-        .find(|account| account.is_active)
-        .map(AccountSummary::from))
+    let Some(account) = accounts.into_iter().find(|account| account.is_active) else {
+        return Ok(None);
+    };
+
+    if account.account_type == AccountType::Offline && !offline_accounts_enabled(&app) {
+        return Ok(None);
+    }
+
+    // a failed refresh should not hide the account; launching retries it anyway
+    let account = match minecraft_auth::ensure_fresh(&app, account.clone()).await {
+        Ok(refreshed) => refreshed,
+        Err(err) => {
+            log::warn!("failed to refresh account {}: {:#}", account.uuid, err);
+            account
+        }
+    };
+
+    Ok(Some(AccountSummary::from(account)))
+}
+
+#[tauri::command]
+async fn refresh<R: Runtime>(app: AppHandle<R>, uuid: String) -> Result<AccountSummary, AuthError> {
+    minecraft_auth::refresh_account(&app, &uuid)
+        .await
+        .map(AccountSummary::from)
+        .map_err(|err| {
+            log::error!("failed to refresh account {}: {:#}", uuid, err);
+            AuthError::from(err)
+        })
 }
 
 #[tauri::command]
@@ -173,20 +433,7 @@ async fn set_active<R: Runtime>(app: AppHandle<R>, uuid: String) -> Result<(), S
         err.to_string()
     })?;
 
-    let mut found = false;
-
-    for account in accounts.iter_mut() {
-        if account.uuid == uuid {
-            account.is_active = true;
-            found = true;
-        } else if account.is_active {
-            account.is_active = false;
-        }
-    }
-
-    if !found {
-        return Err(format!("account {} not found", uuid));
-    }
+    activate_account(&mut accounts, &uuid, offline_accounts_enabled(&app))?;
 
     minecraft_auth::write_accounts(&app, &accounts).map_err(|err| {
         log::error!("failed to update accounts: {:#}", err);
@@ -201,11 +448,20 @@ async fn remove<R: Runtime>(app: AppHandle<R>, uuid: String) -> Result<(), Strin
         err.to_string()
     })?;
 
-    let original_len = accounts.len();
-    accounts.retain(|account| account.uuid != uuid);
-
-    if accounts.len() == original_len {
+    let Some(index) = accounts.iter().position(|account| account.uuid == uuid) else {
         return Err(format!("account {} not found", uuid));
+    };
+    let removed = accounts.remove(index);
+
+    // best effort, the account is removed even when the server cannot be reached
+    if removed.account_type == AccountType::Yggdrasil {
+        if let Err(err) = yggdrasil::invalidate(&Client::new(), &removed).await {
+            log::warn!(
+                "failed to invalidate tokens of {}: {:#}",
+                removed.username,
+                err
+            );
+        }
     }
 
     minecraft_auth::write_accounts(&app, &accounts).map_err(|err| {
@@ -214,6 +470,48 @@ async fn remove<R: Runtime>(app: AppHandle<R>, uuid: String) -> Result<(), Strin
     })
 }
 
+/// Makes `uuid` the only active account. Offline accounts cannot be picked while the settings
+/// disable them, since the launch would be refused anyway.
+fn activate_account(
+    accounts: &mut [AccountRecord],
+    uuid: &str,
+    offline_allowed: bool,
+) -> Result<(), String> {
+    let account = accounts
+        .iter()
+        .find(|account| account.uuid == uuid)
+        .ok_or_else(|| format!("account {} not found", uuid))?;
+    if account.account_type == AccountType::Offline && !offline_allowed {
+        return Err("offline accounts are disabled in the settings".to_string());
+    }
+
+    for account in accounts.iter_mut() {
+        account.is_active = account.uuid == uuid;
+    }
+    Ok(())
+}
+
+fn offline_accounts_enabled<R: Runtime>(app: &AppHandle<R>) -> bool {
+    match settings::read_settings(app) {
+        Ok(settings) => !settings.accounts.disable_offline_accounts,
+        Err(err) => {
+            log::warn!("failed to read settings: {:#}", err);
+            true
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct YggdrasilLogin {
+    #[serde(default)]
+    server_url: Option<String>,
+    username: String,
+    password: String,
+    #[serde(default)]
+    profile: Option<String>,
+}
+
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct AccountSummary {
@@ -221,6 +519,8 @@ struct AccountSummary {
     username: String,
     obtained_at: u64,
     is_active: bool,
+    account_type: AccountType,
+    needs_profile: bool,
 }
 
 impl From<AccountRecord> for AccountSummary {
@@ -230,6 +530,8 @@ impl From<AccountRecord> for AccountSummary {
             username,
             obtained_at,
             is_active,
+            account_type,
+            needs_profile,
             ..
         } = record;
 
@@ -238,6 +540,61 @@ impl From<AccountRecord> for AccountSummary {
             username,
             obtained_at,
             is_active,
+            account_type,
+            needs_profile,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accounts() -> Vec<AccountRecord> {
+        let mut online = minecraft_auth::offline_account("Notch").unwrap();
+        online.uuid = "069a79f444e94726a5befca90e38aaf5".to_string();
+        online.account_type = AccountType::Microsoft;
+        online.is_active = true;
+
+        vec![online, minecraft_auth::offline_account("Alex").unwrap()]
+    }
+
+    fn active(accounts: &[AccountRecord]) -> Vec<&str> {
+        accounts
+            .iter()
+            .filter(|account| account.is_active)
+            .map(|account| account.username.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn activating_switches_the_active_account() {
+        let mut accounts = accounts();
+        let uuid = accounts[1].uuid.clone();
+
+        activate_account(&mut accounts, &uuid, true).unwrap();
+        assert_eq!(active(&accounts), ["Alex"]);
+
+        let uuid = accounts[0].uuid.clone();
+        activate_account(&mut accounts, &uuid, false).unwrap();
+        assert_eq!(active(&accounts), ["Notch"]);
+    }
+
+    #[test]
+    fn offline_accounts_cannot_be_activated_when_disabled() {
+        let mut accounts = accounts();
+        let uuid = accounts[1].uuid.clone();
+
+        let err = activate_account(&mut accounts, &uuid, false).unwrap_err();
+        assert_eq!(err, "offline accounts are disabled in the settings");
+        assert_eq!(active(&accounts), ["Notch"]);
+    }
+
+    #[test]
+    fn unknown_accounts_are_rejected() {
+        let mut accounts = accounts();
+
+        assert!(activate_account(&mut accounts, "missing", true).is_err());
+        assert_eq!(active(&accounts), ["Notch"]);
+    }
+}
@@ -1,12 +1,30 @@
+use crate::utils::assets::{self, AssetLayout};
+use crate::utils::auth_error::AuthError;
+use crate::utils::download::{self, DownloadManager, DownloadTask};
+use crate::utils::java;
+use crate::utils::java_runtime::{self, RuntimeSource};
+use crate::utils::launch::{self, LaunchAccount, LaunchContext};
+use crate::utils::libraries;
+use crate::utils::minecraft_auth::{self, AccountType};
+use crate::utils::settings::{self, LaunchSettings};
+use crate::utils::version_json::RuleContext;
+use crate::utils::versions;
+use crate::utils::yggdrasil;
+use anyhow::{anyhow, Context};
 use base64::Engine;
-use log::{error, info};
+use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
-use std::fs;
+use std::collections::HashMap;
+use std::fs::{self, File};
 use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
 use tauri::{
-    generate_handler,
+    async_runtime, generate_handler,
     plugin::{self, TauriPlugin},
-    Manager, Runtime,
+    Emitter, Manager, Runtime,
 };
 
 pub fn init<R: Runtime>() -> TauriPlugin<R> {
@@ -19,6 +37,8 @@ pub fn init<R: Runtime>() -> TauriPlugin<R> {
                 fs::create_dir_all(&instances_dir)?;
             }
 
+            app.manage(RunningInstances::default());
+
             Ok(())
         })
         .invoke_handler(generate_handler![
@@ -28,6 +48,9 @@ pub fn init<R: Runtime>() -> TauriPlugin<R> {
             get_instances,
             rename_instance,
             update_instance_icon,
+            update_instance_settings,
+            launch_instance,
+            get_running_instances,
         ])
         .build()
 }
@@ -40,6 +63,112 @@ pub struct Instance {
     pub version: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub icon: Option<String>,
+    #[serde(default)]
+    pub settings: LaunchSettings,
+}
+
+/// Game processes started by `launch_instance`, keyed by instance identifier. Instances that
+/// are still being prepared are present without a pid.
+#[derive(Default)]
+struct RunningInstances(Mutex<HashMap<String, Option<u32>>>);
+
+/// An instance's entry in `RunningInstances` while it is being prepared. The entry is released
+/// again when the guard is dropped before the game was started.
+struct LaunchReservation<'a> {
+    running: &'a RunningInstances,
+    identifier: String,
+    launched: bool,
+}
+
+impl<'a> LaunchReservation<'a> {
+    fn reserve(running: &'a RunningInstances, identifier: &str) -> anyhow::Result<Self> {
+        let mut guard = running
+            .0
+            .lock()
+            .map_err(|_| anyhow!("running instances lock poisoned"))?;
+
+        if guard.contains_key(identifier) {
+            return Err(anyhow!("instance {} is already running", identifier));
+        }
+        guard.insert(identifier.to_string(), None);
+
+        Ok(Self {
+            running,
+            identifier: identifier.to_string(),
+            launched: false,
+        })
+    }
+
+    /// Records the pid of the started game; from here on the exit watcher owns the entry.
+    fn launched(mut self, pid: u32) -> anyhow::Result<()> {
+        self.running
+            .0
+            .lock()
+            .map_err(|_| anyhow!("running instances lock poisoned"))?
+            .insert(self.identifier.clone(), Some(pid));
+        self.launched = true;
+        Ok(())
+    }
+}
+
+/// The natives directory of a single launch. It is removed when dropped, unless the started
+/// game took it over through `keep`.
+struct NativesDir {
+    path: PathBuf,
+    kept: bool,
+}
+
+impl NativesDir {
+    fn new(path: PathBuf) -> Self {
+        Self { path, kept: false }
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Hands the directory to the caller, who becomes responsible for removing it.
+    fn keep(mut self) -> PathBuf {
+        self.kept = true;
+        std::mem::take(&mut self.path)
+    }
+}
+
+impl Drop for NativesDir {
+    fn drop(&mut self) {
+        if self.kept || !self.path.exists() {
+            return;
+        }
+        if let Err(err) = fs::remove_dir_all(&self.path) {
+            warn!(
+                "failed to clean up natives directory {:?}: {}",
+                self.path, err
+            );
+        }
+    }
+}
+
+impl Drop for LaunchReservation<'_> {
+    fn drop(&mut self) {
+        if self.launched {
+            return;
+        }
+        if let Ok(mut guard) = self.running.0.lock() {
+            guard.remove(&self.identifier);
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+struct RunningInstance {
+    identifier: String,
+    pid: u32,
+}
+
+#[derive(Serialize, Clone, Debug)]
+struct InstanceExited {
+    identifier: String,
+    code: Option<i32>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -58,6 +187,12 @@ struct UpdateInstanceIconOption {
     icon_data: Option<String>,
 }
 
+#[derive(Serialize, Deserialize)]
+struct UpdateInstanceSettingsOption {
+    identifier: String,
+    settings: LaunchSettings,
+}
+
 #[derive(Serialize, Deserialize)]
 struct RenameInstanceOption {
     identifier: String,
@@ -166,6 +301,16 @@ fn get_instances_internal<R: Runtime>(app: &tauri::AppHandle<R>) -> Result<Vec<I
     Ok(instances)
 }
 
+/// The launch settings every instance stores, keyed by identifier.
+pub(crate) fn instance_settings<R: Runtime>(
+    app: &tauri::AppHandle<R>,
+) -> Result<Vec<(String, LaunchSettings)>, String> {
+    Ok(get_instances_internal(app)?
+        .into_iter()
+        .map(|instance| (instance.identifier, instance.settings))
+        .collect())
+}
+
 fn create_instance_folders(instance_dir: &Path) -> Result<(), String> {
     let folders = vec![
         "mods",
@@ -251,8 +396,9 @@ async fn create_instance<R: Runtime>(
         return Err("Invalid loader. Must be vanilla, fabric, or forge".to_string());
     }
 
-    if !["1.8.9", "1.21.10"].contains(&options.version.as_str()) {
-        return Err("Invalid version. Must be 1.8.9 or 1.21.10".to_string());
+    if let Err(err) = versions::find_version(&app, &options.version).await {
+        error!("failed to validate version {}: {:#}", options.version, err);
+        return Err(format!("Invalid version: {:#}", err));
     }
 
     if check_name_exists(&app, &options.name, None)? {
@@ -316,6 +462,7 @@ async fn create_instance<R: Runtime>(
         loader: options.loader,
         version: options.version,
         icon: icon_path,
+        settings: LaunchSettings::default(),
     };
 
     save_instance(&app, &instance)?;
@@ -353,6 +500,15 @@ async fn remove_instance<R: Runtime>(
         return Err(format!("Instance {} not found", identifier));
     }
 
+    // holding a reservation keeps the instance from being launched while it is deleted
+    let running = app.state::<RunningInstances>();
+    let _reservation = LaunchReservation::reserve(&running, &identifier).map_err(|_| {
+        format!(
+            "Instance {} cannot be removed while it is running",
+            identifier
+        )
+    })?;
+
     fs::remove_dir_all(&instance_dir)
         .map_err(|e| format!("Failed to remove instance directory: {}", e))?;
 
@@ -487,3 +643,393 @@ async fn update_instance_icon<R: Runtime>(
     info!("Returning updated instance with icon");
     Ok(result)
 }
+
+#[tauri::command]
+async fn update_instance_settings<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    options: UpdateInstanceSettingsOption,
+) -> Result<Instance, String> {
+    let mut instance = load_instance(&app, &options.identifier)?;
+
+    let global = settings::read_settings(&app).map_err(|e| format!("{:#}", e))?;
+    options
+        .settings
+        .or(&global.launch)
+        .validate_memory()
+        .map_err(|e| format!("{:#}", e))?;
+
+    instance.settings = options.settings;
+
+    save_instance(&app, &instance)?;
+
+    info!(
+        "Updated launch settings of instance {}",
+        instance.identifier
+    );
+
+    Ok(instance)
+}
+
+#[tauri::command]
+async fn launch_instance<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    identifier: String,
+) -> Result<RunningInstance, String> {
+    match launch_instance_impl(&app, &identifier).await {
+        Ok(running) => Ok(running),
+        Err(err) => {
+            error!("failed to launch instance {}: {:#}", identifier, err);
+            Err(format!("{:#}", err))
+        }
+    }
+}
+
+#[tauri::command]
+async fn get_running_instances<R: Runtime>(
+    app: tauri::AppHandle<R>,
+) -> Result<Vec<RunningInstance>, String> {
+    let running = app.state::<RunningInstances>();
+    let guard = running
+        .0
+        .lock()
+        .map_err(|_| "running instances lock poisoned".to_string())?;
+
+    Ok(guard
+        .iter()
+        .filter_map(|(identifier, pid)| {
+            Some(RunningInstance {
+                identifier: identifier.clone(),
+                pid: (*pid)?,
+            })
+        })
+        .collect())
+}
+
+async fn launch_instance_impl<R: Runtime>(
+    app: &tauri::AppHandle<R>,
+    identifier: &str,
+) -> anyhow::Result<RunningInstance> {
+    let running = app.state::<RunningInstances>();
+    let reservation = LaunchReservation::reserve(running.inner(), identifier)?;
+
+    let instance = load_instance(app, identifier).map_err(|e| anyhow!(e))?;
+    let settings = instance.settings.or(&settings::read_settings(app)?.launch);
+    settings.validate_memory()?;
+    let instance_dir = get_instance_dir(app, identifier).map_err(|e| anyhow!(e))?;
+
+    let account = minecraft_auth::read_accounts(app)?
+        .into_iter()
+        .find(|account| account.is_active)
+        .ok_or_else(|| anyhow!("no active account selected"))?;
+    if account.account_type == AccountType::Offline
+        && settings::read_settings(app)?
+            .accounts
+            .disable_offline_accounts
+    {
+        return Err(anyhow!("offline accounts are disabled in the settings"));
+    }
+    let account = minecraft_auth::ensure_fresh(app, account)
+        .await
+        .context("failed to refresh the active account")?;
+    if account.needs_profile {
+        return Err(AuthError::NoProfile.into());
+    }
+
+    let version = versions::resolve_version_json(app, &instance.version).await?;
+
+    let data_dir = app
+        .path()
+        .app_data_dir()
+        .context("failed to resolve app data directory")?;
+    let versions_dir = versions::versions_dir(app)?;
+    let libraries_dir = data_dir.join("libraries");
+    let assets_dir = data_dir.join("assets");
+    let natives_dir = NativesDir::new(data_dir.join("natives").join(format!(
+        "{}-{}",
+        identifier,
+        launch_timestamp()
+    )));
+    let client_jar = versions_dir
+        .join(&version.id)
+        .join(format!("{}.jar", version.id));
+
+    let rules = RuleContext::current()
+        .with_feature("has_custom_resolution", settings.resolution().is_some());
+    let libraries = libraries::resolve_libraries(&version, &libraries_dir, &rules);
+
+    let mut tasks = libraries.download_tasks();
+
+    if let Some(client) = version
+        .downloads
+        .as_ref()
+        .and_then(|downloads| downloads.client.as_ref())
+    {
+        tasks.push(DownloadTask {
+            url: client.url.clone(),
+            path: client_jar.clone(),
+            sha1: Some(client.sha1.clone()),
+            size: Some(client.size),
+        });
+    }
+
+    let logging_config = version
+        .logging
+        .as_ref()
+        .and_then(|logging| logging.client.as_ref())
+        .map(|config| {
+            let path = assets_dir.join("log_configs").join(&config.file.id);
+            tasks.push(DownloadTask {
+                url: config.file.url.clone(),
+                path: path.clone(),
+                sha1: Some(config.file.sha1.clone()),
+                size: Some(config.file.size),
+            });
+            path
+        });
+
+    let client = reqwest::Client::new();
+    let downloads =
+        DownloadManager::new(client.clone()).with_reporter(download::emit_to(app.clone()));
+    downloads
+        .download_all(identifier, tasks)
+        .await
+        .context("failed to download game files")?;
+    libraries.ensure_present()?;
+
+    if !client_jar.exists() {
+        return Err(anyhow!(
+            "client jar for {} is missing at {:?}",
+            version.id,
+            client_jar
+        ));
+    }
+
+    libraries::extract_natives(&libraries.natives, natives_dir.path())
+        .context("failed to extract native libraries")?;
+
+    let mut extra_jvm_args = Vec::new();
+    if let Some(session) = &account.yggdrasil {
+        let injector =
+            yggdrasil::ensure_authlib_injector(&client, &data_dir.join("authlib-injector"))
+                .await
+                .context("failed to install authlib-injector")?;
+        extra_jvm_args.push(format!(
+            "-javaagent:{}={}",
+            injector.to_string_lossy(),
+            session.server_url
+        ));
+    }
+
+    let assets = match &version.asset_index {
+        Some(index) => {
+            assets::install_assets(&downloads, identifier, &assets_dir, &instance_dir, index)
+                .await
+                .context("failed to install assets")?
+        }
+        None => AssetLayout {
+            index_name: version.assets_id().unwrap_or("legacy").to_string(),
+            assets_root: assets_dir.clone(),
+            game_assets: assets_dir.clone(),
+        },
+    };
+
+    let context = LaunchContext {
+        account: LaunchAccount::from(&account),
+        version_name: version.id.clone(),
+        game_directory: instance_dir.clone(),
+        assets_root: assets.assets_root,
+        game_assets: assets.game_assets,
+        assets_index_name: assets.index_name,
+        natives_directory: natives_dir.path().to_path_buf(),
+        libraries_directory: libraries_dir,
+        classpath: libraries.classpath(&client_jar),
+        logging_config,
+        settings: settings.clone(),
+        extra_jvm_args,
+    };
+
+    let command = launch::build_launch_command(&version, &context, &rules)?;
+
+    let logs_dir = instance_dir.join("logs");
+    fs::create_dir_all(&logs_dir).context("failed to create logs directory")?;
+    let output = File::create(logs_dir.join("launcher-output.log"))
+        .context("failed to create game output log")?;
+
+    let java = match (&settings.java_path, &version.java_version) {
+        (Some(java_path), requirement) => {
+            let runtime = validate_java(app, java_path)
+                .await
+                .with_context(|| format!("the configured java {:?} is not usable", java_path))?;
+            if let Some(requirement) = requirement {
+                if runtime.major_version != requirement.major_version {
+                    warn!(
+                        "configured java {:?} is Java {}, but {} asks for Java {}",
+                        runtime.path, runtime.major_version, version.id, requirement.major_version
+                    );
+                }
+            }
+            runtime.path
+        }
+        (None, Some(requirement)) => match java_runtime::ensure_runtime(
+            &downloads,
+            &client,
+            &RuntimeSource::from_env(),
+            &java::runtimes_dir(app)?,
+            requirement,
+        )
+        .await
+        {
+            Ok(executable) => executable,
+            Err(err) => {
+                warn!(
+                    "failed to prepare managed java runtime {}: {:#}",
+                    requirement.component, err
+                );
+                select_java(app, Some(requirement.major_version)).await?
+            }
+        },
+        (None, None) => select_java(app, None).await?,
+    };
+    info!(
+        "launching instance {} ({}) with {:?}",
+        instance.identifier, version.id, java
+    );
+
+    let mut process = match settings.wrapper_command() {
+        Some((wrapper, wrapper_args)) => {
+            let mut process = Command::new(wrapper);
+            process.args(wrapper_args).arg(&java);
+            process
+        }
+        None => Command::new(&java),
+    };
+
+    let mut child = process
+        .args(command.args())
+        .envs(settings.env.iter().flatten())
+        .current_dir(&instance_dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::from(
+            output
+                .try_clone()
+                .context("failed to open game output log")?,
+        ))
+        .stderr(Stdio::from(output))
+        .spawn()
+        .with_context(|| format!("failed to start java at {:?}", java))?;
+
+    let pid = child.id();
+    reservation.launched(pid)?;
+    let natives_dir = natives_dir.keep();
+
+    let running_instance = RunningInstance {
+        identifier: identifier.to_string(),
+        pid,
+    };
+    let _ = app.emit("instance-launched", running_instance.clone());
+
+    let app = app.clone();
+    let identifier = identifier.to_string();
+    thread::spawn(move || {
+        let code = match child.wait() {
+            Ok(status) => status.code(),
+            Err(err) => {
+                error!("failed to wait for instance {}: {}", identifier, err);
+                None
+            }
+        };
+
+        info!("instance {} exited with code {:?}", identifier, code);
+
+        if let Err(err) = fs::remove_dir_all(&natives_dir) {
+            warn!(
+                "failed to clean up natives directory {:?}: {}",
+                natives_dir, err
+            );
+        }
+
+        if let Ok(mut guard) = app.state::<RunningInstances>().0.lock() {
+            guard.remove(&identifier);
+        }
+
+        let _ = app.emit("instance-exited", InstanceExited { identifier, code });
+    });
+
+    Ok(running_instance)
+}
+
+/// `java::select_executable` starts the runtimes it probes, so it runs on the blocking pool.
+async fn select_java<R: Runtime>(
+    app: &tauri::AppHandle<R>,
+    major_version: Option<u32>,
+) -> anyhow::Result<PathBuf> {
+    let app = app.clone();
+    async_runtime::spawn_blocking(move || java::select_executable(&app, major_version))
+        .await
+        .context("java selection task failed")?
+}
+
+async fn validate_java<R: Runtime>(
+    app: &tauri::AppHandle<R>,
+    path: &Path,
+) -> anyhow::Result<java::JavaRuntime> {
+    let app = app.clone();
+    let path = path.to_path_buf();
+    async_runtime::spawn_blocking(move || java::validate(&app, &path))
+        .await
+        .context("java validation task failed")?
+}
+
+fn launch_timestamp() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reservation_blocks_a_second_launch() {
+        let running = RunningInstances::default();
+
+        let reservation = LaunchReservation::reserve(&running, "survival").unwrap();
+        assert!(LaunchReservation::reserve(&running, "survival").is_err());
+        assert!(LaunchReservation::reserve(&running, "creative").is_ok());
+
+        drop(reservation);
+        assert!(running.0.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn launched_reservation_keeps_its_pid() {
+        let running = RunningInstances::default();
+
+        LaunchReservation::reserve(&running, "survival")
+            .unwrap()
+            .launched(4242)
+            .unwrap();
+
+        assert_eq!(running.0.lock().unwrap().get("survival"), Some(&Some(4242)));
+        assert!(LaunchReservation::reserve(&running, "survival").is_err());
+    }
+
+    #[test]
+    fn natives_dir_is_removed_unless_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let failed = dir.path().join("natives/survival-1");
+        let launched = dir.path().join("natives/survival-2");
+        fs::create_dir_all(&failed).unwrap();
+        fs::create_dir_all(&launched).unwrap();
+        fs::write(failed.join("liblwjgl.so"), b"").unwrap();
+
+        drop(NativesDir::new(failed.clone()));
+        let kept = NativesDir::new(launched.clone()).keep();
+
+        assert!(!failed.exists());
+        assert_eq!(kept, launched);
+        assert!(launched.exists());
+    }
+}
@@ -0,0 +1,43 @@
+use std::path::PathBuf;
+
+use crate::utils::java::{self, JavaCache, JavaRuntime};
+use tauri::{
+    async_runtime, generate_handler,
+    plugin::{self, TauriPlugin},
+    AppHandle, Manager, Runtime,
+};
+
+pub fn init<R: Runtime>() -> TauriPlugin<R> {
+    plugin::Builder::<R>::new("java")
+        .setup(|app, _api| {
+            app.manage(JavaCache::default());
+            Ok(())
+        })
+        .invoke_handler(generate_handler![list_java_runtimes, validate_java_path])
+        .build()
+}
+
+#[tauri::command]
+async fn list_java_runtimes<R: Runtime>(
+    app: AppHandle<R>,
+    refresh: Option<bool>,
+) -> Result<Vec<JavaRuntime>, String> {
+    async_runtime::spawn_blocking(move || java::discover(&app, refresh.unwrap_or(false)))
+        .await
+        .map_err(|err| err.to_string())?
+        .map_err(|err| {
+            log::error!("failed to discover java runtimes: {:#}", err);
+            format!("{:#}", err)
+        })
+}
+
+#[tauri::command]
+async fn validate_java_path<R: Runtime>(
+    app: AppHandle<R>,
+    path: PathBuf,
+) -> Result<JavaRuntime, String> {
+    async_runtime::spawn_blocking(move || java::validate(&app, &path))
+        .await
+        .map_err(|err| err.to_string())?
+        .map_err(|err| format!("{:#}", err))
+}
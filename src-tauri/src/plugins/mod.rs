@@ -1,2 +1,6 @@
 pub mod account;
 pub mod instance;
+pub mod java;
+pub mod settings;
+pub mod version;
+pub mod wardrobe;
@@ -0,0 +1,46 @@
+use crate::plugins::instance;
+use crate::utils::settings::{self, Settings};
+use tauri::{
+    generate_handler,
+    plugin::{self, TauriPlugin},
+    AppHandle, Runtime,
+};
+
+pub fn init<R: Runtime>() -> TauriPlugin<R> {
+    plugin::Builder::<R>::new("settings")
+        .invoke_handler(generate_handler![get_settings, update_settings])
+        .build()
+}
+
+#[tauri::command]
+async fn get_settings<R: Runtime>(app: AppHandle<R>) -> Result<Settings, String> {
+    settings::read_settings(&app).map_err(|err| {
+        log::error!("failed to read settings: {:#}", err);
+        format!("{:#}", err)
+    })
+}
+
+#[tauri::command]
+async fn update_settings<R: Runtime>(
+    app: AppHandle<R>,
+    settings: Settings,
+) -> Result<Settings, String> {
+    // instances fall back to the global memory bounds, so a change may break one of them
+    settings
+        .launch
+        .validate_memory()
+        .map_err(|err| format!("{:#}", err))?;
+    for (identifier, instance) in instance::instance_settings(&app)? {
+        instance
+            .or(&settings.launch)
+            .validate_memory()
+            .map_err(|err| format!("instance {}: {:#}", identifier, err))?;
+    }
+
+    settings::write_settings(&app, &settings).map_err(|err| {
+        log::error!("failed to update settings: {:#}", err);
+        format!("{:#}", err)
+    })?;
+
+    Ok(settings)
+}
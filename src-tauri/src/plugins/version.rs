@@ -0,0 +1,36 @@
+use crate::utils::versions::{self, VersionEntry, VersionType};
+use tauri::{
+    generate_handler,
+    plugin::{self, TauriPlugin},
+    AppHandle, Runtime,
+};
+
+pub fn init<R: Runtime>() -> TauriPlugin<R> {
+    plugin::Builder::<R>::new("version")
+        .invoke_handler(generate_handler![list_versions, get_version])
+        .build()
+}
+
+#[tauri::command]
+async fn list_versions<R: Runtime>(
+    app: AppHandle<R>,
+    types: Option<Vec<VersionType>>,
+    refresh: Option<bool>,
+) -> Result<Vec<VersionEntry>, String> {
+    let manifest = versions::load_manifest(&app, refresh.unwrap_or(false))
+        .await
+        .map_err(|err| {
+            log::error!("failed to load version manifest: {:#}", err);
+            format!("{:#}", err)
+        })?;
+
+    Ok(manifest.filter(types.as_deref().unwrap_or(&[VersionType::Release])))
+}
+
+#[tauri::command]
+async fn get_version<R: Runtime>(app: AppHandle<R>, id: String) -> Result<VersionEntry, String> {
+    versions::find_version(&app, &id).await.map_err(|err| {
+        log::error!("failed to look up version {}: {:#}", id, err);
+        format!("{:#}", err)
+    })
+}
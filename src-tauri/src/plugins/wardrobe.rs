@@ -0,0 +1,208 @@
+use crate::commands::skin::{self, SkinPreview};
+use crate::utils::files::write_replacing;
+use crate::utils::minecraft_services::{MinecraftProfile, SkinVariant};
+use crate::utils::player_lookup::{self, LookupError};
+use crate::utils::skin_library::{self, PresetCape, SkinLibraryLock, SkinPreset};
+use serde::{Deserialize, Serialize};
+use tauri::{
+    generate_handler,
+    plugin::{self, TauriPlugin},
+    AppHandle, Manager, Runtime,
+};
+
+/// Previews larger than this are only ever scaled up by the browser anyway.
+const MAX_PREVIEW_SCALE: u32 = 8;
+
+pub fn init<R: Runtime>() -> TauriPlugin<R> {
+    plugin::Builder::<R>::new("wardrobe")
+        .setup(|app, _api| {
+            app.manage(SkinLibraryLock::default());
+            Ok(())
+        })
+        .invoke_handler(generate_handler![
+            list_skins,
+            import_skin,
+            update_skin,
+            remove_skin,
+            apply_skin
+        ])
+        .build()
+}
+
+/// A preset with thumbnails and the accounts it was last applied to.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SkinSummary {
+    #[serde(flatten)]
+    preset: SkinPreset,
+    preview: Option<SkinPreview>,
+    applied_to: Vec<String>,
+}
+
+/// Where an imported skin comes from.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "lowercase", tag = "type")]
+enum SkinSource {
+    /// A PNG picked by the user, as a data URL or plain base64.
+    File {
+        data: String,
+    },
+    Url {
+        url: String,
+    },
+    /// The skin another player currently wears.
+    Player {
+        username: String,
+    },
+}
+
+#[derive(Deserialize, Debug)]
+struct ImportSkinOption {
+    source: SkinSource,
+    /// Defaults to the player's name for player imports.
+    name: Option<String>,
+    /// Defaults to the player's model for player imports and to classic otherwise.
+    model: Option<SkinVariant>,
+    #[serde(default)]
+    cape: PresetCape,
+}
+
+#[derive(Deserialize, Debug)]
+struct UpdateSkinOption {
+    id: String,
+    name: Option<String>,
+    model: Option<SkinVariant>,
+    cape: Option<PresetCape>,
+}
+
+#[tauri::command]
+async fn list_skins<R: Runtime>(app: AppHandle<R>, scale: u32) -> Result<Vec<SkinSummary>, String> {
+    let library = skin_library::read_library(&app).map_err(wardrobe_error)?;
+    let scale = scale.clamp(1, MAX_PREVIEW_SCALE);
+
+    Ok(library
+        .presets
+        .iter()
+        .map(|preset| SkinSummary {
+            preview: preview(&app, preset, scale),
+            applied_to: library.applied_to(&preset.id),
+            preset: preset.clone(),
+        })
+        .collect())
+}
+
+#[tauri::command]
+async fn import_skin<R: Runtime>(
+    app: AppHandle<R>,
+    options: ImportSkinOption,
+) -> Result<SkinPreset, LookupError> {
+    let texture_error = |message| LookupError::Texture { message };
+    let (png, name, model) = match options.source {
+        SkinSource::File { data } => (
+            skin::decode_skin_data(&data).map_err(texture_error)?,
+            None,
+            None,
+        ),
+        SkinSource::Url { url } => (
+            skin::download_skin(&url).await.map_err(texture_error)?,
+            None,
+            None,
+        ),
+        SkinSource::Player { username } => {
+            let player = player_lookup::lookup_player(&app, &username).await?;
+            let (png, model) = skin::player_skin_bytes(&app, &player.id).await?;
+            (png, Some(player.name), Some(model))
+        }
+    };
+
+    skin::validate_skin_png(&png).map_err(texture_error)?;
+
+    let name = options
+        .name
+        .or(name)
+        .ok_or_else(|| LookupError::Other("skin presets need a name".to_string()))?;
+    let model = options.model.or(model).unwrap_or_default();
+
+    skin_library::add_preset(&app, &name, model, options.cape, &png)
+        .map_err(|err| LookupError::Other(wardrobe_error(err)))
+}
+
+#[tauri::command]
+async fn update_skin<R: Runtime>(
+    app: AppHandle<R>,
+    options: UpdateSkinOption,
+) -> Result<SkinPreset, String> {
+    skin_library::update_preset(&app, &options.id, options.name, options.model, options.cape)
+        .map_err(wardrobe_error)
+}
+
+#[tauri::command]
+async fn remove_skin<R: Runtime>(app: AppHandle<R>, id: String) -> Result<(), String> {
+    skin_library::remove_preset(&app, &id).map_err(wardrobe_error)
+}
+
+/// Uploads preset `id` to the active account and switches its cape as the preset says. The
+/// preset counts as applied once the skin is uploaded, even if the cape cannot be changed.
+#[tauri::command]
+async fn apply_skin<R: Runtime>(app: AppHandle<R>, id: String) -> Result<MinecraftProfile, String> {
+    let library = skin_library::read_library(&app).map_err(wardrobe_error)?;
+    let preset = library.preset(&id).map_err(wardrobe_error)?.clone();
+    let png = skin_library::read_skin(&app, &preset.id).map_err(wardrobe_error)?;
+
+    let profile = skin::upload_skin_bytes(&app, png, preset.model).await?;
+    skin_library::mark_applied(&app, &profile.id, &preset.id).map_err(wardrobe_error)?;
+
+    let cape = match preset.cape {
+        PresetCape::Keep => return Ok(profile),
+        PresetCape::Hide => skin::hide_cape(app.clone()).await,
+        PresetCape::Wear { id } => skin::set_active_cape(app.clone(), id).await,
+    };
+    cape.map_err(|err| {
+        format!(
+            "the skin was applied, but changing the cape failed: {}",
+            err
+        )
+    })
+}
+
+/// A broken preset file should not hide the rest of the library, so failures are only logged.
+fn preview<R: Runtime>(app: &AppHandle<R>, preset: &SkinPreset, scale: u32) -> Option<SkinPreview> {
+    match cached_preview(app, preset, scale) {
+        Ok(preview) => Some(preview),
+        Err(err) => {
+            log::warn!("failed to render preview of skin {}: {}", preset.id, err);
+            None
+        }
+    }
+}
+
+/// Renders the preview once and keeps it next to the preset's PNG for later listings.
+fn cached_preview<R: Runtime>(
+    app: &AppHandle<R>,
+    preset: &SkinPreset,
+    scale: u32,
+) -> Result<SkinPreview, String> {
+    let path = skin_library::thumbnail_path(app, &preset.id, preset.model, scale)
+        .map_err(|err| format!("{:#}", err))?;
+    if let Some(preview) = std::fs::read(&path)
+        .ok()
+        .and_then(|cached| serde_json::from_slice(&cached).ok())
+    {
+        return Ok(preview);
+    }
+
+    let png = skin_library::read_skin(app, &preset.id).map_err(|err| format!("{:#}", err))?;
+    let preview = skin::render_preview(&png, preset.model, scale)?;
+
+    let cached = serde_json::to_vec(&preview).map_err(|err| err.to_string())?;
+    if let Err(err) = write_replacing(&path, &cached) {
+        log::warn!("failed to cache preview of skin {}: {:#}", preset.id, err);
+    }
+
+    Ok(preview)
+}
+
+fn wardrobe_error(err: anyhow::Error) -> String {
+    log::error!("skin library request failed: {:#}", err);
+    format!("{:#}", err)
+}
@@ -0,0 +1,401 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::download::{DownloadManager, DownloadTask};
+use crate::utils::env_url;
+use crate::utils::version_json::AssetIndexRef;
+
+pub const RESOURCES_URL: &str = "https://resources.download.minecraft.net";
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AssetIndex {
+    pub objects: HashMap<String, AssetObject>,
+    /// Pre-1.6 versions read assets from `<game dir>/resources`.
+    #[serde(default)]
+    pub map_to_resources: bool,
+    /// 1.6 - 1.7.2 read assets from `assets/virtual/<index>` by their original names.
+    #[serde(rename = "virtual", default)]
+    pub is_virtual: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AssetObject {
+    pub hash: String,
+    pub size: u64,
+}
+
+/// Where the game should look for its assets once they are installed.
+#[derive(Debug, Clone)]
+pub struct AssetLayout {
+    pub index_name: String,
+    pub assets_root: PathBuf,
+    pub game_assets: PathBuf,
+}
+
+impl AssetObject {
+    fn relative_path(&self) -> String {
+        format!("{}/{}", &self.hash[..2], self.hash)
+    }
+}
+
+/// Downloads the asset index and every object it lists into the shared `assets_dir`,
+/// fetching objects that are missing or have the wrong size, and materialises the `virtual`
+/// or `resources` tree for indexes that need one.
+pub async fn install_assets(
+    downloads: &DownloadManager,
+    group: &str,
+    assets_dir: &Path,
+    game_directory: &Path,
+    index_ref: &AssetIndexRef,
+) -> Result<AssetLayout> {
+    install_assets_from(
+        downloads,
+        &env_url("DECENT_RESOURCES_URL", RESOURCES_URL),
+        group,
+        assets_dir,
+        game_directory,
+        index_ref,
+    )
+    .await
+}
+
+async fn install_assets_from(
+    downloads: &DownloadManager,
+    resources_url: &str,
+    group: &str,
+    assets_dir: &Path,
+    game_directory: &Path,
+    index_ref: &AssetIndexRef,
+) -> Result<AssetLayout> {
+    let index_path = assets_dir
+        .join("indexes")
+        .join(format!("{}.json", index_ref.id));
+
+    downloads
+        .download_all(
+            group,
+            vec![DownloadTask {
+                url: index_ref.url.clone(),
+                path: index_path.clone(),
+                sha1: Some(index_ref.sha1.clone()),
+                size: Some(index_ref.size),
+            }],
+        )
+        .await
+        .with_context(|| format!("failed to download asset index {}", index_ref.id))?;
+
+    let contents = fs::read_to_string(&index_path)
+        .with_context(|| format!("failed to read asset index {}", index_ref.id))?;
+    let index: AssetIndex = serde_json::from_str(&contents)
+        .with_context(|| format!("failed to parse asset index {}", index_ref.id))?;
+
+    let objects_dir = assets_dir.join("objects");
+    let mut tasks: Vec<DownloadTask> = Vec::with_capacity(index.objects.len());
+    let mut seen = HashSet::new();
+
+    for object in index.objects.values() {
+        if object.hash.len() < 2 || !seen.insert(object.hash.as_str()) {
+            continue;
+        }
+
+        let relative = object.relative_path();
+        let path = objects_dir.join(&relative);
+
+        // objects are named by their hash and verified when downloaded, so hashing thousands
+        // of them again on every launch buys nothing over checking that they are all there
+        let present = fs::metadata(&path)
+            .map(|metadata| metadata.len() == object.size)
+            .unwrap_or(false);
+        if present {
+            continue;
+        }
+
+        tasks.push(DownloadTask {
+            url: format!("{}/{}", resources_url, relative),
+            path,
+            sha1: Some(object.hash.clone()),
+            size: Some(object.size),
+        });
+    }
+
+    info!(
+        "downloading {} missing asset objects for index {}",
+        tasks.len(),
+        index_ref.id
+    );
+
+    downloads
+        .download_all(group, tasks)
+        .await
+        .context("failed to download asset objects")?;
+
+    let game_assets = if index.map_to_resources {
+        let resources_dir = game_directory.join("resources");
+        materialise(&index, &objects_dir, &resources_dir)?;
+        resources_dir
+    } else if index.is_virtual {
+        let virtual_dir = assets_dir.join("virtual").join(&index_ref.id);
+        materialise(&index, &objects_dir, &virtual_dir)?;
+        virtual_dir
+    } else {
+        assets_dir.to_path_buf()
+    };
+
+    Ok(AssetLayout {
+        index_name: index_ref.id.clone(),
+        assets_root: assets_dir.to_path_buf(),
+        game_assets,
+    })
+}
+
+/// Copies objects to their named paths under `target`, hard-linking where possible.
+fn materialise(index: &AssetIndex, objects_dir: &Path, target: &Path) -> Result<()> {
+    debug!("materialising legacy assets into {:?}", target);
+
+    for (name, object) in &index.objects {
+        if object.hash.len() < 2 {
+            continue;
+        }
+
+        let is_relative = Path::new(name)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        if !is_relative {
+            bail!("asset name {:?} escapes the asset directory", name);
+        }
+
+        let source = objects_dir.join(object.relative_path());
+        let destination = target.join(name);
+
+        let up_to_date = fs::metadata(&destination)
+            .map(|metadata| metadata.len() == object.size)
+            .unwrap_or(false);
+        if up_to_date {
+            continue;
+        }
+
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create directory {:?}", parent))?;
+        }
+
+        let _ = fs::remove_file(&destination);
+        if fs::hard_link(&source, &destination).is_err() {
+            fs::copy(&source, &destination)
+                .with_context(|| format!("failed to copy asset {} to {:?}", name, destination))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_server::{block_on, Response, TestServer};
+    use reqwest::Client;
+    use sha1::{Digest, Sha1};
+
+    const SOUND: &[u8] = b"a legacy sound";
+    const LANG: &[u8] = b"language strings";
+
+    fn sha1(bytes: &[u8]) -> String {
+        format!("{:x}", Sha1::digest(bytes))
+    }
+
+    fn index_json(flags: &str) -> String {
+        format!(
+            r#"{{{}"objects":{{"sound/step.ogg":{{"hash":"{}","size":{}}},"lang/en_us.json":{{"hash":"{}","size":{}}}}}}}"#,
+            flags,
+            sha1(SOUND),
+            SOUND.len(),
+            sha1(LANG),
+            LANG.len()
+        )
+    }
+
+    /// Serves the index under `/indexes/` and each object under its hash, or garbage in place
+    /// of every object when `corrupt` is set.
+    fn asset_server(index: String, corrupt: bool) -> TestServer {
+        TestServer::start(move |request| {
+            if request.path.starts_with("/indexes/") {
+                return Response::ok(index.clone());
+            }
+            let hash = request.path.rsplit('/').next().unwrap_or_default();
+            match [SOUND, LANG].into_iter().find(|body| sha1(body) == hash) {
+                Some(_) if corrupt => Response::ok(b"corrupted bytes".to_vec()),
+                Some(body) => Response::ok(body),
+                None => Response::status(404),
+            }
+        })
+    }
+
+    fn index_ref(server: &TestServer, index: &str) -> AssetIndexRef {
+        AssetIndexRef {
+            id: "legacy".to_string(),
+            sha1: sha1(index.as_bytes()),
+            size: index.len() as u64,
+            total_size: None,
+            url: format!("{}/indexes/legacy.json", server.url()),
+        }
+    }
+
+    fn install(
+        server: &TestServer,
+        index: &str,
+        dir: &Path,
+        downloads: DownloadManager,
+    ) -> Result<AssetLayout> {
+        block_on(install_assets_from(
+            &downloads,
+            server.url(),
+            "test",
+            &dir.join("assets"),
+            &dir.join("game"),
+            &index_ref(server, index),
+        ))
+    }
+
+    #[test]
+    fn downloads_the_index_and_objects_by_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = index_json("");
+        let server = asset_server(index.clone(), false);
+
+        let layout = install(
+            &server,
+            &index,
+            dir.path(),
+            DownloadManager::new(Client::new()),
+        )
+        .unwrap();
+
+        let objects = dir.path().join("assets/objects");
+        let hash = sha1(SOUND);
+        assert_eq!(
+            fs::read(objects.join(&hash[..2]).join(&hash)).unwrap(),
+            SOUND
+        );
+        assert!(dir.path().join("assets/indexes/legacy.json").is_file());
+        assert_eq!(layout.index_name, "legacy");
+        assert_eq!(layout.game_assets, dir.path().join("assets"));
+    }
+
+    #[test]
+    fn objects_already_present_are_not_downloaded_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = index_json("");
+        let server = asset_server(index.clone(), false);
+
+        install(
+            &server,
+            &index,
+            dir.path(),
+            DownloadManager::new(Client::new()),
+        )
+        .unwrap();
+        install(
+            &server,
+            &index,
+            dir.path(),
+            DownloadManager::new(Client::new()),
+        )
+        .unwrap();
+
+        assert_eq!(server.requests_to("/indexes/").len(), 1);
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[test]
+    fn virtual_indexes_are_materialised_under_their_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = index_json(r#""virtual":true,"#);
+        let server = asset_server(index.clone(), false);
+
+        let layout = install(
+            &server,
+            &index,
+            dir.path(),
+            DownloadManager::new(Client::new()),
+        )
+        .unwrap();
+
+        let virtual_dir = dir.path().join("assets/virtual/legacy");
+        assert_eq!(layout.game_assets, virtual_dir);
+        assert_eq!(fs::read(virtual_dir.join("sound/step.ogg")).unwrap(), SOUND);
+        assert_eq!(fs::read(virtual_dir.join("lang/en_us.json")).unwrap(), LANG);
+    }
+
+    #[test]
+    fn resource_indexes_are_materialised_in_the_game_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = index_json(r#""map_to_resources":true,"#);
+        let server = asset_server(index.clone(), false);
+
+        let layout = install(
+            &server,
+            &index,
+            dir.path(),
+            DownloadManager::new(Client::new()),
+        )
+        .unwrap();
+
+        let resources = dir.path().join("game/resources");
+        assert_eq!(layout.game_assets, resources);
+        assert_eq!(fs::read(resources.join("sound/step.ogg")).unwrap(), SOUND);
+    }
+
+    #[test]
+    fn objects_with_the_wrong_hash_fail_the_install() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = index_json("");
+        let server = asset_server(index.clone(), true);
+
+        let result = install(
+            &server,
+            &index,
+            dir.path(),
+            DownloadManager::new(Client::new()).with_retries(0),
+        );
+
+        assert!(result.is_err());
+        let hash = sha1(SOUND);
+        let object = dir
+            .path()
+            .join("assets/objects")
+            .join(&hash[..2])
+            .join(&hash);
+        assert!(!object.exists());
+    }
+
+    #[test]
+    fn asset_names_cannot_escape_the_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let objects = dir.path().join("objects");
+        let hash = sha1(SOUND);
+        fs::create_dir_all(objects.join(&hash[..2])).unwrap();
+        fs::write(objects.join(&hash[..2]).join(&hash), SOUND).unwrap();
+
+        for name in ["../outside.ogg", "/etc/outside.ogg"] {
+            let index = AssetIndex {
+                objects: HashMap::from([(
+                    name.to_string(),
+                    AssetObject {
+                        hash: hash.clone(),
+                        size: SOUND.len() as u64,
+                    },
+                )]),
+                map_to_resources: true,
+                is_virtual: false,
+            };
+
+            assert!(materialise(&index, &objects, &dir.path().join("resources")).is_err());
+        }
+        assert!(!dir.path().join("outside.ogg").exists());
+    }
+}
@@ -0,0 +1,341 @@
+use chrono::{DateTime, Utc};
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+
+/// Why signing in or refreshing an account failed, in a form the frontend can act on.
+/// Serialised as `{ kind, message }`.
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum AuthError {
+    /// The Microsoft identity platform rejected the request, e.g. `invalid_grant`.
+    #[error("Microsoft sign-in failed ({error}): {message}")]
+    OAuth { error: String, message: String },
+    /// XSTS refused to issue a token; `code` is the `XErr` from the response body.
+    #[error("{}", xbox_message(*.code))]
+    Xbox { code: u64 },
+    #[error("this Microsoft account does not own Minecraft")]
+    NoEntitlement,
+    #[error(
+        "this account has no Minecraft: Java Edition profile yet, choose a profile name first"
+    )]
+    NoProfile,
+    /// Minecraft services refused a profile name; `status` is `DUPLICATE` or `NOT_ALLOWED`.
+    #[error("{}", profile_name_message(name, status))]
+    ProfileName { name: String, status: String },
+    #[error("{}", cooldown_message(next_change_at))]
+    NameChangeCooldown {
+        next_change_at: Option<DateTime<Utc>>,
+    },
+    #[error("sign-in was cancelled")]
+    Cancelled,
+    /// A Yggdrasil server answered with an error body, e.g. `ForbiddenOperationException`
+    /// for a wrong password.
+    #[error("authentication server rejected the request ({error}): {message}")]
+    Yggdrasil { error: String, message: String },
+    #[error("could not reach {service}: {message}")]
+    Network { service: String, message: String },
+    /// A step of the sign-in chain answered with something other than what it should have,
+    /// e.g. a server error.
+    #[error("{service} failed: {message}")]
+    Service { service: String, message: String },
+    #[error("{0}")]
+    Other(String),
+}
+
+impl AuthError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AuthError::OAuth { .. } => "oauth",
+            AuthError::Xbox { code } => match code {
+                2148916233 => "no_xbox_account",
+                2148916235 => "xbox_region_unavailable",
+                2148916236 | 2148916237 => "xbox_adult_verification",
+                2148916238 => "xbox_child_account",
+                _ => "xbox",
+            },
+            AuthError::NoEntitlement => "no_entitlement",
+            AuthError::NoProfile => "no_profile",
+            AuthError::ProfileName { status, .. } => match status.as_str() {
+                "DUPLICATE" => "profile_name_taken",
+                "NOT_ALLOWED" => "profile_name_not_allowed",
+                _ => "profile_name",
+            },
+            AuthError::NameChangeCooldown { .. } => "name_change_cooldown",
+            AuthError::Cancelled => "cancelled",
+            AuthError::Yggdrasil { .. } => "yggdrasil",
+            AuthError::Network { .. } => "network",
+            AuthError::Service { .. } => "service",
+            AuthError::Other(_) => "other",
+        }
+    }
+
+    /// Classifies an error coming out of the auth flow: an `AuthError` anywhere in the chain
+    /// wins, request failures become `Network`, and everything else is kept as `Other` with
+    /// the full context chain as its message.
+    pub fn from_anyhow(err: &anyhow::Error) -> Self {
+        if let Some(auth_error) = err
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<AuthError>())
+        {
+            return auth_error.clone();
+        }
+
+        for cause in err.chain() {
+            if let Some(request_error) = cause.downcast_ref::<reqwest::Error>() {
+                if request_error.is_connect()
+                    || request_error.is_timeout()
+                    || request_error.is_request()
+                {
+                    return AuthError::Network {
+                        service: request_error
+                            .url()
+                            .and_then(|url| url.host_str())
+                            .unwrap_or("the server")
+                            .to_string(),
+                        message: request_error.to_string(),
+                    };
+                }
+            }
+        }
+
+        AuthError::Other(format!("{:#}", err))
+    }
+
+    /// Classifies a failure of the sign-in step talking to `service`. Errors that are not
+    /// classified yet are attributed to that step rather than left as `Other`.
+    pub fn at(service: &str, err: anyhow::Error) -> anyhow::Error {
+        match AuthError::from_anyhow(&err) {
+            AuthError::Network { message, .. } => AuthError::Network {
+                service: service.to_string(),
+                message,
+            }
+            .into(),
+            AuthError::Other(message) => AuthError::Service {
+                service: service.to_string(),
+                message,
+            }
+            .into(),
+            _ => err,
+        }
+    }
+}
+
+impl From<anyhow::Error> for AuthError {
+    fn from(err: anyhow::Error) -> Self {
+        AuthError::from_anyhow(&err)
+    }
+}
+
+impl Serialize for AuthError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("AuthError", 2)?;
+        state.serialize_field("kind", self.kind())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.end()
+    }
+}
+
+/// The explanations Microsoft documents for the XSTS `XErr` codes.
+fn xbox_message(code: u64) -> String {
+    match code {
+        2148916227 => "this Xbox account was banned".to_string(),
+        2148916229 => {
+            "this account is not allowed to play online, a parent has to change the Xbox privacy settings"
+                .to_string()
+        }
+        2148916233 => {
+            "this Microsoft account has no Xbox profile, sign in at xbox.com once to create one"
+                .to_string()
+        }
+        2148916234 => "the Xbox terms of service have not been accepted yet".to_string(),
+        2148916235 => "Xbox Live is not available in this account's country or region".to_string(),
+        2148916236 | 2148916237 => {
+            "this account needs adult verification on xbox.com before it can sign in".to_string()
+        }
+        2148916238 => {
+            "this is a child account, it has to be added to a Microsoft family by an adult"
+                .to_string()
+        }
+        code => format!("Xbox Live refused to sign in (XErr {})", code),
+    }
+}
+
+fn profile_name_message(name: &str, status: &str) -> String {
+    match status {
+        "DUPLICATE" => format!("the name {} is already taken", name),
+        "NOT_ALLOWED" => format!(
+            "the name {} is not allowed, names are 3-16 letters, digits and underscores",
+            name
+        ),
+        status => format!("the name {} cannot be used ({})", name, status),
+    }
+}
+
+fn cooldown_message(next_change_at: &Option<DateTime<Utc>>) -> String {
+    match next_change_at {
+        Some(at) => format!(
+            "the profile name was changed recently, it can be changed again on {}",
+            at.format("%Y-%m-%d")
+        ),
+        None => "the profile name cannot be changed right now".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_server::block_on;
+    use anyhow::{anyhow, Context};
+
+    #[test]
+    fn kinds_tell_the_causes_apart() {
+        let cases = [
+            (
+                AuthError::OAuth {
+                    error: "invalid_grant".to_string(),
+                    message: "revoked".to_string(),
+                },
+                "oauth",
+            ),
+            (AuthError::Xbox { code: 2148916233 }, "no_xbox_account"),
+            (
+                AuthError::Xbox { code: 2148916235 },
+                "xbox_region_unavailable",
+            ),
+            (
+                AuthError::Xbox { code: 2148916236 },
+                "xbox_adult_verification",
+            ),
+            (
+                AuthError::Xbox { code: 2148916237 },
+                "xbox_adult_verification",
+            ),
+            (AuthError::Xbox { code: 2148916238 }, "xbox_child_account"),
+            (AuthError::Xbox { code: 2148916227 }, "xbox"),
+            (AuthError::NoEntitlement, "no_entitlement"),
+            (AuthError::NoProfile, "no_profile"),
+            (
+                AuthError::ProfileName {
+                    name: "Notch".to_string(),
+                    status: "DUPLICATE".to_string(),
+                },
+                "profile_name_taken",
+            ),
+            (
+                AuthError::ProfileName {
+                    name: "a b".to_string(),
+                    status: "NOT_ALLOWED".to_string(),
+                },
+                "profile_name_not_allowed",
+            ),
+            (
+                AuthError::NameChangeCooldown {
+                    next_change_at: None,
+                },
+                "name_change_cooldown",
+            ),
+            (AuthError::Cancelled, "cancelled"),
+            (
+                AuthError::Yggdrasil {
+                    error: "ForbiddenOperationException".to_string(),
+                    message: "Invalid credentials".to_string(),
+                },
+                "yggdrasil",
+            ),
+            (
+                AuthError::Network {
+                    service: "Xbox Live".to_string(),
+                    message: "connection refused".to_string(),
+                },
+                "network",
+            ),
+            (
+                AuthError::Service {
+                    service: "Minecraft login".to_string(),
+                    message: "503".to_string(),
+                },
+                "service",
+            ),
+            (AuthError::Other("unexpected".to_string()), "other"),
+        ];
+
+        for (error, kind) in cases {
+            assert_eq!(error.kind(), kind, "{:?}", error);
+        }
+    }
+
+    #[test]
+    fn serialises_as_kind_and_message() {
+        let value = serde_json::to_value(AuthError::Xbox { code: 2148916238 }).unwrap();
+
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "kind": "xbox_child_account",
+                "message": "this is a child account, it has to be added to a Microsoft family by an adult",
+            })
+        );
+    }
+
+    #[test]
+    fn classified_errors_are_found_anywhere_in_the_chain() {
+        let err = anyhow::Error::from(AuthError::NoEntitlement)
+            .context("failed to complete Microsoft authentication flow");
+
+        assert!(matches!(
+            AuthError::from_anyhow(&err),
+            AuthError::NoEntitlement
+        ));
+    }
+
+    #[test]
+    fn unclassified_errors_keep_their_context() {
+        let err = anyhow!("disk full").context("failed to persist authenticated account");
+
+        match AuthError::from_anyhow(&err) {
+            AuthError::Other(message) => {
+                assert_eq!(
+                    message,
+                    "failed to persist authenticated account: disk full"
+                )
+            }
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unreachable_servers_are_network_errors() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let err = block_on(reqwest::get(format!("http://127.0.0.1:{}/", port)))
+            .context("failed to request Xbox Live authentication")
+            .unwrap_err();
+
+        assert!(matches!(
+            AuthError::from_anyhow(&err),
+            AuthError::Network { service, .. } if service == "127.0.0.1"
+        ));
+        assert!(matches!(
+            AuthError::from_anyhow(&AuthError::at("Xbox Live", err)),
+            AuthError::Network { service, .. } if service == "Xbox Live"
+        ));
+    }
+
+    #[test]
+    fn failed_steps_are_attributed_to_their_service() {
+        let unexpected = AuthError::at("Minecraft login", anyhow!("returned 503"));
+        assert!(matches!(
+            AuthError::from_anyhow(&unexpected),
+            AuthError::Service { service, message }
+                if service == "Minecraft login" && message == "returned 503"
+        ));
+
+        let classified = AuthError::at("Xbox XSTS", AuthError::Xbox { code: 2148916233 }.into());
+        assert_eq!(
+            AuthError::from_anyhow(&classified).kind(),
+            "no_xbox_account"
+        );
+    }
+}
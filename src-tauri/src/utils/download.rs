@@ -0,0 +1,652 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, PoisonError, Weak};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use futures::stream::{self, StreamExt};
+use log::{debug, warn};
+use reqwest::{header, Client, StatusCode};
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+use tauri::{AppHandle, Emitter, Runtime};
+use tokio::io::AsyncWriteExt;
+
+const DEFAULT_CONCURRENCY: usize = 8;
+const DEFAULT_RETRIES: u32 = 3;
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+const PROGRESS_STEP_BYTES: u64 = 256 * 1024;
+
+/// One lock per destination shared by every `DownloadManager`, so two launches that need the
+/// same asset or library at once do not write into the same `.part` file.
+static PATH_LOCKS: LazyLock<Mutex<HashMap<PathBuf, Weak<tokio::sync::Mutex<()>>>>> =
+    LazyLock::new(Default::default);
+
+pub const TASK_PROGRESS_EVENT: &str = "download-task-progress";
+pub const PROGRESS_EVENT: &str = "download-progress";
+
+#[derive(Debug, Clone)]
+pub struct DownloadTask {
+    pub url: String,
+    pub path: PathBuf,
+    pub sha1: Option<String>,
+    pub size: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase", tag = "kind")]
+pub enum DownloadEvent {
+    /// Bytes received for a single file.
+    Task {
+        group: String,
+        url: String,
+        downloaded: u64,
+        total: Option<u64>,
+        finished: bool,
+    },
+    /// Totals across every task of a `download_all` call.
+    Progress {
+        group: String,
+        completed_tasks: usize,
+        total_tasks: usize,
+        downloaded_bytes: u64,
+        total_bytes: u64,
+    },
+}
+
+type Reporter = Arc<dyn Fn(DownloadEvent) + Send + Sync>;
+
+/// Downloads files with bounded concurrency, retrying failed transfers with exponential
+/// backoff. Files are written to `<path>.part`, resumed with a `Range` request when a partial
+/// file is left behind, verified against their SHA-1/size and then renamed into place.
+#[derive(Clone)]
+pub struct DownloadManager {
+    client: Client,
+    concurrency: usize,
+    retries: u32,
+    retry_delay: Duration,
+    reporter: Option<Reporter>,
+}
+
+struct GroupProgress {
+    group: String,
+    total_tasks: usize,
+    total_bytes: u64,
+    completed_tasks: AtomicUsize,
+    downloaded_bytes: AtomicU64,
+}
+
+impl DownloadManager {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            concurrency: DEFAULT_CONCURRENCY,
+            retries: DEFAULT_RETRIES,
+            retry_delay: RETRY_BASE_DELAY,
+            reporter: None,
+        }
+    }
+
+    pub fn with_reporter(
+        mut self,
+        reporter: impl Fn(DownloadEvent) + Send + Sync + 'static,
+    ) -> Self {
+        self.reporter = Some(Arc::new(reporter));
+        self
+    }
+
+    /// How many times a failed transfer is attempted again before giving up.
+    #[cfg(test)]
+    pub(crate) fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Downloads every task, reporting progress under `group`. All tasks are attempted even
+    /// if some fail, after which the first failure is returned.
+    pub async fn download_all(&self, group: &str, tasks: Vec<DownloadTask>) -> Result<()> {
+        // two transfers into the same `.part` file would corrupt each other, so a path that is
+        // listed twice (e.g. a library shared by a loader and the game) is fetched once
+        let mut paths = HashSet::new();
+        let tasks: Vec<DownloadTask> = tasks
+            .into_iter()
+            .filter(|task| paths.insert(task.path.clone()))
+            .collect();
+
+        let progress = Arc::new(GroupProgress {
+            group: group.to_string(),
+            total_tasks: tasks.len(),
+            total_bytes: tasks.iter().filter_map(|task| task.size).sum(),
+            completed_tasks: AtomicUsize::new(0),
+            downloaded_bytes: AtomicU64::new(0),
+        });
+
+        self.report_progress(&progress);
+
+        let failures: Vec<anyhow::Error> = stream::iter(tasks)
+            .map(|task| {
+                let progress = Arc::clone(&progress);
+                async move {
+                    let result = self.download_with_retries(&task, &progress).await;
+                    progress.completed_tasks.fetch_add(1, Ordering::SeqCst);
+                    self.report_progress(&progress);
+                    result.with_context(|| format!("failed to download {}", task.url))
+                }
+            })
+            .buffer_unordered(self.concurrency)
+            .filter_map(|result| async move { result.err() })
+            .collect()
+            .await;
+
+        match failures.into_iter().next() {
+            None => Ok(()),
+            Some(first) if progress.total_tasks == 1 => Err(first),
+            Some(first) => Err(first.context(format!("one or more of {} downloads failed", group))),
+        }
+    }
+
+    async fn download_with_retries(
+        &self,
+        task: &DownloadTask,
+        progress: &GroupProgress,
+    ) -> Result<()> {
+        // whoever held the lock before may have just finished this very file
+        let lock = path_lock(&task.path);
+        let _guard = lock.lock().await;
+
+        if is_valid_file(&task.path, task.sha1.as_deref(), task.size).await? {
+            progress
+                .downloaded_bytes
+                .fetch_add(task.size.unwrap_or(0), Ordering::SeqCst);
+            return Ok(());
+        }
+
+        let mut attempt = 0;
+        loop {
+            let before = progress.downloaded_bytes.load(Ordering::SeqCst);
+
+            match self.download_once(task, progress).await {
+                Ok(()) => return Ok(()),
+                Err(err) if attempt < self.retries && is_retryable(&err) => {
+                    attempt += 1;
+                    let delay = self.retry_delay * 2u32.pow(attempt - 1);
+                    warn!(
+                        "download of {} failed (attempt {}/{}), retrying in {:?}: {:#}",
+                        task.url,
+                        attempt,
+                        self.retries + 1,
+                        delay,
+                        err
+                    );
+
+                    let after = progress.downloaded_bytes.load(Ordering::SeqCst);
+                    progress
+                        .downloaded_bytes
+                        .fetch_sub(after.saturating_sub(before), Ordering::SeqCst);
+
+                    tokio::time::sleep(delay).await;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    async fn download_once(&self, task: &DownloadTask, progress: &GroupProgress) -> Result<()> {
+        if let Some(parent) = task.path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .with_context(|| format!("failed to create directory {:?}", parent))?;
+        }
+
+        let part_path = part_path(&task.path);
+        let mut existing = tokio::fs::metadata(&part_path)
+            .await
+            .map(|metadata| metadata.len())
+            .unwrap_or(0);
+
+        if task.size.is_some_and(|size| existing >= size) {
+            // a complete (or oversized) leftover cannot be resumed, start from scratch
+            existing = 0;
+        }
+
+        let mut request = self.client.get(&task.url);
+        if existing > 0 {
+            request = request.header(header::RANGE, format!("bytes={}-", existing));
+        }
+
+        let mut response = request
+            .send()
+            .await
+            .context("failed to send request")?
+            .error_for_status()
+            .context("server returned non-success status")?;
+
+        let resumed = existing > 0 && response.status() == StatusCode::PARTIAL_CONTENT;
+        let mut hasher = Sha1::new();
+        let mut downloaded = 0u64;
+
+        let mut file = if resumed {
+            debug!("resuming {} from byte {}", task.url, existing);
+            let prefix = hash_prefix(&part_path).await?;
+            hasher = prefix.0;
+            downloaded = prefix.1;
+            tokio::fs::OpenOptions::new()
+                .append(true)
+                .open(&part_path)
+                .await
+                .context("failed to open partial file")?
+        } else {
+            tokio::fs::File::create(&part_path)
+                .await
+                .context("failed to create partial file")?
+        };
+
+        progress
+            .downloaded_bytes
+            .fetch_add(downloaded, Ordering::SeqCst);
+
+        let total = task
+            .size
+            .or_else(|| response.content_length().map(|length| length + downloaded));
+        let mut last_reported = downloaded;
+
+        while let Some(chunk) = response.chunk().await.context("failed to read response")? {
+            file.write_all(&chunk)
+                .await
+                .context("failed to write partial file")?;
+            hasher.update(&chunk);
+
+            downloaded += chunk.len() as u64;
+            progress
+                .downloaded_bytes
+                .fetch_add(chunk.len() as u64, Ordering::SeqCst);
+
+            if downloaded - last_reported >= PROGRESS_STEP_BYTES {
+                last_reported = downloaded;
+                self.report_task(progress, task, downloaded, total, false);
+                self.report_progress(progress);
+            }
+        }
+
+        file.flush().await.context("failed to flush partial file")?;
+        drop(file);
+
+        if let Some(size) = task.size {
+            if downloaded != size {
+                let _ = tokio::fs::remove_file(&part_path).await;
+                return Err(anyhow!(
+                    "expected {} bytes but received {}",
+                    size,
+                    downloaded
+                ));
+            }
+        }
+
+        if let Some(expected) = &task.sha1 {
+            let actual = format!("{:x}", hasher.finalize());
+            if !actual.eq_ignore_ascii_case(expected) {
+                let _ = tokio::fs::remove_file(&part_path).await;
+                return Err(anyhow!(
+                    "sha1 mismatch (expected {}, got {})",
+                    expected,
+                    actual
+                ));
+            }
+        }
+
+        tokio::fs::rename(&part_path, &task.path)
+            .await
+            .with_context(|| format!("failed to move download into {:?}", task.path))?;
+
+        self.report_task(progress, task, downloaded, total, true);
+        Ok(())
+    }
+
+    fn report_task(
+        &self,
+        progress: &GroupProgress,
+        task: &DownloadTask,
+        downloaded: u64,
+        total: Option<u64>,
+        finished: bool,
+    ) {
+        if let Some(reporter) = &self.reporter {
+            reporter(DownloadEvent::Task {
+                group: progress.group.clone(),
+                url: task.url.clone(),
+                downloaded,
+                total,
+                finished,
+            });
+        }
+    }
+
+    fn report_progress(&self, progress: &GroupProgress) {
+        if let Some(reporter) = &self.reporter {
+            reporter(DownloadEvent::Progress {
+                group: progress.group.clone(),
+                completed_tasks: progress.completed_tasks.load(Ordering::SeqCst),
+                total_tasks: progress.total_tasks,
+                downloaded_bytes: progress.downloaded_bytes.load(Ordering::SeqCst),
+                total_bytes: progress.total_bytes,
+            });
+        }
+    }
+}
+
+/// Reporter that forwards download events to the frontend.
+pub fn emit_to<R: Runtime>(app: AppHandle<R>) -> impl Fn(DownloadEvent) + Send + Sync + 'static {
+    move |event| {
+        let name = match event {
+            DownloadEvent::Task { .. } => TASK_PROGRESS_EVENT,
+            DownloadEvent::Progress { .. } => PROGRESS_EVENT,
+        };
+
+        if let Err(err) = app.emit(name, event) {
+            warn!("failed to emit {} event: {}", name, err);
+        }
+    }
+}
+
+/// Checks whether `path` exists and matches the expected size and SHA-1, if given.
+pub async fn is_valid_file(path: &Path, sha1: Option<&str>, size: Option<u64>) -> Result<bool> {
+    let metadata = match tokio::fs::metadata(path).await {
+        Ok(metadata) => metadata,
+        Err(_) => return Ok(false),
+    };
+
+    if size.is_some_and(|size| metadata.len() != size) {
+        return Ok(false);
+    }
+
+    match sha1 {
+        Some(expected) => {
+            let actual = sha1_file(path.to_path_buf()).await?;
+            Ok(actual.eq_ignore_ascii_case(expected))
+        }
+        None => Ok(true),
+    }
+}
+
+pub async fn sha1_file(path: PathBuf) -> Result<String> {
+    tokio::task::spawn_blocking(move || {
+        let (hasher, _) = hash_file(&path)?;
+        Ok(format!("{:x}", hasher.finalize()))
+    })
+    .await
+    .context("hashing task panicked")?
+}
+
+async fn hash_prefix(path: &Path) -> Result<(Sha1, u64)> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || hash_file(&path))
+        .await
+        .context("hashing task panicked")?
+}
+
+fn hash_file(path: &Path) -> Result<(Sha1, u64)> {
+    let mut file =
+        fs::File::open(path).with_context(|| format!("failed to open {:?} for hashing", path))?;
+    let mut hasher = Sha1::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut total = 0u64;
+
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .with_context(|| format!("failed to read {:?}", path))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        total += read as u64;
+    }
+
+    Ok((hasher, total))
+}
+
+fn part_path(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+fn path_lock(path: &Path) -> Arc<tokio::sync::Mutex<()>> {
+    let mut locks = PATH_LOCKS.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(lock) = locks.get(path).and_then(Weak::upgrade) {
+        return lock;
+    }
+
+    locks.retain(|_, lock| lock.strong_count() > 0);
+    let lock = Arc::new(tokio::sync::Mutex::new(()));
+    locks.insert(path.to_path_buf(), Arc::downgrade(&lock));
+    lock
+}
+
+/// Client errors will not go away by asking again, except for timeouts and rate limits.
+fn is_retryable(err: &anyhow::Error) -> bool {
+    let status = err
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<reqwest::Error>())
+        .find_map(|err| err.status());
+
+    match status {
+        Some(status) if status.is_client_error() => matches!(
+            status,
+            StatusCode::REQUEST_TIMEOUT | StatusCode::TOO_MANY_REQUESTS
+        ),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_server::{block_on, Response, TestServer};
+    use std::sync::atomic::AtomicUsize;
+
+    const CONTENT: &[u8] = b"the quick brown fox jumps over the lazy dog";
+
+    fn manager() -> DownloadManager {
+        DownloadManager {
+            retry_delay: Duration::ZERO,
+            ..DownloadManager::new(Client::new())
+        }
+    }
+
+    fn sha1(bytes: &[u8]) -> String {
+        format!("{:x}", Sha1::digest(bytes))
+    }
+
+    fn task(server: &TestServer, path: PathBuf) -> DownloadTask {
+        DownloadTask {
+            url: format!("{}/file", server.url()),
+            path,
+            sha1: Some(sha1(CONTENT)),
+            size: Some(CONTENT.len() as u64),
+        }
+    }
+
+    /// Serves `CONTENT`, honouring `Range: bytes=<start>-`.
+    fn content_server() -> TestServer {
+        TestServer::start(|request| {
+            let start = request
+                .header("range")
+                .and_then(|range| range.strip_prefix("bytes="))
+                .and_then(|range| range.strip_suffix('-'))
+                .and_then(|start| start.parse::<usize>().ok());
+
+            match start {
+                Some(start) => Response::new(206, &CONTENT[start..]),
+                None => Response::ok(CONTENT),
+            }
+        })
+    }
+
+    #[test]
+    fn resumes_partial_file_with_range_request() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = content_server();
+        let path = dir.path().join("file.bin");
+        fs::write(part_path(&path), &CONTENT[..10]).unwrap();
+
+        block_on(manager().download_all("test", vec![task(&server, path.clone())])).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), CONTENT);
+        assert!(!part_path(&path).exists());
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].header("range"), Some("bytes=10-"));
+    }
+
+    #[test]
+    fn restarts_when_server_ignores_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = TestServer::start(|_| Response::ok(CONTENT));
+        let path = dir.path().join("file.bin");
+        fs::write(part_path(&path), b"garbage").unwrap();
+
+        block_on(manager().download_all("test", vec![task(&server, path.clone())])).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), CONTENT);
+    }
+
+    #[test]
+    fn retries_after_sha1_mismatch() {
+        let dir = tempfile::tempdir().unwrap();
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counter = attempts.clone();
+        let server = TestServer::start(move |_| {
+            if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                Response::ok(CONTENT.to_ascii_uppercase())
+            } else {
+                Response::ok(CONTENT)
+            }
+        });
+        let path = dir.path().join("file.bin");
+
+        block_on(manager().download_all("test", vec![task(&server, path.clone())])).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), CONTENT);
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn gives_up_after_repeated_sha1_mismatch() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = TestServer::start(|_| Response::ok(CONTENT.to_ascii_uppercase()));
+        let path = dir.path().join("file.bin");
+
+        let err = block_on(manager().download_all("test", vec![task(&server, path.clone())]))
+            .unwrap_err();
+
+        assert!(format!("{:#}", err).contains("sha1 mismatch"));
+        assert_eq!(server.requests().len(), DEFAULT_RETRIES as usize + 1);
+        assert!(!path.exists());
+        assert!(!part_path(&path).exists());
+    }
+
+    #[test]
+    fn rejects_wrong_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = TestServer::start(|_| Response::ok(&CONTENT[..20]));
+        let path = dir.path().join("file.bin");
+        let task = DownloadTask {
+            sha1: None,
+            ..task(&server, path.clone())
+        };
+
+        let manager = DownloadManager {
+            retries: 0,
+            ..manager()
+        };
+        let err = block_on(manager.download_all("test", vec![task])).unwrap_err();
+
+        assert!(format!("{:#}", err)
+            .contains(&format!("expected {} bytes but received 20", CONTENT.len())));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn skips_valid_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = content_server();
+        let path = dir.path().join("file.bin");
+        fs::write(&path, CONTENT).unwrap();
+
+        block_on(manager().download_all("test", vec![task(&server, path)])).unwrap();
+
+        assert!(server.requests().is_empty());
+    }
+
+    #[test]
+    fn downloads_each_path_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = content_server();
+        let path = dir.path().join("nested/file.bin");
+        let tasks = vec![
+            task(&server, path.clone()),
+            task(&server, path.clone()),
+            task(&server, path.clone()),
+        ];
+
+        block_on(manager().download_all("test", tasks)).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), CONTENT);
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn client_errors_are_not_retried() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = TestServer::start(|_| Response::status(404));
+
+        let result = block_on(
+            manager().download_all("test", vec![task(&server, dir.path().join("file.bin"))]),
+        );
+
+        assert!(result.is_err());
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn rate_limits_are_retried() {
+        let dir = tempfile::tempdir().unwrap();
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counter = attempts.clone();
+        let server = TestServer::start(move |_| {
+            if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                Response::status(429)
+            } else {
+                Response::ok(CONTENT)
+            }
+        });
+        let path = dir.path().join("file.bin");
+
+        block_on(manager().download_all("test", vec![task(&server, path.clone())])).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), CONTENT);
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn concurrent_downloads_of_one_path_fetch_it_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = content_server();
+        let path = dir.path().join("file.bin");
+        let (first, second) = (manager(), manager());
+
+        let (first, second) = block_on(futures::future::join(
+            first.download_all("first", vec![task(&server, path.clone())]),
+            second.download_all("second", vec![task(&server, path.clone())]),
+        ));
+
+        first.unwrap();
+        second.unwrap();
+        assert_eq!(fs::read(&path).unwrap(), CONTENT);
+        assert_eq!(server.requests().len(), 1);
+    }
+}
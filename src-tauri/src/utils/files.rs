@@ -0,0 +1,41 @@
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+
+/// Writes to a uniquely named file next to `path` first, so a crash never leaves a truncated
+/// file behind and two writers of the same file never share a temporary file.
+pub fn write_replacing(path: &Path, contents: &[u8]) -> Result<()> {
+    let dir = path
+        .parent()
+        .ok_or_else(|| anyhow!("{:?} has no parent directory", path))?;
+    let mut file = tempfile::NamedTempFile::new_in(dir)
+        .with_context(|| format!("failed to create a temporary file in {:?}", dir))?;
+    file.write_all(contents)
+        .with_context(|| format!("failed to write {:?}", file.path()))?;
+    file.persist(path)
+        .with_context(|| format!("failed to replace {:?}", path))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn replacing_a_file_leaves_no_partial_behind() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+        fs::write(&path, "{\"old\":true}").unwrap();
+
+        write_replacing(&path, b"{}").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{}");
+        let names: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["profiles.json"]);
+    }
+}
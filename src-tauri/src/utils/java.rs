@@ -0,0 +1,455 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{anyhow, Context, Result};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, Runtime};
+
+#[cfg(windows)]
+pub const JAVA_BINARY: &str = "javaw.exe";
+#[cfg(not(windows))]
+pub const JAVA_BINARY: &str = "java";
+
+/// How long `java -version` may take before the candidate is given up on.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct JavaRuntime {
+    pub path: PathBuf,
+    pub version: String,
+    pub major_version: u32,
+    pub vendor: Option<String>,
+    pub arch: Option<String>,
+    /// Whether the runtime lives in a launcher-managed directory.
+    pub managed: bool,
+}
+
+/// Probe results keyed by executable path, invalidated when the executable changes.
+#[derive(Default)]
+pub struct JavaCache(Mutex<HashMap<PathBuf, (Option<SystemTime>, JavaRuntime)>>);
+
+/// Directory holding the runtimes the launcher downloads itself.
+pub fn runtimes_dir<R: Runtime>(app: &AppHandle<R>) -> Result<PathBuf> {
+    let data_dir = app
+        .path()
+        .app_data_dir()
+        .context("failed to resolve app data directory")?;
+
+    Ok(data_dir.join("runtimes"))
+}
+
+/// Finds Java installations from `JAVA_HOME`, `PATH`, the usual system locations and the
+/// launcher-managed runtime directory. Executables that fail to probe are skipped.
+pub fn discover<R: Runtime>(app: &AppHandle<R>, refresh: bool) -> Result<Vec<JavaRuntime>> {
+    let managed_dir = runtimes_dir(app)?;
+    let cache = app.state::<JavaCache>();
+
+    if refresh {
+        if let Ok(mut guard) = cache.0.lock() {
+            guard.clear();
+        }
+    }
+
+    let mut seen = HashSet::new();
+    let mut runtimes = Vec::new();
+
+    for candidate in candidates(&managed_dir) {
+        let canonical = fs::canonicalize(&candidate).unwrap_or(candidate);
+        if !seen.insert(canonical.clone()) {
+            continue;
+        }
+
+        match probe_cached(&cache, &canonical) {
+            Ok(mut runtime) => {
+                runtime.managed = canonical.starts_with(&managed_dir);
+                runtimes.push(runtime);
+            }
+            Err(err) => debug!("skipping java candidate {:?}: {:#}", canonical, err),
+        }
+    }
+
+    runtimes.sort_by_key(|runtime| std::cmp::Reverse(runtime.major_version));
+    Ok(runtimes)
+}
+
+/// Picks the Java executable for a version requiring `major_version`: a discovered runtime
+/// of exactly that version if there is one, otherwise `JAVA_HOME` or whatever is on `PATH`.
+pub fn select_executable<R: Runtime>(
+    app: &AppHandle<R>,
+    major_version: Option<u32>,
+) -> Result<PathBuf> {
+    if let Some(major_version) = major_version {
+        let runtimes = discover(app, false)?;
+        if let Some(runtime) = runtimes
+            .iter()
+            .filter(|runtime| runtime.major_version == major_version)
+            .max_by_key(|runtime| runtime.managed)
+        {
+            return Ok(runtime.path.clone());
+        }
+
+        warn!(
+            "no Java {} runtime found, falling back to the default java",
+            major_version
+        );
+    }
+
+    Ok(env::var_os("JAVA_HOME")
+        .map(|home| PathBuf::from(home).join("bin").join(JAVA_BINARY))
+        .filter(|path| path.is_file())
+        .unwrap_or_else(|| PathBuf::from(JAVA_BINARY)))
+}
+
+/// Probes the executable at `path`, which may also point at a Java home directory.
+pub fn validate<R: Runtime>(app: &AppHandle<R>, path: &Path) -> Result<JavaRuntime> {
+    let executable = if path.is_dir() {
+        let in_home = path.join("bin").join(JAVA_BINARY);
+        if !in_home.is_file() {
+            return Err(anyhow!("no {} found in {:?}", JAVA_BINARY, path));
+        }
+        in_home
+    } else {
+        path.to_path_buf()
+    };
+
+    let canonical = fs::canonicalize(&executable)
+        .with_context(|| format!("java executable {:?} does not exist", executable))?;
+    let mut runtime = probe_cached(&app.state::<JavaCache>(), &canonical)?;
+    runtime.managed = canonical.starts_with(runtimes_dir(app)?);
+    Ok(runtime)
+}
+
+/// Runs `java -XshowSettings:properties -version` and reads the reported properties.
+pub fn probe(executable: &Path) -> Result<JavaRuntime> {
+    probe_with_timeout(executable, PROBE_TIMEOUT)
+}
+
+fn probe_with_timeout(executable: &Path, timeout: Duration) -> Result<JavaRuntime> {
+    // the settings dump goes to stderr, but be lenient about where it ends up
+    let text = run_with_timeout(
+        Command::new(executable).args(["-XshowSettings:properties", "-version"]),
+        timeout,
+    )
+    .with_context(|| format!("failed to run {:?}", executable))?;
+
+    let properties = parse_properties(&text);
+    let version = properties
+        .get("java.version")
+        .cloned()
+        .ok_or_else(|| anyhow!("{:?} did not report java.version", executable))?;
+    let major_version = parse_major_version(&version)
+        .ok_or_else(|| anyhow!("unrecognised java version '{}'", version))?;
+
+    Ok(JavaRuntime {
+        path: executable.to_path_buf(),
+        version,
+        major_version,
+        vendor: properties.get("java.vendor").cloned(),
+        arch: properties.get("os.arch").cloned(),
+        managed: false,
+    })
+}
+
+/// Turns `1.8.0_392`, `17.0.9` or `21` into the major version.
+pub fn parse_major_version(version: &str) -> Option<u32> {
+    let mut parts = version.split(['.', '_', '-', '+']);
+    let first: u32 = parts.next()?.parse().ok()?;
+
+    if first == 1 {
+        parts.next()?.parse().ok()
+    } else {
+        Some(first)
+    }
+}
+
+/// Runs `command` and returns its stderr followed by its stdout, killing it once `timeout`
+/// has passed so a hung executable cannot stall discovery.
+fn run_with_timeout(command: &mut Command, timeout: Duration) -> Result<String> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // drained on their own threads so a chatty child cannot block on a full pipe
+    let stdout = child.stdout.take().map(read_to_end);
+    let stderr = child.stderr.take().map(read_to_end);
+
+    let deadline = Instant::now() + timeout;
+    while child.try_wait()?.is_none() {
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(anyhow!("timed out after {:?}", timeout));
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    let mut text = String::new();
+    for output in [stderr, stdout].into_iter().flatten() {
+        let bytes = output.join().unwrap_or_default();
+        text.push_str(&String::from_utf8_lossy(&bytes));
+    }
+    Ok(text)
+}
+
+fn read_to_end(mut pipe: impl Read + Send + 'static) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        let _ = pipe.read_to_end(&mut bytes);
+        bytes
+    })
+}
+
+fn probe_cached(cache: &JavaCache, executable: &Path) -> Result<JavaRuntime> {
+    let modified = fs::metadata(executable)
+        .and_then(|metadata| metadata.modified())
+        .ok();
+
+    if let Ok(guard) = cache.0.lock() {
+        if let Some((cached_modified, runtime)) = guard.get(executable) {
+            if *cached_modified == modified {
+                return Ok(runtime.clone());
+            }
+        }
+    }
+
+    let runtime = probe(executable)?;
+
+    match cache.0.lock() {
+        Ok(mut guard) => {
+            guard.insert(executable.to_path_buf(), (modified, runtime.clone()));
+        }
+        Err(_) => warn!("java runtime cache lock poisoned"),
+    }
+
+    Ok(runtime)
+}
+
+fn parse_properties(text: &str) -> HashMap<String, String> {
+    text.lines()
+        .filter_map(|line| line.trim().split_once(" = "))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect()
+}
+
+fn candidates(managed_dir: &Path) -> Vec<PathBuf> {
+    let mut candidates = Vec::new();
+
+    if let Some(home) = env::var_os("JAVA_HOME") {
+        candidates.push(PathBuf::from(home).join("bin").join(JAVA_BINARY));
+    }
+
+    if let Some(path) = env::var_os("PATH") {
+        for dir in env::split_paths(&path) {
+            candidates.push(dir.join(JAVA_BINARY));
+        }
+    }
+
+    for root in system_java_roots() {
+        candidates.extend(java_homes_in(&root));
+    }
+
+    candidates.extend(java_homes_in(managed_dir));
+
+    candidates
+        .into_iter()
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+fn system_java_roots() -> Vec<PathBuf> {
+    let mut roots = Vec::new();
+
+    if cfg!(target_os = "linux") {
+        roots.push(PathBuf::from("/usr/lib/jvm"));
+        roots.push(PathBuf::from("/usr/lib64/jvm"));
+        roots.push(PathBuf::from("/usr/java"));
+        roots.push(PathBuf::from("/opt/java"));
+        roots.push(PathBuf::from("/opt/jdk"));
+        if let Some(home) = env::var_os("HOME") {
+            roots.push(PathBuf::from(&home).join(".sdkman/candidates/java"));
+            roots.push(PathBuf::from(&home).join(".jdks"));
+        }
+    } else if cfg!(target_os = "macos") {
+        roots.push(PathBuf::from("/Library/Java/JavaVirtualMachines"));
+    } else if cfg!(windows) {
+        for var in ["ProgramFiles", "ProgramFiles(x86)"] {
+            if let Some(dir) = env::var_os(var) {
+                let dir = PathBuf::from(dir);
+                roots.push(dir.join("Java"));
+                roots.push(dir.join("Eclipse Adoptium"));
+                roots.push(dir.join("Microsoft"));
+                roots.push(dir.join("Zulu"));
+            }
+        }
+    }
+
+    roots
+}
+
+/// Executables of the Java homes directly below `root`, searching one extra level for the
+/// nested layouts of managed runtimes and macOS bundles.
+fn java_homes_in(root: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(root) else {
+        return Vec::new();
+    };
+
+    let mut found = Vec::new();
+    for entry in entries.flatten() {
+        let home = entry.path();
+        for candidate in [
+            home.join("bin").join(JAVA_BINARY),
+            home.join("Contents/Home/bin").join(JAVA_BINARY),
+            home.join("jre.bundle/Contents/Home/bin").join(JAVA_BINARY),
+        ] {
+            if candidate.is_file() {
+                found.push(candidate);
+            }
+        }
+
+        if let Ok(children) = fs::read_dir(&home) {
+            for child in children.flatten() {
+                let candidate = child.path().join("bin").join(JAVA_BINARY);
+                if candidate.is_file() {
+                    found.push(candidate);
+                }
+            }
+        }
+    }
+
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETTINGS: &str = "Property settings:
+    file.encoding = UTF-8
+    java.home = /usr/lib/jvm/temurin-17
+    java.vendor = Eclipse Adoptium
+    java.version = 17.0.9
+    os.arch = amd64
+    sun.java.launcher = SUN_STANDARD
+
+openjdk version \"17.0.9\" 2023-10-17
+";
+
+    #[test]
+    fn major_versions_are_parsed_from_both_schemes() {
+        for (version, major) in [
+            ("1.8.0_392", Some(8)),
+            ("1.7.0", Some(7)),
+            ("17.0.9", Some(17)),
+            ("21", Some(21)),
+            ("22-ea", Some(22)),
+            ("11.0.2+9", Some(11)),
+            ("", None),
+            ("1", None),
+            ("openjdk", None),
+        ] {
+            assert_eq!(parse_major_version(version), major, "{}", version);
+        }
+    }
+
+    #[test]
+    fn settings_are_read_as_properties() {
+        let properties = parse_properties(SETTINGS);
+
+        assert_eq!(properties["java.version"], "17.0.9");
+        assert_eq!(properties["java.vendor"], "Eclipse Adoptium");
+        assert_eq!(properties["os.arch"], "amd64");
+        assert!(!properties.contains_key("openjdk version"));
+    }
+
+    /// A stand-in `java` executable running the shell `body`.
+    #[cfg(unix)]
+    fn fake_java(dir: &Path, body: &str) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = dir.join("java");
+        fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    /// Prints `SETTINGS` to stderr and counts its runs in `calls`.
+    #[cfg(unix)]
+    fn settings_script(dir: &Path) -> PathBuf {
+        let calls = dir.join("calls");
+        fake_java(
+            dir,
+            &format!(
+                "echo run >> '{}'\nprintf '%s' \"{}\" >&2",
+                calls.display(),
+                SETTINGS
+            ),
+        )
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn probing_reads_the_reported_runtime() {
+        let dir = tempfile::tempdir().unwrap();
+        let java = settings_script(dir.path());
+
+        let runtime = probe(&java).unwrap();
+
+        assert_eq!(runtime.version, "17.0.9");
+        assert_eq!(runtime.major_version, 17);
+        assert_eq!(runtime.vendor.as_deref(), Some("Eclipse Adoptium"));
+        assert_eq!(runtime.arch.as_deref(), Some("amd64"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hung_executables_are_killed() {
+        let dir = tempfile::tempdir().unwrap();
+        let java = fake_java(dir.path(), "sleep 30");
+
+        let started = Instant::now();
+        let err = probe_with_timeout(&java, Duration::from_millis(200)).unwrap_err();
+
+        assert!(format!("{:#}", err).contains("timed out"));
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn cached_probes_are_reused_until_the_executable_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let java = settings_script(dir.path());
+        let calls = || {
+            fs::read_to_string(dir.path().join("calls"))
+                .unwrap()
+                .lines()
+                .count()
+        };
+        let cache = JavaCache::default();
+
+        probe_cached(&cache, &java).unwrap();
+        probe_cached(&cache, &java).unwrap();
+        assert_eq!(calls(), 1);
+
+        let modified = fs::metadata(&java).unwrap().modified().unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&java)
+            .unwrap()
+            .set_modified(modified + Duration::from_secs(60))
+            .unwrap();
+
+        probe_cached(&cache, &java).unwrap();
+        assert_eq!(calls(), 2);
+    }
+}
@@ -0,0 +1,623 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use log::{debug, info, warn};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+use crate::utils::download::{DownloadManager, DownloadTask};
+use crate::utils::env_url;
+use crate::utils::java::JAVA_BINARY;
+use crate::utils::version_json::{Download, JavaVersion};
+
+pub const LAUNCHER_META_URL: &str = "https://launchermeta.mojang.com";
+
+const RUNTIME_INDEX_PATH: &str =
+    "v1/products/java-runtime/2ec0cc96c44e5a76b9c8b7c39df7210883d12871/all.json";
+const VERSION_MARKER: &str = ".version";
+
+/// Where the java-runtime index is fetched from. Defaults to Mojang's launchermeta host,
+/// overridable through `DECENT_JAVA_RUNTIME_URL`.
+#[derive(Debug, Clone)]
+pub struct RuntimeSource {
+    base_url: String,
+}
+
+impl RuntimeSource {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(env_url("DECENT_JAVA_RUNTIME_URL", LAUNCHER_META_URL))
+    }
+
+    pub fn index_url(&self) -> String {
+        format!("{}/{}", self.base_url, RUNTIME_INDEX_PATH)
+    }
+}
+
+/// `all.json`: platform -> component -> available builds.
+type RuntimeIndex = HashMap<String, HashMap<String, Vec<RuntimeEntry>>>;
+
+#[derive(Debug, Deserialize, Clone)]
+struct RuntimeEntry {
+    manifest: Download,
+    version: RuntimeVersion,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct RuntimeVersion {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RuntimeManifest {
+    files: HashMap<String, RuntimeFile>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum RuntimeFile {
+    File {
+        #[serde(default)]
+        executable: bool,
+        downloads: RuntimeFileDownloads,
+    },
+    Directory,
+    Link {
+        target: String,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+struct RuntimeFileDownloads {
+    raw: Download,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct InstalledMarker {
+    version: String,
+    manifest_sha1: String,
+}
+
+/// Makes sure the runtime `java.component` is installed under `runtimes_dir` and returns its
+/// Java executable. An already installed runtime is reused while it matches the build
+/// currently published in the index.
+pub async fn ensure_runtime(
+    downloads: &DownloadManager,
+    client: &Client,
+    source: &RuntimeSource,
+    runtimes_dir: &Path,
+    java: &JavaVersion,
+) -> Result<PathBuf> {
+    let platform = platform_key()
+        .ok_or_else(|| anyhow!("managed Java runtimes are not available for this platform"))?;
+    let install_dir = runtimes_dir.join(&java.component);
+    let marker_path = install_dir.join(VERSION_MARKER);
+
+    let executable = java_executable(&install_dir);
+    let installed = fs::read_to_string(&marker_path)
+        .ok()
+        .and_then(|contents| serde_json::from_str::<InstalledMarker>(&contents).ok());
+
+    let index: RuntimeIndex = match fetch_json(client, &source.index_url()).await {
+        Ok(index) => index,
+        Err(err) if installed.is_some() && executable.is_file() => {
+            warn!(
+                "failed to check for java runtime updates, using installed {}: {:#}",
+                java.component, err
+            );
+            return Ok(executable);
+        }
+        Err(err) => return Err(err.context("failed to fetch java runtime index")),
+    };
+
+    let entry = index
+        .get(platform)
+        .and_then(|components| components.get(&java.component))
+        .and_then(|entries| entries.first())
+        .cloned()
+        .ok_or_else(|| {
+            anyhow!(
+                "java runtime {} is not published for {}",
+                java.component,
+                platform
+            )
+        })?;
+
+    let marker = InstalledMarker {
+        version: entry.version.name.clone(),
+        manifest_sha1: entry.manifest.sha1.clone(),
+    };
+
+    if installed.as_ref() == Some(&marker) && executable.is_file() {
+        debug!(
+            "java runtime {} {} already installed",
+            java.component, marker.version
+        );
+        return Ok(executable);
+    }
+
+    info!(
+        "installing java runtime {} {} into {:?}",
+        java.component, marker.version, install_dir
+    );
+
+    let manifest_bytes = fetch_bytes(client, &entry.manifest.url).await?;
+    let digest = format!("{:x}", Sha1::digest(&manifest_bytes));
+    if !digest.eq_ignore_ascii_case(&entry.manifest.sha1) {
+        return Err(anyhow!(
+            "java runtime manifest failed verification (expected sha1 {}, got {})",
+            entry.manifest.sha1,
+            digest
+        ));
+    }
+
+    let manifest: RuntimeManifest =
+        serde_json::from_slice(&manifest_bytes).context("failed to parse java runtime manifest")?;
+
+    // the marker only comes back once the new build is complete, so an install that fails
+    // halfway is never mistaken for a usable runtime
+    match fs::remove_file(&marker_path) {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err).context("failed to remove runtime marker"),
+    }
+
+    if installed.is_some() {
+        remove_stale_files(&install_dir, &install_dir, &manifest)
+            .context("failed to remove files of the previous java runtime")?;
+    }
+
+    fs::create_dir_all(&install_dir)
+        .with_context(|| format!("failed to create directory {:?}", install_dir))?;
+
+    let mut tasks = Vec::new();
+    let mut executables = Vec::new();
+    let mut links = Vec::new();
+
+    for (name, file) in &manifest.files {
+        let path = install_dir.join(name);
+        match file {
+            RuntimeFile::Directory => {
+                fs::create_dir_all(&path)
+                    .with_context(|| format!("failed to create directory {:?}", path))?;
+            }
+            RuntimeFile::File {
+                executable,
+                downloads,
+            } => {
+                if *executable {
+                    executables.push(path.clone());
+                }
+                tasks.push(DownloadTask {
+                    url: downloads.raw.url.clone(),
+                    path,
+                    sha1: Some(downloads.raw.sha1.clone()),
+                    size: Some(downloads.raw.size),
+                });
+            }
+            RuntimeFile::Link { target } => links.push((path, PathBuf::from(target))),
+        }
+    }
+
+    downloads
+        .download_all(&format!("java-runtime:{}", java.component), tasks)
+        .await
+        .context("failed to download java runtime files")?;
+
+    for path in executables {
+        mark_executable(&path)?;
+    }
+
+    for (path, target) in links {
+        create_link(&path, &target)?;
+    }
+
+    if !executable.is_file() {
+        return Err(anyhow!(
+            "java runtime {} does not contain {:?}",
+            java.component,
+            executable
+        ));
+    }
+
+    fs::write(
+        &marker_path,
+        serde_json::to_string(&marker).context("failed to serialize runtime marker")?,
+    )
+    .context("failed to write runtime marker")?;
+
+    Ok(executable)
+}
+
+/// The key Mojang's runtime index uses for the current platform.
+pub fn platform_key() -> Option<&'static str> {
+    match (env::consts::OS, env::consts::ARCH) {
+        ("linux", "x86_64") => Some("linux"),
+        ("linux", "x86") => Some("linux-i386"),
+        ("macos", "x86_64") => Some("mac-os"),
+        ("macos", "aarch64") => Some("mac-os-arm64"),
+        ("windows", "x86_64") => Some("windows-x64"),
+        ("windows", "x86") => Some("windows-x86"),
+        ("windows", "aarch64") => Some("windows-arm64"),
+        _ => None,
+    }
+}
+
+fn java_executable(install_dir: &Path) -> PathBuf {
+    if cfg!(target_os = "macos") {
+        install_dir
+            .join("jre.bundle/Contents/Home/bin")
+            .join(JAVA_BINARY)
+    } else {
+        install_dir.join("bin").join(JAVA_BINARY)
+    }
+}
+
+/// Removes whatever a previously installed build left under `dir` that `manifest` does not
+/// list, or lists as a different kind of entry.
+fn remove_stale_files(install_dir: &Path, dir: &Path, manifest: &RuntimeManifest) -> Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err).with_context(|| format!("failed to read {:?}", dir)),
+    };
+
+    for entry in entries {
+        let entry = entry.with_context(|| format!("failed to read {:?}", dir))?;
+        let path = entry.path();
+        let name = path
+            .strip_prefix(install_dir)?
+            .to_string_lossy()
+            .replace('\\', "/");
+        if name == VERSION_MARKER {
+            continue;
+        }
+
+        let is_dir = entry
+            .file_type()
+            .with_context(|| format!("failed to read {:?}", path))?
+            .is_dir();
+
+        match (manifest.files.get(&name), is_dir) {
+            (Some(RuntimeFile::Directory), true) => {
+                remove_stale_files(install_dir, &path, manifest)?;
+            }
+            (Some(RuntimeFile::File { .. } | RuntimeFile::Link { .. }), false) => {}
+            (_, true) => {
+                debug!("removing stale java runtime directory {:?}", path);
+                fs::remove_dir_all(&path)
+                    .with_context(|| format!("failed to remove {:?}", path))?;
+            }
+            (_, false) => {
+                debug!("removing stale java runtime file {:?}", path);
+                fs::remove_file(&path).with_context(|| format!("failed to remove {:?}", path))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn fetch_bytes(client: &Client, url: &str) -> Result<Vec<u8>> {
+    let bytes = client
+        .get(url)
+        .send()
+        .await
+        .with_context(|| format!("failed to request {}", url))?
+        .error_for_status()
+        .with_context(|| format!("{} returned non-success status", url))?
+        .bytes()
+        .await
+        .with_context(|| format!("failed to read response from {}", url))?;
+
+    Ok(bytes.to_vec())
+}
+
+async fn fetch_json<T: serde::de::DeserializeOwned>(client: &Client, url: &str) -> Result<T> {
+    let bytes = fetch_bytes(client, url).await?;
+    serde_json::from_slice(&bytes).with_context(|| format!("failed to parse response from {}", url))
+}
+
+#[cfg(unix)]
+fn mark_executable(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut permissions = fs::metadata(path)
+        .with_context(|| format!("failed to read permissions of {:?}", path))?
+        .permissions();
+    permissions.set_mode(permissions.mode() | 0o755);
+    fs::set_permissions(path, permissions)
+        .with_context(|| format!("failed to mark {:?} executable", path))
+}
+
+#[cfg(not(unix))]
+fn mark_executable(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn create_link(path: &Path, target: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    if fs::symlink_metadata(path).is_ok() {
+        fs::remove_file(path).with_context(|| format!("failed to replace link {:?}", path))?;
+    }
+
+    std::os::unix::fs::symlink(target, path)
+        .with_context(|| format!("failed to link {:?} to {:?}", path, target))
+}
+
+#[cfg(not(unix))]
+fn create_link(path: &Path, target: &Path) -> Result<()> {
+    // runtime links only occur in the unix builds; copy as a fallback just in case
+    let source = path.parent().map(|parent| parent.join(target));
+    match source {
+        Some(source) if source.is_file() => {
+            fs::copy(&source, path)
+                .with_context(|| format!("failed to copy {:?} to {:?}", source, path))?;
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_server::{block_on, Response, TestServer};
+    use std::sync::{Arc, Mutex};
+
+    const COMPONENT: &str = "java-runtime-delta";
+
+    fn requirement() -> JavaVersion {
+        JavaVersion {
+            component: COMPONENT.to_string(),
+            major_version: 21,
+        }
+    }
+
+    fn java_path() -> String {
+        java_executable(Path::new(""))
+            .to_string_lossy()
+            .replace('\\', "/")
+    }
+
+    fn sha1(bytes: &[u8]) -> String {
+        format!("{:x}", Sha1::digest(bytes))
+    }
+
+    type Routes = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+    /// Publishes build `version` of the runtime, consisting of the java executable, the
+    /// directory holding it, `files` and `links` (name to target). The returned routes map
+    /// paths to response bodies.
+    fn runtime_server(
+        version: &str,
+        files: &[(&str, &str)],
+        links: &[(&str, &str)],
+    ) -> (TestServer, Routes) {
+        let routes: Routes = Default::default();
+        let served = routes.clone();
+        let server =
+            TestServer::start(
+                move |request| match served.lock().unwrap().get(&request.path) {
+                    Some(body) => Response::ok(body.clone()),
+                    None => Response::status(404),
+                },
+            );
+
+        let mut published = routes.lock().unwrap();
+        let java = java_path();
+        let mut manifest = serde_json::Map::new();
+        let (java_dir, _) = java.rsplit_once('/').unwrap();
+        manifest.insert(
+            java_dir.to_string(),
+            serde_json::json!({ "type": "directory" }),
+        );
+
+        let contents = [(java.as_str(), "#!/bin/sh\n")]
+            .into_iter()
+            .chain(files.iter().copied());
+        for (name, content) in contents {
+            let path = format!("/files/{}/{}", version, name);
+            manifest.insert(
+                name.to_string(),
+                serde_json::json!({
+                    "type": "file",
+                    "executable": name == java,
+                    "downloads": { "raw": {
+                        "sha1": sha1(content.as_bytes()),
+                        "size": content.len(),
+                        "url": format!("{}{}", server.url(), path),
+                    }}
+                }),
+            );
+            published.insert(path, content.as_bytes().to_vec());
+        }
+        for (name, target) in links {
+            manifest.insert(
+                name.to_string(),
+                serde_json::json!({ "type": "link", "target": target }),
+            );
+        }
+
+        let manifest = serde_json::json!({ "files": manifest }).to_string();
+        let manifest_path = format!("/manifests/{}.json", version);
+        let index = serde_json::json!({
+            platform_key().unwrap(): {
+                COMPONENT: [{
+                    "manifest": {
+                        "sha1": sha1(manifest.as_bytes()),
+                        "size": manifest.len(),
+                        "url": format!("{}{}", server.url(), manifest_path),
+                    },
+                    "version": { "name": version }
+                }]
+            }
+        });
+        published.insert(manifest_path, manifest.into_bytes());
+        published.insert(
+            format!("/{}", RUNTIME_INDEX_PATH),
+            index.to_string().into_bytes(),
+        );
+        drop(published);
+
+        (server, routes)
+    }
+
+    fn ensure(server_url: &str, runtimes_dir: &Path) -> Result<PathBuf> {
+        let client = Client::new();
+        block_on(ensure_runtime(
+            &DownloadManager::new(client.clone()),
+            &client,
+            &RuntimeSource::new(server_url),
+            runtimes_dir,
+            &requirement(),
+        ))
+    }
+
+    fn marker(runtimes_dir: &Path) -> InstalledMarker {
+        let path = runtimes_dir.join(COMPONENT).join(VERSION_MARKER);
+        serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn installs_runtime_and_writes_marker() {
+        let dir = tempfile::tempdir().unwrap();
+        let (server, _) = runtime_server("21.0.3", &[("lib/modules", "modules")], &[]);
+
+        let executable = ensure(server.url(), dir.path()).unwrap();
+
+        let install_dir = dir.path().join(COMPONENT);
+        assert_eq!(executable, install_dir.join(java_path()));
+        assert_eq!(
+            fs::read_to_string(install_dir.join("lib/modules")).unwrap(),
+            "modules"
+        );
+        assert_eq!(marker(dir.path()).version, "21.0.3");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&executable).unwrap().permissions().mode();
+            assert_eq!(mode & 0o755, 0o755);
+        }
+    }
+
+    #[test]
+    fn matching_marker_skips_the_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let (server, _) = runtime_server("21.0.3", &[], &[]);
+        ensure(server.url(), dir.path()).unwrap();
+
+        let executable = ensure(server.url(), dir.path()).unwrap();
+
+        assert!(executable.is_file());
+        assert_eq!(server.requests_to("/manifests/").len(), 1);
+        assert_eq!(server.requests_to("/files/").len(), 1);
+    }
+
+    #[test]
+    fn installed_runtime_is_used_when_the_index_is_unreachable() {
+        let dir = tempfile::tempdir().unwrap();
+        let (server, _) = runtime_server("21.0.3", &[], &[]);
+        let installed = ensure(server.url(), dir.path()).unwrap();
+        let offline = TestServer::start(|_| Response::status(503));
+
+        assert_eq!(ensure(offline.url(), dir.path()).unwrap(), installed);
+    }
+
+    #[test]
+    fn unreachable_index_without_runtime_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let offline = TestServer::start(|_| Response::status(503));
+
+        assert!(ensure(offline.url(), dir.path()).is_err());
+    }
+
+    #[test]
+    fn reinstall_removes_files_of_the_previous_build() {
+        let dir = tempfile::tempdir().unwrap();
+        let (old, _) = runtime_server(
+            "21.0.3",
+            &[
+                ("lib/modules", "old modules"),
+                ("lib/old/libjsound.so", "sound"),
+            ],
+            &[],
+        );
+        ensure(old.url(), dir.path()).unwrap();
+
+        let (new, _) = runtime_server("21.0.5", &[("lib/modules", "new modules")], &[]);
+        let executable = ensure(new.url(), dir.path()).unwrap();
+
+        let install_dir = dir.path().join(COMPONENT);
+        assert!(executable.is_file());
+        assert_eq!(
+            fs::read_to_string(install_dir.join("lib/modules")).unwrap(),
+            "new modules"
+        );
+        assert!(!install_dir.join("lib/old").exists());
+        assert_eq!(marker(dir.path()).version, "21.0.5");
+    }
+
+    #[test]
+    fn manifest_with_wrong_sha1_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let (server, routes) = runtime_server("21.0.3", &[], &[]);
+        routes
+            .lock()
+            .unwrap()
+            .insert("/manifests/21.0.3.json".to_string(), b"{}".to_vec());
+
+        let err = ensure(server.url(), dir.path()).unwrap_err();
+
+        assert!(err.to_string().contains("failed verification"));
+        assert!(!dir.path().join(COMPONENT).join(VERSION_MARKER).exists());
+    }
+
+    #[test]
+    fn interrupted_reinstall_is_not_used_offline() {
+        let dir = tempfile::tempdir().unwrap();
+        let (old, _) = runtime_server("21.0.3", &[("lib/modules", "old modules")], &[]);
+        ensure(old.url(), dir.path()).unwrap();
+
+        let (new, routes) = runtime_server("21.0.5", &[("lib/modules", "new modules")], &[]);
+        routes.lock().unwrap().remove("/files/21.0.5/lib/modules");
+        assert!(ensure(new.url(), dir.path()).is_err());
+
+        let offline = TestServer::start(|_| Response::status(503));
+        assert!(ensure(offline.url(), dir.path()).is_err());
+        assert!(!dir.path().join(COMPONENT).join(VERSION_MARKER).exists());
+    }
+
+    #[test]
+    fn links_point_at_their_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let java = java_path();
+        let (java_dir, java_name) = java.rsplit_once('/').unwrap();
+        let link = format!("{}/java-link", java_dir);
+        let (server, _) = runtime_server("21.0.3", &[], &[(&link, java_name)]);
+
+        ensure(server.url(), dir.path()).unwrap();
+        // installing over an existing link replaces it
+        let (server, _) = runtime_server("21.0.5", &[], &[(&link, java_name)]);
+        ensure(server.url(), dir.path()).unwrap();
+
+        let link = dir.path().join(COMPONENT).join(&link);
+        assert_eq!(fs::read_to_string(&link).unwrap(), "#!/bin/sh\n");
+        #[cfg(unix)]
+        assert_eq!(fs::read_link(&link).unwrap(), Path::new(java_name));
+    }
+}
@@ -0,0 +1,503 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+
+use crate::utils::minecraft_auth::{self, AccountRecord, AccountType};
+use crate::utils::settings::{LaunchSettings, DEFAULT_MAX_MEMORY, DEFAULT_MIN_MEMORY};
+use crate::utils::version_json::{Argument, RuleContext, VersionJson};
+use crate::utils::versions::VersionType;
+
+pub const LAUNCHER_NAME: &str = "decent-client";
+pub const LAUNCHER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[cfg(windows)]
+pub const CLASSPATH_SEPARATOR: &str = ";";
+#[cfg(not(windows))]
+pub const CLASSPATH_SEPARATOR: &str = ":";
+
+#[derive(Debug, Clone)]
+pub struct LaunchAccount {
+    pub username: String,
+    pub uuid: String,
+    pub access_token: String,
+    pub user_type: String,
+}
+
+impl From<&AccountRecord> for LaunchAccount {
+    fn from(record: &AccountRecord) -> Self {
+        let (access_token, user_type) = match record.account_type {
+            AccountType::Microsoft => (record.minecraft.access_token.clone(), "msa"),
+            AccountType::Offline => (minecraft_auth::OFFLINE_ACCESS_TOKEN.to_string(), "legacy"),
+            AccountType::Yggdrasil => (record.minecraft.access_token.clone(), "mojang"),
+        };
+
+        Self {
+            username: record.username.clone(),
+            uuid: record.uuid.clone(),
+            access_token,
+            user_type: user_type.to_string(),
+        }
+    }
+}
+
+/// Everything the argument templates of a version JSON can refer to.
+#[derive(Debug, Clone)]
+pub struct LaunchContext {
+    pub account: LaunchAccount,
+    pub version_name: String,
+    pub game_directory: PathBuf,
+    pub assets_root: PathBuf,
+    pub game_assets: PathBuf,
+    pub assets_index_name: String,
+    pub natives_directory: PathBuf,
+    pub libraries_directory: PathBuf,
+    pub classpath: Vec<PathBuf>,
+    pub logging_config: Option<PathBuf>,
+    /// Instance settings already merged with the global defaults.
+    pub settings: LaunchSettings,
+    /// JVM arguments the launcher itself needs, e.g. the authlib-injector agent; added before
+    /// the user's own so those can still override them.
+    pub extra_jvm_args: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LaunchCommand {
+    pub jvm_args: Vec<String>,
+    pub main_class: String,
+    pub game_args: Vec<String>,
+}
+
+impl LaunchCommand {
+    /// The full argument list passed to the `java` executable.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = self.jvm_args.clone();
+        args.push(self.main_class.clone());
+        args.extend(self.game_args.iter().cloned());
+        args
+    }
+}
+
+/// Builds the JVM and game arguments for `version`, substituting `${...}` placeholders from
+/// `context`. Versions with a legacy `minecraftArguments` string get the default JVM
+/// arguments the vanilla launcher used before `arguments.jvm` existed.
+pub fn build_launch_command(
+    version: &VersionJson,
+    context: &LaunchContext,
+    rules: &RuleContext,
+) -> Result<LaunchCommand> {
+    let main_class = version
+        .main_class
+        .clone()
+        .ok_or_else(|| anyhow!("version {} does not declare a main class", version.id))?;
+
+    let settings = &context.settings;
+    let variables = launch_variables(version, context);
+
+    let (jvm_templates, game_templates) = match (&version.arguments, &version.minecraft_arguments) {
+        (Some(arguments), _) => (
+            collect_arguments(&arguments.jvm, rules),
+            collect_arguments(&arguments.game, rules),
+        ),
+        (None, Some(legacy)) => {
+            let mut game: Vec<String> = legacy.split_whitespace().map(str::to_string).collect();
+            // legacy versions have no `has_custom_resolution` rule to hang these off
+            if settings.resolution().is_some() {
+                game.extend(
+                    [
+                        "--width",
+                        "${resolution_width}",
+                        "--height",
+                        "${resolution_height}",
+                    ]
+                    .map(str::to_string),
+                );
+            }
+            (legacy_jvm_arguments(), game)
+        }
+        (None, None) => {
+            return Err(anyhow!(
+                "version {} declares neither arguments nor minecraftArguments",
+                version.id
+            ))
+        }
+    };
+
+    let mut jvm_args: Vec<String> = jvm_templates
+        .iter()
+        .map(|arg| substitute(arg, &variables))
+        .collect();
+
+    jvm_args.push(format!(
+        "-Xms{}M",
+        settings.min_memory.unwrap_or(DEFAULT_MIN_MEMORY)
+    ));
+    jvm_args.push(format!(
+        "-Xmx{}M",
+        settings.max_memory.unwrap_or(DEFAULT_MAX_MEMORY)
+    ));
+    jvm_args.extend(context.extra_jvm_args.iter().cloned());
+    jvm_args.extend(settings.jvm_args.iter().flatten().cloned());
+
+    if let (Some(logging), Some(config)) = (
+        version
+            .logging
+            .as_ref()
+            .and_then(|logging| logging.client.as_ref()),
+        &context.logging_config,
+    ) {
+        let path = HashMap::from([("path", config.to_string_lossy().into_owned())]);
+        jvm_args.push(substitute(&logging.argument, &path));
+    }
+
+    let mut game_args: Vec<String> = game_templates
+        .iter()
+        .map(|arg| substitute(arg, &variables))
+        .collect();
+
+    if settings.fullscreen.unwrap_or(false) {
+        game_args.push("--fullscreen".to_string());
+    }
+
+    Ok(LaunchCommand {
+        jvm_args,
+        main_class,
+        game_args,
+    })
+}
+
+/// Replaces every `${name}` in `template` with its value, leaving unknown placeholders as-is.
+pub fn substitute(template: &str, variables: &HashMap<&str, String>) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("${") {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+
+        match after.find('}') {
+            Some(end) => {
+                let name = &after[..end];
+                match variables.get(name) {
+                    Some(value) => output.push_str(value),
+                    None => output.push_str(&rest[start..start + 2 + end + 1]),
+                }
+                rest = &after[end + 1..];
+            }
+            None => {
+                output.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+
+    output.push_str(rest);
+    output
+}
+
+pub fn join_classpath(entries: &[PathBuf]) -> String {
+    entries
+        .iter()
+        .map(|entry| entry.to_string_lossy().into_owned())
+        .collect::<Vec<_>>()
+        .join(CLASSPATH_SEPARATOR)
+}
+
+fn collect_arguments(arguments: &[Argument], rules: &RuleContext) -> Vec<String> {
+    arguments
+        .iter()
+        .flat_map(|argument| argument.values(rules))
+        .collect()
+}
+
+fn legacy_jvm_arguments() -> Vec<String> {
+    vec![
+        "-Djava.library.path=${natives_directory}".to_string(),
+        "-Dminecraft.launcher.brand=${launcher_name}".to_string(),
+        "-Dminecraft.launcher.version=${launcher_version}".to_string(),
+        "-cp".to_string(),
+        "${classpath}".to_string(),
+    ]
+}
+
+fn launch_variables(
+    version: &VersionJson,
+    context: &LaunchContext,
+) -> HashMap<&'static str, String> {
+    let account = &context.account;
+    let path = |path: &Path| path.to_string_lossy().into_owned();
+
+    HashMap::from([
+        ("auth_player_name", account.username.clone()),
+        ("auth_uuid", account.uuid.clone()),
+        ("auth_access_token", account.access_token.clone()),
+        (
+            "auth_session",
+            format!("token:{}:{}", account.access_token, account.uuid),
+        ),
+        ("auth_xuid", String::new()),
+        ("clientid", minecraft_auth::MSA_CLIENT_ID.to_string()),
+        ("user_type", account.user_type.clone()),
+        ("user_properties", "{}".to_string()),
+        ("version_name", context.version_name.clone()),
+        (
+            "version_type",
+            version
+                .kind
+                .unwrap_or(VersionType::Release)
+                .as_str()
+                .to_string(),
+        ),
+        ("game_directory", path(&context.game_directory)),
+        ("assets_root", path(&context.assets_root)),
+        ("game_assets", path(&context.game_assets)),
+        ("assets_index_name", context.assets_index_name.clone()),
+        ("natives_directory", path(&context.natives_directory)),
+        ("library_directory", path(&context.libraries_directory)),
+        ("classpath", join_classpath(&context.classpath)),
+        ("classpath_separator", CLASSPATH_SEPARATOR.to_string()),
+        ("launcher_name", LAUNCHER_NAME.to_string()),
+        ("launcher_version", LAUNCHER_VERSION.to_string()),
+        (
+            "resolution_width",
+            context.settings.width.unwrap_or_default().to_string(),
+        ),
+        (
+            "resolution_height",
+            context.settings.height.unwrap_or_default().to_string(),
+        ),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_fixtures::version_json as fixture;
+
+    fn linux() -> RuleContext {
+        RuleContext {
+            os_name: "linux".to_string(),
+            os_version: "6.8.0".to_string(),
+            arch: "x86_64".to_string(),
+            features: HashMap::new(),
+        }
+    }
+
+    fn context(settings: LaunchSettings) -> LaunchContext {
+        LaunchContext {
+            account: LaunchAccount {
+                username: "Steve".to_string(),
+                uuid: "069a79f444e94726a5befca90e38aaf5".to_string(),
+                access_token: "token".to_string(),
+                user_type: "msa".to_string(),
+            },
+            version_name: "1.21".to_string(),
+            game_directory: PathBuf::from("/game"),
+            assets_root: PathBuf::from("/assets"),
+            game_assets: PathBuf::from("/assets/virtual/legacy"),
+            assets_index_name: "17".to_string(),
+            natives_directory: PathBuf::from("/natives"),
+            libraries_directory: PathBuf::from("/libraries"),
+            classpath: vec![
+                PathBuf::from("/libraries/a.jar"),
+                PathBuf::from("/client.jar"),
+            ],
+            logging_config: Some(PathBuf::from("/assets/log_configs/client-1.12.xml")),
+            settings,
+            extra_jvm_args: Vec::new(),
+        }
+    }
+
+    fn position(args: &[String], arg: &str) -> usize {
+        args.iter()
+            .position(|candidate| candidate == arg)
+            .unwrap_or_else(|| panic!("{} missing from {:?}", arg, args))
+    }
+
+    #[test]
+    fn substitute_replaces_known_placeholders() {
+        let variables =
+            HashMap::from([("name", "Steve".to_string()), ("dir", "/game".to_string())]);
+
+        assert_eq!(substitute("${name}", &variables), "Steve");
+        assert_eq!(
+            substitute("--dir=${dir}/${name}.log", &variables),
+            "--dir=/game/Steve.log"
+        );
+        assert_eq!(substitute("no placeholders", &variables), "no placeholders");
+    }
+
+    #[test]
+    fn substitute_keeps_unknown_and_unterminated_placeholders() {
+        let variables = HashMap::from([("name", "Steve".to_string())]);
+
+        assert_eq!(
+            substitute("${missing}-${name}", &variables),
+            "${missing}-Steve"
+        );
+        assert_eq!(substitute("${name}-${name", &variables), "Steve-${name");
+        assert_eq!(substitute("$name {name}", &variables), "$name {name}");
+    }
+
+    #[test]
+    fn modern_arguments_follow_rules() {
+        let command = build_launch_command(
+            &fixture("1.21"),
+            &context(LaunchSettings::default()),
+            &linux(),
+        )
+        .unwrap();
+
+        assert_eq!(command.main_class, "net.minecraft.client.main.Main");
+        assert_eq!(
+            &command.jvm_args[..5],
+            [
+                "-Djava.library.path=/natives",
+                "-Dminecraft.launcher.brand=decent-client",
+                &format!("-Dminecraft.launcher.version={}", LAUNCHER_VERSION),
+                "-cp",
+                &join_classpath(&[
+                    PathBuf::from("/libraries/a.jar"),
+                    PathBuf::from("/client.jar")
+                ]),
+            ]
+        );
+        assert!(!command
+            .jvm_args
+            .iter()
+            .any(|arg| arg == "-XstartOnFirstThread"));
+
+        assert_eq!(command.game_args[..2], ["--username", "Steve"]);
+        assert_eq!(
+            command.game_args[position(&command.game_args, "--gameDir") + 1],
+            "/game"
+        );
+        assert_eq!(
+            command.game_args[position(&command.game_args, "--versionType") + 1],
+            "release"
+        );
+        assert!(!command.game_args.iter().any(|arg| arg == "--demo"));
+        assert!(!command.game_args.iter().any(|arg| arg == "--width"));
+    }
+
+    #[test]
+    fn modern_arguments_include_custom_resolution() {
+        let settings = LaunchSettings {
+            width: Some(1280),
+            height: Some(720),
+            fullscreen: Some(true),
+            ..Default::default()
+        };
+        let rules = linux().with_feature("has_custom_resolution", true);
+
+        let command = build_launch_command(&fixture("1.21"), &context(settings), &rules).unwrap();
+
+        assert_eq!(
+            command.game_args[command.game_args.len() - 5..],
+            ["--width", "1280", "--height", "720", "--fullscreen"]
+        );
+    }
+
+    #[test]
+    fn legacy_arguments_get_default_jvm_arguments() {
+        let settings = LaunchSettings {
+            width: Some(854),
+            height: Some(480),
+            ..Default::default()
+        };
+
+        let command =
+            build_launch_command(&fixture("1.8.9"), &context(settings), &linux()).unwrap();
+
+        assert_eq!(command.jvm_args[0], "-Djava.library.path=/natives");
+        assert_eq!(command.jvm_args[3], "-cp");
+        assert_eq!(
+            command.game_args,
+            [
+                "--username",
+                "Steve",
+                "--version",
+                "1.21",
+                "--gameDir",
+                "/game",
+                "--assetsDir",
+                "/assets",
+                "--assetIndex",
+                "17",
+                "--uuid",
+                "069a79f444e94726a5befca90e38aaf5",
+                "--accessToken",
+                "token",
+                "--userProperties",
+                "{}",
+                "--userType",
+                "msa",
+                "--width",
+                "854",
+                "--height",
+                "480",
+            ]
+        );
+    }
+
+    #[test]
+    fn memory_and_extra_arguments_follow_version_arguments() {
+        let mut context = context(LaunchSettings {
+            min_memory: Some(1024),
+            max_memory: Some(4096),
+            jvm_args: Some(vec!["-XX:+UseG1GC".to_string()]),
+            ..Default::default()
+        });
+        context.extra_jvm_args = vec!["-javaagent:/injector.jar=https://example.com".to_string()];
+
+        let command = build_launch_command(&fixture("1.21"), &context, &linux()).unwrap();
+        let args = &command.jvm_args;
+
+        let classpath = position(args, "-cp") + 1;
+        let xms = position(args, "-Xms1024M");
+        let xmx = position(args, "-Xmx4096M");
+        let agent = position(args, "-javaagent:/injector.jar=https://example.com");
+        let user = position(args, "-XX:+UseG1GC");
+        let logging = position(
+            args,
+            "-Dlog4j.configurationFile=/assets/log_configs/client-1.12.xml",
+        );
+
+        assert_eq!(
+            [classpath + 1, xms + 1, xmx + 1, agent + 1, user + 1],
+            [xms, xmx, agent, user, logging]
+        );
+        assert_eq!(logging, args.len() - 1);
+
+        let full = command.args();
+        assert_eq!(full[args.len()], "net.minecraft.client.main.Main");
+        assert_eq!(full[args.len() + 1], "--username");
+    }
+
+    #[test]
+    fn memory_defaults_apply_without_settings() {
+        let command = build_launch_command(
+            &fixture("1.8.9"),
+            &context(LaunchSettings::default()),
+            &linux(),
+        )
+        .unwrap();
+
+        assert!(command
+            .jvm_args
+            .contains(&format!("-Xms{}M", DEFAULT_MIN_MEMORY)));
+        assert!(command
+            .jvm_args
+            .contains(&format!("-Xmx{}M", DEFAULT_MAX_MEMORY)));
+    }
+
+    #[test]
+    fn version_without_arguments_is_rejected() {
+        let mut version = fixture("1.8.9");
+        version.minecraft_arguments = None;
+
+        assert!(
+            build_launch_command(&version, &context(LaunchSettings::default()), &linux()).is_err()
+        );
+    }
+}
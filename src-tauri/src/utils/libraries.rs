@@ -0,0 +1,453 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use log::debug;
+use zip::ZipArchive;
+
+use crate::utils::download::DownloadTask;
+use crate::utils::version_json::{Artifact, Library, RuleContext, VersionJson};
+
+#[derive(Debug, Clone)]
+pub struct ResolvedLibrary {
+    pub name: String,
+    pub path: PathBuf,
+    pub download: Option<DownloadTask>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NativeLibrary {
+    pub library: ResolvedLibrary,
+    pub exclude: Vec<String>,
+}
+
+/// The libraries of a version that apply on the current platform.
+#[derive(Debug, Clone, Default)]
+pub struct LibrarySet {
+    pub classpath: Vec<ResolvedLibrary>,
+    pub natives: Vec<NativeLibrary>,
+}
+
+impl LibrarySet {
+    pub fn download_tasks(&self) -> Vec<DownloadTask> {
+        self.classpath
+            .iter()
+            .chain(self.natives.iter().map(|native| &native.library))
+            .filter_map(|library| library.download.clone())
+            .collect()
+    }
+
+    /// Library jars in declaration order followed by the client jar.
+    pub fn classpath(&self, client_jar: &Path) -> Vec<PathBuf> {
+        let mut entries: Vec<PathBuf> = self
+            .classpath
+            .iter()
+            .map(|library| library.path.clone())
+            .collect();
+        entries.push(client_jar.to_path_buf());
+        entries
+    }
+
+    /// Fails on the first classpath library that is not on disk. Meant to run after the
+    /// downloads, when only libraries without download metadata can still be missing.
+    pub fn ensure_present(&self) -> Result<()> {
+        match self
+            .classpath
+            .iter()
+            .find(|library| !library.path.is_file())
+        {
+            Some(library) => bail!(
+                "library {} is not installed at {:?}",
+                library.name,
+                library.path
+            ),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Resolves which library artifacts and natives apply under `rules`, laid out in the shared
+/// Maven-style `libraries_dir`. When the same `group:artifact[:classifier]` appears more than
+/// once the first declaration wins, so libraries of an inheriting version override the
+/// parent's.
+pub fn resolve_libraries(
+    version: &VersionJson,
+    libraries_dir: &Path,
+    rules: &RuleContext,
+) -> LibrarySet {
+    let mut set = LibrarySet::default();
+    let mut seen_classpath = HashSet::new();
+    let mut seen_natives = HashSet::new();
+
+    for library in version.libraries.iter().filter(|l| l.applies(rules)) {
+        let artifact = library
+            .downloads
+            .as_ref()
+            .and_then(|downloads| downloads.artifact.as_ref());
+
+        if let Some(classifier) = native_classifier(library, rules) {
+            let key = format!("{}:{}", library_key(&library.name), classifier);
+            if seen_natives.insert(key) {
+                if let Some(resolved) = resolve_native(library, &classifier, libraries_dir) {
+                    set.natives.push(NativeLibrary {
+                        library: resolved,
+                        exclude: library
+                            .extract
+                            .as_ref()
+                            .map(|extract| extract.exclude.clone())
+                            .unwrap_or_default(),
+                    });
+                }
+            }
+
+            // natives-only entries (e.g. LWJGL 2's platform jars) have nothing for the classpath
+            if artifact.is_none() {
+                continue;
+            }
+        }
+
+        if !seen_classpath.insert(library_key(&library.name)) {
+            debug!("skipping duplicate library {}", library.name);
+            continue;
+        }
+
+        if let Some(resolved) = resolve_artifact(library, artifact, libraries_dir) {
+            set.classpath.push(resolved);
+        }
+    }
+
+    set
+}
+
+/// Extracts every native jar into `natives_dir`, skipping entries under `extract.exclude`.
+pub fn extract_natives(natives: &[NativeLibrary], natives_dir: &Path) -> Result<()> {
+    fs::create_dir_all(natives_dir)
+        .with_context(|| format!("failed to create natives directory {:?}", natives_dir))?;
+
+    for native in natives {
+        let file = File::open(&native.library.path)
+            .with_context(|| format!("failed to open native library {}", native.library.name))?;
+        let mut archive = ZipArchive::new(file)
+            .with_context(|| format!("failed to read native library {}", native.library.name))?;
+
+        for index in 0..archive.len() {
+            let mut entry = archive.by_index(index)?;
+            if entry.is_dir() {
+                continue;
+            }
+
+            let Some(relative) = entry.enclosed_name() else {
+                continue;
+            };
+
+            let name = relative.to_string_lossy().replace('\\', "/");
+            if native
+                .exclude
+                .iter()
+                .any(|prefix| name.starts_with(prefix.as_str()))
+            {
+                continue;
+            }
+
+            let destination = natives_dir.join(&relative);
+            if let Some(parent) = destination.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            let mut output = File::create(&destination)
+                .with_context(|| format!("failed to create {:?}", destination))?;
+            io::copy(&mut entry, &mut output)
+                .with_context(|| format!("failed to extract {}", name))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Converts `group:artifact:version[:classifier][@extension]` into its Maven repository path.
+pub fn maven_path(name: &str) -> Option<String> {
+    let (coordinates, extension) = name.split_once('@').unwrap_or((name, "jar"));
+    let mut parts = coordinates.split(':');
+    let group = parts.next()?;
+    let artifact = parts.next()?;
+    let version = parts.next()?;
+    let classifier = parts.next();
+
+    let file_name = match classifier {
+        Some(classifier) => format!("{}-{}-{}.{}", artifact, version, classifier, extension),
+        None => format!("{}-{}.{}", artifact, version, extension),
+    };
+
+    Some(format!(
+        "{}/{}/{}/{}",
+        group.replace('.', "/"),
+        artifact,
+        version,
+        file_name
+    ))
+}
+
+/// `group:artifact[:classifier]`, i.e. the coordinates without the version.
+fn library_key(name: &str) -> String {
+    let parts: Vec<&str> = name.split('@').next().unwrap_or(name).split(':').collect();
+    match parts.as_slice() {
+        [group, artifact, _version, classifier, ..] => {
+            format!("{}:{}:{}", group, artifact, classifier)
+        }
+        [group, artifact, ..] => format!("{}:{}", group, artifact),
+        _ => name.to_string(),
+    }
+}
+
+fn native_classifier(library: &Library, rules: &RuleContext) -> Option<String> {
+    let natives = library.natives.as_ref()?;
+    let template = natives.get(&rules.os_name)?;
+    let bits = if rules.arch.ends_with("64") {
+        "64"
+    } else {
+        "32"
+    };
+    Some(template.replace("${arch}", bits))
+}
+
+fn resolve_artifact(
+    library: &Library,
+    artifact: Option<&Artifact>,
+    libraries_dir: &Path,
+) -> Option<ResolvedLibrary> {
+    match (artifact, &library.url) {
+        (Some(artifact), _) => {
+            let path = artifact
+                .path
+                .clone()
+                .or_else(|| maven_path(&library.name))?;
+            Some(resolved(
+                library.name.clone(),
+                libraries_dir,
+                path,
+                Some(artifact),
+            ))
+        }
+        (None, Some(repository)) => {
+            let path = maven_path(&library.name)?;
+            let full_path = libraries_dir.join(&path);
+            Some(ResolvedLibrary {
+                name: library.name.clone(),
+                download: Some(DownloadTask {
+                    url: format!("{}/{}", repository.trim_end_matches('/'), path),
+                    path: full_path.clone(),
+                    sha1: None,
+                    size: None,
+                }),
+                path: full_path,
+            })
+        }
+        // loader libraries without download metadata are expected to be installed already,
+        // which `LibrarySet::ensure_present` checks once the downloads are done
+        (None, None) => {
+            let path = maven_path(&library.name)?;
+            Some(resolved(library.name.clone(), libraries_dir, path, None))
+        }
+    }
+}
+
+fn resolve_native(
+    library: &Library,
+    classifier: &str,
+    libraries_dir: &Path,
+) -> Option<ResolvedLibrary> {
+    let name = format!("{}:{}", library.name, classifier);
+    let artifact = library
+        .downloads
+        .as_ref()
+        .and_then(|downloads| downloads.classifiers.as_ref())
+        .and_then(|classifiers| classifiers.get(classifier));
+
+    let path = artifact
+        .and_then(|artifact| artifact.path.clone())
+        .or_else(|| maven_path(&name))?;
+
+    Some(resolved(name, libraries_dir, path, artifact))
+}
+
+fn resolved(
+    name: String,
+    libraries_dir: &Path,
+    path: String,
+    artifact: Option<&Artifact>,
+) -> ResolvedLibrary {
+    let full_path = libraries_dir.join(path);
+
+    ResolvedLibrary {
+        name,
+        download: artifact
+            .filter(|artifact| !artifact.url.is_empty())
+            .map(|artifact| DownloadTask {
+                url: artifact.url.clone(),
+                path: full_path.clone(),
+                sha1: Some(artifact.sha1.clone()),
+                size: Some(artifact.size),
+            }),
+        path: full_path,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    fn rules(os_name: &str, arch: &str) -> RuleContext {
+        RuleContext {
+            os_name: os_name.to_string(),
+            os_version: String::new(),
+            arch: arch.to_string(),
+            features: HashMap::new(),
+        }
+    }
+
+    fn version(libraries: serde_json::Value) -> VersionJson {
+        serde_json::from_value(json!({ "id": "test", "libraries": libraries })).unwrap()
+    }
+
+    fn artifact(path: &str) -> serde_json::Value {
+        json!({
+            "path": path,
+            "sha1": "0000000000000000000000000000000000000000",
+            "size": 1,
+            "url": format!("https://libraries.example/{}", path),
+        })
+    }
+
+    fn names(libraries: &[ResolvedLibrary]) -> Vec<&str> {
+        libraries
+            .iter()
+            .map(|library| library.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn duplicate_libraries_keep_the_first_declaration_in_order() {
+        let version = version(json!([
+            { "name": "org.ow2.asm:asm:9.7", "downloads": { "artifact": artifact("asm-9.7.jar") } },
+            { "name": "com.google:guava:32.0", "downloads": { "artifact": artifact("guava.jar") } },
+            { "name": "org.ow2.asm:asm:9.6", "downloads": { "artifact": artifact("asm-9.6.jar") } },
+            { "name": "org.lwjgl:lwjgl:3.3.3", "downloads": { "artifact": artifact("lwjgl.jar") } },
+            { "name": "org.lwjgl:lwjgl:3.3.3:natives-linux", "downloads": { "artifact": artifact("lwjgl-natives.jar") } },
+        ]));
+
+        let set = resolve_libraries(&version, Path::new("/libraries"), &rules("linux", "x86_64"));
+
+        assert_eq!(
+            names(&set.classpath),
+            [
+                "org.ow2.asm:asm:9.7",
+                "com.google:guava:32.0",
+                "org.lwjgl:lwjgl:3.3.3",
+                "org.lwjgl:lwjgl:3.3.3:natives-linux",
+            ]
+        );
+        assert_eq!(
+            set.classpath(Path::new("/client.jar")),
+            [
+                PathBuf::from("/libraries/asm-9.7.jar"),
+                PathBuf::from("/libraries/guava.jar"),
+                PathBuf::from("/libraries/lwjgl.jar"),
+                PathBuf::from("/libraries/lwjgl-natives.jar"),
+                PathBuf::from("/client.jar"),
+            ]
+        );
+    }
+
+    #[test]
+    fn natives_use_the_classifier_for_the_platform_and_arch() {
+        let version = version(json!([{
+            "name": "org.lwjgl.lwjgl:lwjgl-platform:2.9.4",
+            "natives": { "linux": "natives-linux", "windows": "natives-windows-${arch}" },
+            "extract": { "exclude": ["META-INF/"] },
+            "downloads": { "classifiers": {
+                "natives-linux": artifact("platform-linux.jar"),
+                "natives-windows-32": artifact("platform-windows-32.jar"),
+                "natives-windows-64": artifact("platform-windows-64.jar"),
+            } },
+        }]));
+        let libraries = Path::new("/libraries");
+
+        for (os_name, arch, path) in [
+            ("linux", "x86_64", "platform-linux.jar"),
+            ("windows", "x86", "platform-windows-32.jar"),
+            ("windows", "x86_64", "platform-windows-64.jar"),
+        ] {
+            let set = resolve_libraries(&version, libraries, &rules(os_name, arch));
+
+            assert!(set.classpath.is_empty());
+            assert_eq!(set.natives.len(), 1);
+            assert_eq!(set.natives[0].library.path, libraries.join(path));
+            assert_eq!(set.natives[0].exclude, ["META-INF/"]);
+        }
+
+        let set = resolve_libraries(&version, libraries, &rules("osx", "aarch64"));
+        assert!(set.natives.is_empty());
+    }
+
+    #[test]
+    fn libraries_without_downloads_must_already_be_installed() {
+        let dir = tempfile::tempdir().unwrap();
+        let version = version(json!([{ "name": "net.fabricmc:intermediary:1.21" }]));
+
+        let set = resolve_libraries(&version, dir.path(), &rules("linux", "x86_64"));
+
+        let err = set.ensure_present().unwrap_err();
+        assert!(err.to_string().contains("net.fabricmc:intermediary:1.21"));
+
+        let path = &set.classpath[0].path;
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, b"jar").unwrap();
+        set.ensure_present().unwrap();
+    }
+
+    #[test]
+    fn extraction_skips_excluded_and_escaping_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let jar = dir.path().join("natives.jar");
+        let mut writer = ZipWriter::new(File::create(&jar).unwrap());
+        for name in [
+            "liblwjgl.so",
+            "linux/libopenal.so",
+            "META-INF/MANIFEST.MF",
+            "../escaped.so",
+        ] {
+            writer
+                .start_file(name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(name.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let natives_dir = dir.path().join("natives");
+        let native = NativeLibrary {
+            library: ResolvedLibrary {
+                name: "org.lwjgl:lwjgl:3.3.3:natives-linux".to_string(),
+                path: jar,
+                download: None,
+            },
+            exclude: vec!["META-INF/".to_string()],
+        };
+
+        extract_natives(&[native], &natives_dir).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(natives_dir.join("liblwjgl.so")).unwrap(),
+            "liblwjgl.so"
+        );
+        assert!(natives_dir.join("linux/libopenal.so").is_file());
+        assert!(!natives_dir.join("META-INF").exists());
+        assert!(!dir.path().join("escaped.so").exists());
+    }
+}
@@ -0,0 +1,264 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use log::debug;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time;
+use url::Url;
+
+use crate::utils::auth_error::AuthError;
+
+/// How long the user gets to finish signing in before the listener gives up.
+const REDIRECT_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+/// How long a single connection may take to send its request head, so one stalled connection
+/// cannot hold up the redirect behind it.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_REQUEST_SIZE: usize = 16 * 1024;
+
+const SUCCESS_PAGE: &str =
+    "<!doctype html><html><head><meta charset=\"utf-8\"><title>Decent Client</title></head>\
+<body style=\"font-family: sans-serif; text-align: center; margin-top: 4em\">\
+<h2>Signed in</h2><p>You can close this tab and return to the launcher.</p></body></html>";
+const FAILURE_PAGE: &str =
+    "<!doctype html><html><head><meta charset=\"utf-8\"><title>Decent Client</title></head>\
+<body style=\"font-family: sans-serif; text-align: center; margin-top: 4em\">\
+<h2>Sign-in failed</h2><p>Return to the launcher and try again.</p></body></html>";
+
+/// A temporary HTTP listener on `127.0.0.1` that receives the authorization redirect.
+pub struct LoopbackListener {
+    listener: TcpListener,
+    port: u16,
+    request_timeout: Duration,
+}
+
+impl LoopbackListener {
+    /// Binds to a free port chosen by the OS.
+    pub async fn bind() -> Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", 0))
+            .await
+            .context("failed to bind loopback listener")?;
+        let port = listener
+            .local_addr()
+            .context("failed to read loopback listener address")?
+            .port();
+
+        Ok(Self {
+            listener,
+            port,
+            request_timeout: REQUEST_TIMEOUT,
+        })
+    }
+
+    pub fn redirect_url(&self) -> String {
+        format!("http://127.0.0.1:{}", self.port)
+    }
+
+    /// Waits for the browser to be redirected back with `code` and `state`, answering
+    /// unrelated requests (e.g. `/favicon.ico`) with a 404 and malformed requests or redirects
+    /// with another `state` with a 400, and returns the code once `state` matches
+    /// `expected_state`.
+    pub async fn wait_for_code(self, expected_state: &str) -> Result<String> {
+        time::timeout(REDIRECT_TIMEOUT, self.accept_redirect(expected_state))
+            .await
+            .map_err(|_| anyhow!("timed out waiting for the browser sign-in"))?
+    }
+
+    async fn accept_redirect(&self, expected_state: &str) -> Result<String> {
+        loop {
+            let (mut stream, _) = self
+                .listener
+                .accept()
+                .await
+                .context("failed to accept loopback connection")?;
+
+            let target = time::timeout(self.request_timeout, read_request_target(&mut stream))
+                .await
+                .unwrap_or_else(|_| Err(anyhow!("timed out reading the request")));
+            let url = target.and_then(|target| {
+                Url::parse(&format!("http://127.0.0.1{}", target))
+                    .context("failed to parse redirect")
+            });
+            let url = match url {
+                Ok(url) => url,
+                Err(err) => {
+                    debug!("ignoring malformed loopback request: {:#}", err);
+                    respond(&mut stream, "400 Bad Request", "").await;
+                    continue;
+                }
+            };
+
+            let mut code = None;
+            let mut state = None;
+            let mut error = None;
+            let mut error_description = None;
+            for (key, value) in url.query_pairs() {
+                match key.as_ref() {
+                    "code" => code = Some(value.into_owned()),
+                    "state" => state = Some(value.into_owned()),
+                    "error" => error = Some(value.into_owned()),
+                    "error_description" => error_description = Some(value.into_owned()),
+                    _ => {}
+                }
+            }
+
+            if code.is_none() && error.is_none() {
+                respond(&mut stream, "404 Not Found", "").await;
+                continue;
+            }
+
+            // a redirect that does not carry our state was not started by this sign-in, so it
+            // must not end it either
+            if state.as_deref() != Some(expected_state) {
+                debug!("ignoring loopback redirect with a mismatched state");
+                respond(&mut stream, "400 Bad Request", FAILURE_PAGE).await;
+                continue;
+            }
+
+            if let Some(error) = error {
+                respond(&mut stream, "200 OK", FAILURE_PAGE).await;
+                return Err(AuthError::OAuth {
+                    message: error_description.unwrap_or_else(|| error.clone()),
+                    error,
+                }
+                .into());
+            }
+
+            respond(&mut stream, "200 OK", SUCCESS_PAGE).await;
+            return code.ok_or_else(|| anyhow!("missing authorization code"));
+        }
+    }
+}
+
+/// Reads the request head and returns the target of its request line, e.g. `/?code=...`.
+async fn read_request_target(stream: &mut TcpStream) -> Result<String> {
+    let mut buffer = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 1024];
+
+    while !buffer.windows(4).any(|window| window == b"\r\n\r\n") {
+        if buffer.len() > MAX_REQUEST_SIZE {
+            return Err(anyhow!("request head too large"));
+        }
+
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            break;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+
+    let head = String::from_utf8_lossy(&buffer);
+    let request_line = head.lines().next().unwrap_or_default();
+    let mut parts = request_line.split_whitespace();
+
+    match (parts.next(), parts.next()) {
+        (Some("GET"), Some(target)) if target.starts_with('/') => Ok(target.to_string()),
+        _ => Err(anyhow!("unexpected request line '{}'", request_line)),
+    }
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, body: &str) {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+
+    if let Err(err) = stream.write_all(response.as_bytes()).await {
+        debug!("failed to answer loopback request: {}", err);
+    }
+    let _ = stream.shutdown().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_server::block_on;
+
+    /// Sends a bare GET for `target` and returns the status line of the answer.
+    async fn get(url: &str, target: &str) -> String {
+        let address = url.trim_start_matches("http://");
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream
+            .write_all(format!("GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", target, address).as_bytes())
+            .await
+            .unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response.lines().next().unwrap_or_default().to_string()
+    }
+
+    #[test]
+    fn mismatched_state_is_rejected_and_listening_continues() {
+        block_on(async {
+            let listener = LoopbackListener::bind().await.unwrap();
+            let url = listener.redirect_url();
+            let waiting = tokio::spawn(listener.wait_for_code("expected"));
+
+            assert_eq!(get(&url, "/favicon.ico").await, "HTTP/1.1 404 Not Found");
+            assert_eq!(
+                get(&url, "/?code=forged&state=other").await,
+                "HTTP/1.1 400 Bad Request"
+            );
+            assert_eq!(
+                get(&url, "/?error=access_denied&state=other").await,
+                "HTTP/1.1 400 Bad Request"
+            );
+            assert_eq!(
+                get(&url, "/?code=real&state=expected").await,
+                "HTTP/1.1 200 OK"
+            );
+
+            assert_eq!(waiting.await.unwrap().unwrap(), "real");
+        });
+    }
+
+    #[test]
+    fn malformed_and_stalled_connections_do_not_end_the_wait() {
+        block_on(async {
+            let mut listener = LoopbackListener::bind().await.unwrap();
+            listener.request_timeout = Duration::from_millis(100);
+            let url = listener.redirect_url();
+            let address = url.trim_start_matches("http://").to_string();
+            let waiting = tokio::spawn(listener.wait_for_code("expected"));
+
+            let mut malformed = TcpStream::connect(&address).await.unwrap();
+            malformed.write_all(b"NONSENSE\r\n\r\n").await.unwrap();
+            let mut response = String::new();
+            malformed.read_to_string(&mut response).await.unwrap();
+            assert!(response.starts_with("HTTP/1.1 400 Bad Request"));
+
+            // connects but never sends a request
+            let _stalled = TcpStream::connect(&address).await.unwrap();
+
+            assert_eq!(
+                get(&url, "/?code=real&state=expected").await,
+                "HTTP/1.1 200 OK"
+            );
+            assert_eq!(waiting.await.unwrap().unwrap(), "real");
+        });
+    }
+
+    #[test]
+    fn oauth_error_with_matching_state_ends_the_wait() {
+        block_on(async {
+            let listener = LoopbackListener::bind().await.unwrap();
+            let url = listener.redirect_url();
+            let waiting = tokio::spawn(listener.wait_for_code("expected"));
+
+            get(
+                &url,
+                "/?error=access_denied&error_description=denied&state=expected",
+            )
+            .await;
+
+            let err = waiting.await.unwrap().unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<AuthError>(),
+                Some(AuthError::OAuth { error, .. }) if error == "access_denied"
+            ));
+        });
+    }
+}
@@ -1,44 +1,147 @@
+use std::collections::HashMap;
 use std::env;
 use std::fs;
-use std::path::PathBuf;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use anyhow::{anyhow, Context, Result};
-use log::debug;
+use log::{debug, info, warn};
+use md5::{Digest, Md5};
 use oauth2::{
-    basic::{BasicClient, BasicErrorResponseType},
-    AuthType, AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, EndpointNotSet,
-    EndpointSet, PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, RequestTokenError, Scope,
-    TokenResponse, TokenUrl,
+    basic::BasicClient, AuthType, AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken,
+    DeviceAuthorizationUrl, EndpointNotSet, EndpointSet, ErrorResponseType, PkceCodeChallenge,
+    PkceCodeVerifier, RedirectUrl, RefreshToken, RequestTokenError, Scope,
+    StandardDeviceAuthorizationResponse, StandardErrorResponse, TokenResponse, TokenUrl,
 };
-use reqwest::Client;
+use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
-use serde_json::{json, Value};
+use serde_json::json;
 use tauri::{AppHandle, Manager, Runtime, Url};
 
+use crate::utils::auth_error::AuthError;
+use crate::utils::env_url;
+use crate::utils::minecraft_services::{MinecraftServices, NameAvailability, NameChangeInfo};
+use crate::utils::player_lookup;
+use crate::utils::secret_store::{SecretStore, Secrets};
+use crate::utils::yggdrasil::{self, YggdrasilSession};
+
 pub const MSA_CLIENT_ID: &str = "f7770de8-077a-46ea-9604-908154eee29b";
 pub const AUTHORIZE_URL: &str = "https://login.microsoftonline.com/consumers/oauth2/v2.0/authorize";
 pub const TOKEN_URL: &str = "https://login.microsoftonline.com/consumers/oauth2/v2.0/token";
+pub const DEVICE_CODE_URL: &str =
+    "https://login.microsoftonline.com/consumers/oauth2/v2.0/devicecode";
 pub const REDIRECT_URL: &str = "https://login.live.com/oauth20_desktop.srf";
 
-const XBOX_USER_AUTH_URL: &str = "https://user.auth.xboxlive.com/user/authenticate";
-const XBOX_XSTS_URL: &str = "https://xsts.auth.xboxlive.com/xsts/authorize";
-const MINECRAFT_LOGIN_URL: &str =
-    "https://api.minecraftservices.com/authentication/login_with_xbox";
-const MINECRAFT_ENTITLEMENTS_URL: &str = "https://api.minecraftservices.com/entitlements/mcstore";
-const MINECRAFT_PROFILE_URL: &str = "https://api.minecraftservices.com/minecraft/profile";
+pub const XBOX_USER_AUTH_URL: &str = "https://user.auth.xboxlive.com/user/authenticate";
+pub const XBOX_XSTS_URL: &str = "https://xsts.auth.xboxlive.com/xsts/authorize";
+pub const MINECRAFT_SERVICES_URL: &str = "https://api.minecraftservices.com";
+
+/// Offline accounts have no Minecraft token; the game only needs something non-empty.
+pub const OFFLINE_ACCESS_TOKEN: &str = "0";
+
+/// Accounts that own the game but have not picked a profile name yet are stored under this
+/// prefix and their Xbox user hash until [`create_profile`] gives them a real UUID.
+const PENDING_PROFILE_PREFIX: &str = "pending-";
+
+/// Tokens expiring within this many seconds are refreshed before use.
+const REFRESH_MARGIN_SECS: u64 = 5 * 60;
+
+/// How long a Yggdrasil token the server accepted is trusted without asking again.
+const VALIDATION_TTL: Duration = Duration::from_secs(60);
 
 type ConfiguredClient =
     BasicClient<EndpointSet, EndpointNotSet, EndpointNotSet, EndpointNotSet, EndpointSet>;
+type DeviceCodeClient =
+    BasicClient<EndpointSet, EndpointSet, EndpointNotSet, EndpointNotSet, EndpointSet>;
+
+/// The Microsoft, Xbox Live and Minecraft services the login chain talks to. Defaults to the
+/// production endpoints; each one can be pointed elsewhere (e.g. a mock server) through the
+/// `DECENT_*_URL` environment variables read by [`AuthEndpoints::from_env`].
+#[derive(Debug, Clone)]
+pub struct AuthEndpoints {
+    pub authorize_url: String,
+    pub token_url: String,
+    pub device_code_url: String,
+    pub xbox_user_auth_url: String,
+    pub xbox_xsts_url: String,
+    pub minecraft_services_url: String,
+}
+
+impl Default for AuthEndpoints {
+    fn default() -> Self {
+        Self {
+            authorize_url: AUTHORIZE_URL.to_string(),
+            token_url: TOKEN_URL.to_string(),
+            device_code_url: DEVICE_CODE_URL.to_string(),
+            xbox_user_auth_url: XBOX_USER_AUTH_URL.to_string(),
+            xbox_xsts_url: XBOX_XSTS_URL.to_string(),
+            minecraft_services_url: MINECRAFT_SERVICES_URL.to_string(),
+        }
+    }
+}
+
+impl AuthEndpoints {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let var = |name: &str, default: String| env_url(name, &default);
+
+        Self {
+            authorize_url: var("DECENT_MSA_AUTHORIZE_URL", defaults.authorize_url),
+            token_url: var("DECENT_MSA_TOKEN_URL", defaults.token_url),
+            device_code_url: var("DECENT_MSA_DEVICE_CODE_URL", defaults.device_code_url),
+            xbox_user_auth_url: var("DECENT_XBOX_USER_AUTH_URL", defaults.xbox_user_auth_url),
+            xbox_xsts_url: var("DECENT_XBOX_XSTS_URL", defaults.xbox_xsts_url),
+            minecraft_services_url: var(
+                "DECENT_MINECRAFT_SERVICES_URL",
+                defaults.minecraft_services_url,
+            ),
+        }
+    }
+
+    fn minecraft_url(&self, path: &str) -> String {
+        format!("{}/{}", self.minecraft_services_url, path)
+    }
+}
+
+/// One lock per account UUID, so callers that notice the same expired token at once refresh
+/// it only once; Microsoft may revoke a refresh token that is redeemed twice. Registered as
+/// managed state.
+#[derive(Default)]
+pub struct RefreshLocks(Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>);
+
+/// The Yggdrasil tokens the server recently confirmed, keyed by account UUID, so commands
+/// called in a row do not validate the same token each time. Registered as managed state.
+#[derive(Default)]
+pub struct ValidatedTokens(Mutex<HashMap<String, (String, Instant)>>);
 
 #[derive(Debug)]
 pub struct AuthFlow {
     client: ConfiguredClient,
+    endpoints: AuthEndpoints,
     pkce_verifier: PkceCodeVerifier,
     csrf_state: CsrfToken,
     authorize_url: Url,
 }
 
+/// A sign-in started with [`init_device_code`], waiting for the user to enter the code.
+#[derive(Debug)]
+pub struct DeviceCodeFlow {
+    client: DeviceCodeClient,
+    endpoints: AuthEndpoints,
+    details: StandardDeviceAuthorizationResponse,
+}
+
+/// What the user needs to complete a device-code sign-in in any browser.
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceCodePrompt {
+    pub user_code: String,
+    pub verification_uri: String,
+    pub expires_in: u64,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct MicrosoftTokens {
     pub access_token: String,
@@ -60,6 +163,86 @@ pub struct MinecraftTokens {
     pub username: String,
 }
 
+/// How an account signs in.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountType {
+    #[default]
+    Microsoft,
+    /// A local username without authentication, for testing and LAN play.
+    Offline,
+    /// Signed in against a Yggdrasil-compatible server and launched through authlib-injector.
+    Yggdrasil,
+}
+
+/// What an entitlement grants, derived from its name.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EntitlementKind {
+    /// `product_minecraft`, the purchased Java Edition licence.
+    ProductMinecraft,
+    /// `game_minecraft`, the right to launch Java Edition that comes with the product.
+    GameMinecraft,
+    /// A PC or Ultimate Game Pass subscription.
+    GamePass,
+    /// Anything else, e.g. Bedrock or Dungeons.
+    Other,
+}
+
+/// One item of the `entitlements/mcstore` response together with the JWT Mojang signed it
+/// with, so ownership can be re-checked later without another request.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Entitlement {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Entitlements {
+    #[serde(default)]
+    pub items: Vec<Entitlement>,
+    /// Signature over the whole item list.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+}
+
+impl Entitlement {
+    pub fn kind(&self) -> EntitlementKind {
+        match self.name.as_str() {
+            "product_minecraft" => EntitlementKind::ProductMinecraft,
+            "game_minecraft" => EntitlementKind::GameMinecraft,
+            name if name.starts_with("product_game_pass") || name.starts_with("game_pass") => {
+                EntitlementKind::GamePass
+            }
+            _ => EntitlementKind::Other,
+        }
+    }
+}
+
+impl Entitlements {
+    /// Whether any item lets the account play Java Edition.
+    pub fn owns_java_edition(&self) -> bool {
+        self.items
+            .iter()
+            .any(|item| item.kind() != EntitlementKind::Other)
+    }
+
+    /// Whether the game is only available through a Game Pass subscription.
+    pub fn via_game_pass(&self) -> bool {
+        self.items
+            .iter()
+            .all(|item| item.kind() != EntitlementKind::ProductMinecraft)
+            && self
+                .items
+                .iter()
+                .any(|item| item.kind() == EntitlementKind::GamePass)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AccountRecord {
     pub uuid: String,
@@ -70,13 +253,86 @@ pub struct AccountRecord {
     pub minecraft: MinecraftTokens,
     #[serde(default)]
     pub is_active: bool,
+    #[serde(default)]
+    pub account_type: AccountType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub yggdrasil: Option<YggdrasilSession>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entitlements: Option<Entitlements>,
+    /// The account owns the game but has no profile yet; see [`create_profile`].
+    #[serde(default)]
+    pub needs_profile: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Default)]
 struct AccountStore {
     accounts: Vec<AccountRecord>,
 }
 
+/// The on-disk shape of `accounts.json`. Only metadata is written to it; each token of an
+/// account is kept in the secret store under its own [`secret_key`].
+#[derive(Debug, Serialize, Deserialize, Default)]
+struct AccountFile {
+    #[serde(default)]
+    accounts: Vec<StoredAccount>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum StoredAccount {
+    /// Written before secrets moved out of the file; migrated on the next read.
+    Legacy(AccountRecord),
+    Metadata(AccountMetadata),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct AccountMetadata {
+    uuid: String,
+    username: String,
+    obtained_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    microsoft_expires_at: Option<u64>,
+    xbox_uhs: String,
+    minecraft_username: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    minecraft_expires_at: Option<u64>,
+    #[serde(default)]
+    is_active: bool,
+    #[serde(default)]
+    account_type: AccountType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    yggdrasil_server: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    entitlements: Option<Entitlements>,
+    #[serde(default)]
+    needs_profile: bool,
+}
+
+#[derive(Debug, Default)]
+struct AccountSecrets {
+    microsoft_access_token: String,
+    microsoft_refresh_token: String,
+    xbox_user_token: String,
+    xbox_xsts_token: String,
+    minecraft_access_token: String,
+    yggdrasil_client_token: String,
+}
+
+impl AccountSecrets {
+    /// Every token with the name it is stored under. Stored separately because a Windows
+    /// Credential Manager entry holds at most 2560 bytes, less than all tokens together.
+    fn tokens_mut(&mut self) -> [(&'static str, &mut String); 6] {
+        [
+            ("microsoft-access", &mut self.microsoft_access_token),
+            ("refresh", &mut self.microsoft_refresh_token),
+            ("xbox-user", &mut self.xbox_user_token),
+            ("xsts", &mut self.xbox_xsts_token),
+            ("minecraft-access", &mut self.minecraft_access_token),
+            ("client-token", &mut self.yggdrasil_client_token),
+        ]
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct XboxAuthResponse {
     #[serde(rename = "Token")]
@@ -105,6 +361,12 @@ struct XboxXstsResponse {
     display_claims: XboxDisplayClaims,
 }
 
+#[derive(Debug, Deserialize)]
+struct XboxErrorResponse {
+    #[serde(rename = "XErr")]
+    xerr: u64,
+}
+
 #[derive(Debug, Deserialize)]
 struct MinecraftLoginResponse {
     username: String,
@@ -112,18 +374,6 @@ struct MinecraftLoginResponse {
     expires_in: Option<u64>,
 }
 
-#[derive(Debug, Deserialize)]
-struct EntitlementsResponse {
-    #[serde(default)]
-    items: Vec<Value>,
-}
-
-#[derive(Debug, Deserialize)]
-struct MinecraftProfileResponse {
-    id: String,
-    name: String,
-}
-
 impl AuthFlow {
     pub fn authorize_url(&self) -> Url {
         self.authorize_url.clone()
@@ -142,111 +392,446 @@ impl AuthFlow {
             .set_pkce_verifier(self.pkce_verifier)
             .request_async(&http_client)
             .await
-            .map_err(|err| {
-                if let RequestTokenError::ServerResponse(response) = &err {
-                    if response.error() == &BasicErrorResponseType::InvalidClient {
-                        return anyhow!(
-                            "authorization server rejected the client credentials (invalid_client). \
-Please ensure the Azure/Microsoft application is configured as a public client or provide a client secret via the MSA_CLIENT_SECRET environment variable."
-                        );
-                    }
-                }
+            .map_err(token_error)
+            .context("failed to exchange authorization code")?;
+
+        let microsoft = MicrosoftTokens {
+            access_token: token_response.access_token().secret().to_string(),
+            refresh_token: token_response
+                .refresh_token()
+                .ok_or_else(|| anyhow!("missing microsoft refresh token"))?
+                .secret()
+                .to_string(),
+            expires_at: token_response.expires_in().map(timestamp_after),
+        };
+
+        login_with_microsoft(&http_client, &self.endpoints, microsoft).await
+    }
+}
+
+impl DeviceCodeFlow {
+    pub fn prompt(&self) -> DeviceCodePrompt {
+        DeviceCodePrompt {
+            user_code: self.details.user_code().secret().to_string(),
+            verification_uri: self.details.verification_uri().to_string(),
+            expires_in: self.details.expires_in().as_secs(),
+        }
+    }
+
+    /// Polls the token endpoint at the interval the server asked for until the user has
+    /// signed in, then runs the same Xbox Live and Minecraft login as [`AuthFlow::exchange`].
+    pub async fn exchange(self) -> Result<AccountRecord> {
+        let http_client = Client::new();
+
+        let token_response = self
+            .client
+            .exchange_device_access_token(&self.details)
+            .request_async(&http_client, tokio::time::sleep, None)
+            .await
+            .map_err(token_error)
+            .context("failed to complete device code sign-in")?;
+
+        let microsoft = MicrosoftTokens {
+            access_token: token_response.access_token().secret().to_string(),
+            refresh_token: token_response
+                .refresh_token()
+                .ok_or_else(|| anyhow!("missing microsoft refresh token"))?
+                .secret()
+                .to_string(),
+            expires_at: token_response.expires_in().map(timestamp_after),
+        };
 
-                anyhow!(err)
+        login_with_microsoft(&http_client, &self.endpoints, microsoft).await
+    }
+}
+
+impl AccountRecord {
+    /// Whether the Minecraft access token has expired or is about to. The short-lived
+    /// Microsoft access token only matters while refreshing, so it is not considered.
+    pub fn needs_refresh(&self) -> bool {
+        self.account_type == AccountType::Microsoft
+            && self.minecraft.expires_at.is_some_and(|expires_at| {
+                expires_at <= current_timestamp().saturating_add(REFRESH_MARGIN_SECS)
             })
-            .context("failed to exchange authorization code")?;
+    }
+}
+
+/// Redeems the account's Microsoft refresh token and runs the Xbox Live, XSTS and Minecraft
+/// login again, returning the account with fresh tokens. The active flag is carried over.
+pub async fn refresh_tokens(
+    endpoints: &AuthEndpoints,
+    account: &AccountRecord,
+) -> Result<AccountRecord> {
+    if account.account_type != AccountType::Microsoft {
+        return Err(anyhow!("only Microsoft accounts can be refreshed"));
+    }
+
+    let http_client = Client::new();
 
-        let microsoft_access_token = token_response.access_token().secret().to_string();
-        let microsoft_refresh_token = token_response
+    let token_response = oauth_client(endpoints)?
+        .exchange_refresh_token(&RefreshToken::new(account.microsoft.refresh_token.clone()))
+        .request_async(&http_client)
+        .await
+        .map_err(token_error)
+        .context("failed to refresh Microsoft token")?;
+
+    let microsoft = MicrosoftTokens {
+        access_token: token_response.access_token().secret().to_string(),
+        // Microsoft usually rotates the refresh token, but keep the old one if it does not
+        refresh_token: token_response
             .refresh_token()
-            .ok_or_else(|| anyhow!("missing microsoft refresh token"))?
-            .secret()
-            .to_string();
-        let microsoft_expires_at = token_response
-            .expires_in()
-            .map(|duration| timestamp_after(duration));
-
-        let xbox_auth = xbox_live_authenticate(&http_client, &microsoft_access_token).await?;
-        let xbox_uhs = xbox_auth
-            .display_claims
-            .users
-            .get(0)
-            .map(|user| user.uhs.clone())
-            .ok_or_else(|| anyhow!("missing Xbox user hash"))?;
-
-        let xsts = xbox_xsts_authorize(&http_client, &xbox_auth.token).await?;
-        let xsts_token = xsts.token.clone();
-        let xsts_uhs = xsts
-            .display_claims
-            .users
-            .get(0)
-            .map(|user| user.uhs.clone())
-            .unwrap_or_else(|| xbox_uhs.clone());
-
-        let minecraft_login =
-            minecraft_login_with_xbox(&http_client, &xsts_uhs, &xsts_token).await?;
-        let minecraft_access_token = minecraft_login.access_token.clone();
-        let minecraft_expires_at = minecraft_login
-            .expires_in
-            .map(Duration::from_secs)
-            .map(timestamp_after);
-
-        let entitlements = minecraft_entitlements(&http_client, &minecraft_access_token).await?;
-        if entitlements.items.is_empty() {
-            return Err(anyhow!("no Minecraft entitlements found for account"));
-        }
-
-        let profile = minecraft_profile(&http_client, &minecraft_access_token).await?;
-
-        let obtained_at = current_timestamp();
-
-        let account = AccountRecord {
-            uuid: profile.id,
-            username: profile.name.clone(),
-            obtained_at,
-            microsoft: MicrosoftTokens {
-                access_token: microsoft_access_token,
-                refresh_token: microsoft_refresh_token,
-                expires_at: microsoft_expires_at,
-            },
-            xbox: XboxTokens {
-                user_token: xbox_auth.token,
-                xsts_token,
-                uhs: xsts_uhs,
-            },
-            minecraft: MinecraftTokens {
-                access_token: minecraft_access_token,
-                expires_at: minecraft_expires_at,
-                username: minecraft_login.username,
-            },
-            is_active: false,
-        };
+            .map(|token| token.secret().to_string())
+            .unwrap_or_else(|| account.microsoft.refresh_token.clone()),
+        expires_at: token_response.expires_in().map(timestamp_after),
+    };
 
-        Ok(account)
+    let mut refreshed = login_with_microsoft(&http_client, endpoints, microsoft).await?;
+    // a pending account may have been given a profile elsewhere in the meantime
+    if refreshed.uuid != account.uuid && !account.needs_profile {
+        return Err(anyhow!(
+            "refreshed tokens belong to profile {} instead of {}",
+            refreshed.uuid,
+            account.uuid
+        ));
     }
+
+    refreshed.is_active = account.is_active;
+    Ok(refreshed)
 }
 
-pub fn init() -> Result<AuthFlow> {
-    let mut client = BasicClient::new(ClientId::new(MSA_CLIENT_ID.to_string()))
-        .set_auth_uri(AuthUrl::new(AUTHORIZE_URL.to_string()).expect("valid authorize url"))
-        .set_auth_type(AuthType::RequestBody)
-        .set_token_uri(TokenUrl::new(TOKEN_URL.to_string()).expect("valid token url"))
-        .set_redirect_uri(RedirectUrl::new(REDIRECT_URL.to_string()).expect("valid redirect url"));
+/// Creates an offline account for `username`, identified like vanilla does for offline
+/// players so worlds and server data line up with other launchers.
+pub fn offline_account(username: &str) -> Result<AccountRecord> {
+    let username = username.trim();
+    if !player_lookup::is_possible_name(username) {
+        return Err(anyhow!(
+            "offline usernames must be 1-16 characters of letters, digits and underscores"
+        ));
+    }
 
-    if let Some(secret) = env::var("MSA_CLIENT_SECRET")
-        .or_else(|_| env::var("DECENT_MSA_CLIENT_SECRET"))
-        .ok()
-        .and_then(|value| {
-            let trimmed = value.trim().to_owned();
-            if trimmed.is_empty() {
-                None
-            } else {
-                Some(trimmed)
+    Ok(AccountRecord {
+        uuid: offline_uuid(username),
+        username: username.to_string(),
+        obtained_at: current_timestamp(),
+        microsoft: MicrosoftTokens {
+            access_token: String::new(),
+            refresh_token: String::new(),
+            expires_at: None,
+        },
+        xbox: XboxTokens {
+            user_token: String::new(),
+            xsts_token: String::new(),
+            uhs: String::new(),
+        },
+        minecraft: MinecraftTokens {
+            access_token: String::new(),
+            expires_at: None,
+            username: username.to_string(),
+        },
+        is_active: false,
+        account_type: AccountType::Offline,
+        yggdrasil: None,
+        entitlements: None,
+        needs_profile: false,
+    })
+}
+
+/// Java's `UUID.nameUUIDFromBytes("OfflinePlayer:<name>")`: an MD5 based version 3 UUID
+/// without a namespace, in the undashed form Mojang's APIs use.
+pub fn offline_uuid(username: &str) -> String {
+    let mut bytes: [u8; 16] = Md5::digest(format!("OfflinePlayer:{}", username)).into();
+    bytes[6] = (bytes[6] & 0x0f) | 0x30;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Refreshes the stored account `uuid` and writes the new tokens to `accounts.json`.
+pub async fn refresh_account<R: Runtime>(app: &AppHandle<R>, uuid: &str) -> Result<AccountRecord> {
+    let lock = app.state::<RefreshLocks>().account(uuid);
+    let _guard = lock.lock().await;
+
+    refresh_stored(app, stored_account(app, uuid)?).await
+}
+
+/// Refreshes `account`, read from the store, without taking its lock.
+async fn refresh_stored<R: Runtime>(
+    app: &AppHandle<R>,
+    account: AccountRecord,
+) -> Result<AccountRecord> {
+    info!("refreshing tokens for account {}", account.username);
+
+    let refreshed = match account.account_type {
+        AccountType::Microsoft => refresh_tokens(&AuthEndpoints::from_env(), &account).await?,
+        AccountType::Yggdrasil => yggdrasil::refresh(&Client::new(), &account).await?,
+        AccountType::Offline => return Ok(account),
+    };
+    replace_account(app, &account.uuid, &refreshed)
+        .context("failed to persist refreshed account")?;
+
+    Ok(refreshed)
+}
+
+/// Creates the Java Edition profile of an account that owns the game but has none yet,
+/// replacing the pending account with one for the new profile.
+pub async fn create_profile<R: Runtime>(
+    app: &AppHandle<R>,
+    uuid: &str,
+    name: &str,
+) -> Result<AccountRecord> {
+    let account = fresh_account(app, uuid).await?;
+    if !account.needs_profile {
+        return Err(anyhow!(
+            "this account already has the profile {}",
+            account.username
+        ));
+    }
+
+    let name = checked_profile_name(name)?;
+    let profile = MinecraftServices::from_env()
+        .create_profile(&account.minecraft.access_token, name)
+        .await?;
+
+    info!(
+        "created profile {} for account {}",
+        profile.name, account.uuid
+    );
+
+    let mut created = account.clone();
+    created.uuid = profile.id;
+    created.username = profile.name;
+    created.needs_profile = false;
+    replace_account(app, &account.uuid, &created).context("failed to persist the new profile")?;
+
+    Ok(created)
+}
+
+/// Whether `name` is free to be taken by the profile of account `uuid`.
+pub async fn profile_name_availability<R: Runtime>(
+    app: &AppHandle<R>,
+    uuid: &str,
+    name: &str,
+) -> Result<NameAvailability> {
+    let name = name.trim();
+    if !is_valid_profile_name(name) {
+        return Ok(NameAvailability::NotAllowed);
+    }
+
+    let account = microsoft_account(app, uuid).await?;
+    MinecraftServices::from_env()
+        .name_availability(&account.minecraft.access_token, name)
+        .await
+}
+
+pub async fn name_change_info<R: Runtime>(
+    app: &AppHandle<R>,
+    uuid: &str,
+) -> Result<NameChangeInfo> {
+    let account = microsoft_account(app, uuid).await?;
+    MinecraftServices::from_env()
+        .name_change_info(&account.minecraft.access_token)
+        .await
+}
+
+/// Renames the profile of account `uuid` once the cooldown since the last change has passed,
+/// and stores the new name.
+pub async fn change_profile_name<R: Runtime>(
+    app: &AppHandle<R>,
+    uuid: &str,
+    name: &str,
+) -> Result<AccountRecord> {
+    let account = microsoft_account(app, uuid).await?;
+    let name = checked_profile_name(name)?;
+    let services = MinecraftServices::from_env();
+
+    let info = services
+        .name_change_info(&account.minecraft.access_token)
+        .await?;
+    if !info.name_change_allowed {
+        return Err(AuthError::NameChangeCooldown {
+            next_change_at: info.next_change_at,
+        }
+        .into());
+    }
+
+    let profile = services
+        .change_name(&account.minecraft.access_token, name)
+        .await?;
+
+    info!("renamed profile {} to {}", account.username, profile.name);
+
+    let mut renamed = account;
+    renamed.username = profile.name;
+    save_account(app, &renamed).context("failed to persist the new profile name")?;
+
+    Ok(renamed)
+}
+
+/// Profile names follow the same rules as usernames: 3-16 letters, digits and underscores.
+pub fn is_valid_profile_name(name: &str) -> bool {
+    (3..=16).contains(&name.len()) && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn checked_profile_name(name: &str) -> Result<&str> {
+    let name = name.trim();
+    if !is_valid_profile_name(name) {
+        return Err(AuthError::ProfileName {
+            name: name.to_string(),
+            status: "NOT_ALLOWED".to_string(),
+        }
+        .into());
+    }
+    Ok(name)
+}
+
+/// Looks up the stored account `uuid`, refreshing its tokens if they are about to expire.
+pub async fn fresh_account<R: Runtime>(app: &AppHandle<R>, uuid: &str) -> Result<AccountRecord> {
+    ensure_fresh(app, stored_account(app, uuid)?).await
+}
+
+fn stored_account<R: Runtime>(app: &AppHandle<R>, uuid: &str) -> Result<AccountRecord> {
+    read_accounts(app)?
+        .into_iter()
+        .find(|account| account.uuid == uuid)
+        .ok_or_else(|| anyhow!("account {} not found", uuid))
+}
+
+/// The active account, refreshed, for calls that need a Microsoft account with a profile.
+pub async fn active_microsoft_account<R: Runtime>(app: &AppHandle<R>) -> Result<AccountRecord> {
+    let uuid = read_accounts(app)?
+        .into_iter()
+        .find(|account| account.is_active)
+        .map(|account| account.uuid)
+        .ok_or_else(|| anyhow!("no active account selected"))?;
+
+    microsoft_account(app, &uuid).await
+}
+
+/// Like [`fresh_account`], for calls that need a Microsoft account with a profile.
+async fn microsoft_account<R: Runtime>(app: &AppHandle<R>, uuid: &str) -> Result<AccountRecord> {
+    let account = fresh_account(app, uuid).await?;
+    if account.account_type != AccountType::Microsoft {
+        return Err(anyhow!(
+            "only Microsoft accounts have a Minecraft profile to manage"
+        ));
+    }
+    if account.needs_profile {
+        return Err(AuthError::NoProfile.into());
+    }
+    Ok(account)
+}
+
+/// Returns `account` as is while its tokens are still valid, refreshing it otherwise.
+/// Yggdrasil tokens carry no expiry, so the server is asked whether it still accepts them,
+/// at most once per [`VALIDATION_TTL`].
+pub async fn ensure_fresh<R: Runtime>(
+    app: &AppHandle<R>,
+    account: AccountRecord,
+) -> Result<AccountRecord> {
+    let valid = match account.account_type {
+        AccountType::Microsoft => !account.needs_refresh(),
+        AccountType::Offline => true,
+        AccountType::Yggdrasil => {
+            let client = Client::new();
+            app.state::<ValidatedTokens>()
+                .check(&account, || yggdrasil::validate(&client, &account))
+                .await?
+        }
+    };
+
+    if valid {
+        return Ok(account);
+    }
+
+    app.state::<RefreshLocks>()
+        .refresh_once(
+            &account,
+            || stored_account(app, &account.uuid),
+            |stored| refresh_stored(app, stored),
+        )
+        .await
+}
+
+impl ValidatedTokens {
+    /// Returns true without calling `validate` while the account's token was confirmed less
+    /// than [`VALIDATION_TTL`] ago.
+    async fn check<F, Fut>(&self, account: &AccountRecord, validate: F) -> Result<bool>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<bool>>,
+    {
+        let token = &account.minecraft.access_token;
+        if let Some((validated, at)) = self.lock().get(&account.uuid) {
+            if validated == token && at.elapsed() < VALIDATION_TTL {
+                return Ok(true);
             }
-        })
+        }
+
+        let valid = validate().await?;
+        let mut tokens = self.lock();
+        if valid {
+            tokens.insert(account.uuid.clone(), (token.clone(), Instant::now()));
+        } else {
+            tokens.remove(&account.uuid);
+        }
+        Ok(valid)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, (String, Instant)>> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl RefreshLocks {
+    fn account(&self, uuid: &str) -> Arc<tokio::sync::Mutex<()>> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .entry(uuid.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// Runs `refresh` on the stored copy of `account`, read through `stored` once the
+    /// account's lock is held, unless another caller replaced its tokens in the meantime.
+    async fn refresh_once<S, F, Fut>(
+        &self,
+        account: &AccountRecord,
+        stored: S,
+        refresh: F,
+    ) -> Result<AccountRecord>
+    where
+        S: FnOnce() -> Result<AccountRecord>,
+        F: FnOnce(AccountRecord) -> Fut,
+        Fut: Future<Output = Result<AccountRecord>>,
     {
-        client = client.set_client_secret(ClientSecret::new(secret));
+        let lock = self.account(&account.uuid);
+        let _guard = lock.lock().await;
+
+        let stored = stored()?;
+        if stored.minecraft.access_token != account.minecraft.access_token {
+            debug!("account {} was refreshed by another request", account.uuid);
+            return Ok(stored);
+        }
+
+        refresh(stored).await
     }
+}
+
+/// Starts an authorization code flow for the embedded auth window, which is redirected to
+/// [`REDIRECT_URL`].
+pub fn init() -> Result<AuthFlow> {
+    init_with_redirect(REDIRECT_URL)
+}
+
+/// Starts an authorization code flow with PKCE that redirects to `redirect_url`, e.g. a
+/// loopback listener receiving the code from the system browser.
+pub fn init_with_redirect(redirect_url: &str) -> Result<AuthFlow> {
+    let endpoints = AuthEndpoints::from_env();
+    let client = oauth_client(&endpoints)?.set_redirect_uri(
+        RedirectUrl::new(redirect_url.to_string()).context("invalid redirect url")?,
+    );
 
     let (pkce_code_challenge, pkce_code_verifier) = PkceCodeChallenge::new_random_sha256();
 
@@ -265,12 +850,196 @@ pub fn init() -> Result<AuthFlow> {
 
     Ok(AuthFlow {
         client,
+        endpoints,
         pkce_verifier: pkce_code_verifier,
         csrf_state,
         authorize_url,
     })
 }
 
+/// Requests a device code for signing in on another device or in the user's own browser.
+pub async fn init_device_code() -> Result<DeviceCodeFlow> {
+    let endpoints = AuthEndpoints::from_env();
+    let client = oauth_client(&endpoints)?.set_device_authorization_url(
+        DeviceAuthorizationUrl::new(endpoints.device_code_url.clone())
+            .context("invalid device code url")?,
+    );
+
+    let details: StandardDeviceAuthorizationResponse = client
+        .exchange_device_code()
+        .add_scope(Scope::new("XboxLive.signin".to_string()))
+        .add_scope(Scope::new("offline_access".to_string()))
+        .request_async(&Client::new())
+        .await
+        .map_err(token_error)
+        .context("failed to request a device code")?;
+
+    debug!(
+        "device code issued, verification uri: {}",
+        details.verification_uri().as_str()
+    );
+
+    Ok(DeviceCodeFlow {
+        client,
+        endpoints,
+        details,
+    })
+}
+
+fn oauth_client(endpoints: &AuthEndpoints) -> Result<ConfiguredClient> {
+    let mut client = BasicClient::new(ClientId::new(MSA_CLIENT_ID.to_string()))
+        .set_auth_uri(
+            AuthUrl::new(endpoints.authorize_url.clone()).context("invalid authorize url")?,
+        )
+        .set_auth_type(AuthType::RequestBody)
+        .set_token_uri(TokenUrl::new(endpoints.token_url.clone()).context("invalid token url")?);
+
+    if let Some(secret) = env::var("MSA_CLIENT_SECRET")
+        .or_else(|_| env::var("DECENT_MSA_CLIENT_SECRET"))
+        .ok()
+        .and_then(|value| {
+            let trimmed = value.trim().to_owned();
+            if trimmed.is_empty() {
+                None
+            } else {
+                Some(trimmed)
+            }
+        })
+    {
+        client = client.set_client_secret(ClientSecret::new(secret));
+    }
+
+    Ok(client)
+}
+
+/// Turns a failed token request into an [`AuthError`], explaining the error codes users are
+/// likely to run into.
+fn token_error<RE, T>(err: RequestTokenError<RE, StandardErrorResponse<T>>) -> anyhow::Error
+where
+    RE: std::error::Error + Send + Sync + 'static,
+    T: ErrorResponseType + AsRef<str> + std::fmt::Display + Send + Sync + 'static,
+{
+    match err {
+        RequestTokenError::ServerResponse(response) => {
+            let error = response.error().as_ref().to_string();
+            let message = match error.as_str() {
+                "invalid_client" => "the authorization server rejected the client credentials. \
+Please ensure the Azure/Microsoft application is configured as a public client or provide a client secret via the MSA_CLIENT_SECRET environment variable."
+                    .to_string(),
+                "invalid_grant" => {
+                    "the sign-in has expired or was revoked, please sign in again".to_string()
+                }
+                "expired_token" => {
+                    "the device code expired before sign-in was completed".to_string()
+                }
+                "access_denied" => "sign-in was declined".to_string(),
+                _ => response
+                    .error_description()
+                    .cloned()
+                    .unwrap_or_else(|| "no further details were given".to_string()),
+            };
+
+            AuthError::OAuth { error, message }.into()
+        }
+        RequestTokenError::Request(err) => AuthError::Network {
+            service: "Microsoft sign-in".to_string(),
+            message: err.to_string(),
+        }
+        .into(),
+        err => anyhow!(err),
+    }
+}
+
+/// Runs the Xbox Live → XSTS → Minecraft chain for a Microsoft access token and fetches the
+/// profile the resulting Minecraft token belongs to.
+async fn login_with_microsoft(
+    http_client: &Client,
+    endpoints: &AuthEndpoints,
+    microsoft: MicrosoftTokens,
+) -> Result<AccountRecord> {
+    let xbox_auth = xbox_live_authenticate(http_client, endpoints, &microsoft.access_token)
+        .await
+        .map_err(|err| AuthError::at("Xbox Live", err))?;
+    let xbox_uhs = xbox_auth
+        .display_claims
+        .users
+        .first()
+        .map(|user| user.uhs.clone())
+        .ok_or_else(|| anyhow!("missing Xbox user hash"))?;
+
+    let xsts = xbox_xsts_authorize(http_client, endpoints, &xbox_auth.token)
+        .await
+        .map_err(|err| AuthError::at("Xbox XSTS", err))?;
+    let xsts_token = xsts.token.clone();
+    let xsts_uhs = xsts
+        .display_claims
+        .users
+        .first()
+        .map(|user| user.uhs.clone())
+        .unwrap_or_else(|| xbox_uhs.clone());
+
+    let minecraft_login = minecraft_login_with_xbox(http_client, endpoints, &xsts_uhs, &xsts_token)
+        .await
+        .map_err(|err| AuthError::at("Minecraft login", err))?;
+    let minecraft_access_token = minecraft_login.access_token.clone();
+    let minecraft_expires_at = minecraft_login
+        .expires_in
+        .map(Duration::from_secs)
+        .map(timestamp_after);
+
+    let services = MinecraftServices::new(
+        http_client.clone(),
+        endpoints.minecraft_services_url.clone(),
+    );
+    let entitlements = services
+        .entitlements(&minecraft_access_token)
+        .await
+        .map_err(|err| AuthError::at("Minecraft entitlements", err))?;
+    let profile = services
+        .profile(&minecraft_access_token)
+        .await
+        .map_err(|err| AuthError::at("Minecraft profile", err))?;
+
+    // Game Pass licences are not always listed, so an existing profile is proof enough
+    let (uuid, username, needs_profile) = match profile {
+        Some(profile) => (profile.id, profile.name, false),
+        None if entitlements.owns_java_edition() => (
+            format!("{}{}", PENDING_PROFILE_PREFIX, xsts_uhs),
+            String::new(),
+            true,
+        ),
+        None => return Err(AuthError::NoEntitlement.into()),
+    };
+
+    if entitlements.via_game_pass() {
+        debug!("account {} plays through Game Pass", uuid);
+    }
+
+    let obtained_at = current_timestamp();
+
+    Ok(AccountRecord {
+        uuid,
+        username,
+        obtained_at,
+        microsoft,
+        xbox: XboxTokens {
+            user_token: xbox_auth.token,
+            xsts_token,
+            uhs: xsts_uhs,
+        },
+        minecraft: MinecraftTokens {
+            access_token: minecraft_access_token,
+            expires_at: minecraft_expires_at,
+            username: minecraft_login.username,
+        },
+        is_active: false,
+        account_type: AccountType::Microsoft,
+        yggdrasil: None,
+        entitlements: Some(entitlements),
+        needs_profile,
+    })
+}
+
 pub fn save_account<R: Runtime>(app: &AppHandle<R>, account: &AccountRecord) -> Result<()> {
     let mut accounts = read_accounts(app)?;
 
@@ -289,12 +1058,33 @@ pub fn save_account<R: Runtime>(app: &AppHandle<R>, account: &AccountRecord) ->
     write_accounts(app, &accounts)
 }
 
-pub fn read_accounts<R: Runtime>(app: &AppHandle<R>) -> Result<Vec<AccountRecord>> {
-    let mut store = load_account_store(app)?;
-    if normalize_active_flags(&mut store.accounts) {
-        persist_account_store(app, &store)?;
+/// Stores `account` in place of the account `previous_uuid`, for when refreshing or creating
+/// a profile changed its UUID. Any other entry for the new UUID is dropped.
+fn replace_account<R: Runtime>(
+    app: &AppHandle<R>,
+    previous_uuid: &str,
+    account: &AccountRecord,
+) -> Result<()> {
+    if previous_uuid == account.uuid {
+        return save_account(app, account);
     }
-    Ok(store.accounts)
+
+    let mut accounts = read_accounts(app)?;
+    accounts.retain(|existing| existing.uuid != account.uuid);
+
+    match accounts
+        .iter_mut()
+        .find(|existing| existing.uuid == previous_uuid)
+    {
+        Some(existing) => {
+            let was_active = existing.is_active;
+            *existing = account.clone();
+            existing.is_active = was_active;
+        }
+        None => accounts.push(account.clone()),
+    }
+
+    write_accounts(app, &accounts)
 }
 
 pub fn write_accounts<R: Runtime>(app: &AppHandle<R>, accounts: &[AccountRecord]) -> Result<()> {
@@ -305,28 +1095,206 @@ pub fn write_accounts<R: Runtime>(app: &AppHandle<R>, accounts: &[AccountRecord]
     persist_account_store(app, &store)
 }
 
-fn load_account_store<R: Runtime>(app: &AppHandle<R>) -> Result<AccountStore> {
-    let path = account_store_path(app)?;
+pub fn read_accounts<R: Runtime>(app: &AppHandle<R>) -> Result<Vec<AccountRecord>> {
+    let (mut store, migrated) = load_account_store(app)?;
+    if normalize_active_flags(&mut store.accounts) || migrated {
+        persist_account_store(app, &store)?;
+    }
+    Ok(store.accounts)
+}
+
+fn load_account_store<R: Runtime>(app: &AppHandle<R>) -> Result<(AccountStore, bool)> {
+    load_account_store_from(&account_store_path(app)?, app.state::<Secrets>().store())
+}
+
+fn persist_account_store<R: Runtime>(app: &AppHandle<R>, store: &AccountStore) -> Result<()> {
+    persist_account_store_to(
+        &account_store_path(app)?,
+        app.state::<Secrets>().store(),
+        store,
+    )
+}
+
+/// Loads the accounts, joining the metadata in the accounts file at `path` with the secrets.
+/// Also reports whether the file still held plaintext tokens and has to be rewritten.
+fn load_account_store_from(path: &Path, secrets: &dyn SecretStore) -> Result<(AccountStore, bool)> {
+    let file = load_account_file(path)?;
+    let mut store = AccountStore::default();
+    let mut migrated = false;
+
+    for stored in file.accounts {
+        match stored {
+            StoredAccount::Legacy(account) => {
+                info!(
+                    "moving tokens of account {} into the secret store",
+                    account.username
+                );
+                migrated = true;
+                store.accounts.push(account);
+            }
+            StoredAccount::Metadata(metadata) if metadata.account_type == AccountType::Offline => {
+                store
+                    .accounts
+                    .push(join_account(metadata, AccountSecrets::default()));
+            }
+            StoredAccount::Metadata(metadata) => {
+                let mut account_secrets = AccountSecrets::default();
+                let mut found = false;
+                for (name, token) in account_secrets.tokens_mut() {
+                    if let Some(value) = secrets.get(&secret_key(&metadata.uuid, name))? {
+                        *token = value;
+                        found = true;
+                    }
+                }
+                if !found {
+                    // without tokens the account stays listed but has to sign in again
+                    warn!("no stored secrets for account {}", metadata.uuid);
+                }
+                store.accounts.push(join_account(metadata, account_secrets));
+            }
+        }
+    }
+
+    Ok((store, migrated))
+}
 
+fn load_account_file(path: &Path) -> Result<AccountFile> {
     if !path.exists() {
-        return Ok(AccountStore::default());
+        return Ok(AccountFile::default());
     }
 
-    let contents = fs::read_to_string(&path).context("failed to read existing accounts.json")?;
+    let contents = fs::read_to_string(path).context("failed to read existing accounts.json")?;
     if contents.trim().is_empty() {
-        return Ok(AccountStore::default());
+        return Ok(AccountFile::default());
     }
 
-    serde_json::from_str::<AccountStore>(&contents)
-        .context("failed to parse existing accounts.json")
+    serde_json::from_str::<AccountFile>(&contents).context("failed to parse existing accounts.json")
 }
 
-fn persist_account_store<R: Runtime>(app: &AppHandle<R>, store: &AccountStore) -> Result<()> {
-    let path = account_store_path(app)?;
-    let serialized = serde_json::to_string_pretty(store).context("failed to serialize accounts")?;
+/// Writes the secrets of every account to the secret store before replacing the accounts file
+/// at `path`, and drops the secrets of accounts that are no longer listed.
+fn persist_account_store_to(
+    path: &Path,
+    secrets: &dyn SecretStore,
+    store: &AccountStore,
+) -> Result<()> {
+    let previous = load_account_file(path).unwrap_or_default();
+    let mut file = AccountFile::default();
+
+    for account in &store.accounts {
+        let (metadata, mut account_secrets) = split_account(account);
+        if account.account_type == AccountType::Offline {
+            file.accounts.push(StoredAccount::Metadata(metadata));
+            continue;
+        }
+
+        for (name, token) in account_secrets.tokens_mut() {
+            let key = secret_key(&account.uuid, name);
+            if token.is_empty() {
+                secrets.delete(&key)?;
+            } else {
+                secrets.set(&key, token)?;
+            }
+        }
+        file.accounts.push(StoredAccount::Metadata(metadata));
+    }
+
+    for stored in previous.accounts {
+        let uuid = match &stored {
+            StoredAccount::Legacy(account) => &account.uuid,
+            StoredAccount::Metadata(metadata) => &metadata.uuid,
+        };
+        if !store.accounts.iter().any(|account| &account.uuid == uuid) {
+            for (name, _) in AccountSecrets::default().tokens_mut() {
+                secrets.delete(&secret_key(uuid, name))?;
+            }
+        }
+    }
+
+    let serialized = serde_json::to_string_pretty(&file).context("failed to serialize accounts")?;
     fs::write(path, serialized).context("failed to write accounts.json")
 }
 
+fn secret_key(uuid: &str, token: &str) -> String {
+    format!("account:{}:{}", uuid, token)
+}
+
+fn split_account(account: &AccountRecord) -> (AccountMetadata, AccountSecrets) {
+    (
+        AccountMetadata {
+            uuid: account.uuid.clone(),
+            username: account.username.clone(),
+            obtained_at: account.obtained_at,
+            microsoft_expires_at: account.microsoft.expires_at,
+            xbox_uhs: account.xbox.uhs.clone(),
+            minecraft_username: account.minecraft.username.clone(),
+            minecraft_expires_at: account.minecraft.expires_at,
+            is_active: account.is_active,
+            account_type: account.account_type,
+            yggdrasil_server: account
+                .yggdrasil
+                .as_ref()
+                .map(|session| session.server_url.clone()),
+            entitlements: account.entitlements.clone(),
+            needs_profile: account.needs_profile,
+        },
+        AccountSecrets {
+            microsoft_access_token: account.microsoft.access_token.clone(),
+            microsoft_refresh_token: account.microsoft.refresh_token.clone(),
+            xbox_user_token: account.xbox.user_token.clone(),
+            xbox_xsts_token: account.xbox.xsts_token.clone(),
+            minecraft_access_token: account.minecraft.access_token.clone(),
+            yggdrasil_client_token: account
+                .yggdrasil
+                .as_ref()
+                .map(|session| session.client_token.clone())
+                .unwrap_or_default(),
+        },
+    )
+}
+
+fn join_account(metadata: AccountMetadata, secrets: AccountSecrets) -> AccountRecord {
+    // an account without a token must be refreshed before it can be used
+    let minecraft_expires_at = if metadata.account_type == AccountType::Microsoft
+        && secrets.minecraft_access_token.is_empty()
+    {
+        Some(0)
+    } else {
+        metadata.minecraft_expires_at
+    };
+
+    AccountRecord {
+        uuid: metadata.uuid,
+        username: metadata.username,
+        obtained_at: metadata.obtained_at,
+        microsoft: MicrosoftTokens {
+            access_token: secrets.microsoft_access_token,
+            refresh_token: secrets.microsoft_refresh_token,
+            expires_at: metadata.microsoft_expires_at,
+        },
+        xbox: XboxTokens {
+            user_token: secrets.xbox_user_token,
+            xsts_token: secrets.xbox_xsts_token,
+            uhs: metadata.xbox_uhs,
+        },
+        minecraft: MinecraftTokens {
+            access_token: secrets.minecraft_access_token,
+            expires_at: minecraft_expires_at,
+            username: metadata.minecraft_username,
+        },
+        is_active: metadata.is_active,
+        account_type: metadata.account_type,
+        yggdrasil: metadata
+            .yggdrasil_server
+            .map(|server_url| YggdrasilSession {
+                server_url,
+                client_token: secrets.yggdrasil_client_token,
+            }),
+        entitlements: metadata.entitlements,
+        needs_profile: metadata.needs_profile,
+    }
+}
+
 fn account_store_path<R: Runtime>(app: &AppHandle<R>) -> Result<PathBuf> {
     let mut data_dir = app
         .path()
@@ -362,9 +1330,13 @@ fn normalize_active_flags(accounts: &mut [AccountRecord]) -> bool {
     changed
 }
 
-async fn xbox_live_authenticate(client: &Client, access_token: &str) -> Result<XboxAuthResponse> {
+async fn xbox_live_authenticate(
+    client: &Client,
+    endpoints: &AuthEndpoints,
+    access_token: &str,
+) -> Result<XboxAuthResponse> {
     let response = client
-        .post(XBOX_USER_AUTH_URL)
+        .post(&endpoints.xbox_user_auth_url)
         .json(&json!({
             "Properties": {
                 "AuthMethod": "RPS",
@@ -388,9 +1360,13 @@ async fn xbox_live_authenticate(client: &Client, access_token: &str) -> Result<X
     Ok(parsed)
 }
 
-async fn xbox_xsts_authorize(client: &Client, user_token: &str) -> Result<XboxXstsResponse> {
+async fn xbox_xsts_authorize(
+    client: &Client,
+    endpoints: &AuthEndpoints,
+    user_token: &str,
+) -> Result<XboxXstsResponse> {
     let response = client
-        .post(XBOX_XSTS_URL)
+        .post(&endpoints.xbox_xsts_url)
         .json(&json!({
             "Properties": {
                 "SandboxId": "RETAIL",
@@ -401,7 +1377,18 @@ async fn xbox_xsts_authorize(client: &Client, user_token: &str) -> Result<XboxXs
         }))
         .send()
         .await
-        .context("failed to request Xbox XSTS token")?
+        .context("failed to request Xbox XSTS token")?;
+
+    // XSTS explains why an account cannot sign in through the XErr code of a 401
+    if response.status() == StatusCode::UNAUTHORIZED {
+        let error = response
+            .json::<XboxErrorResponse>()
+            .await
+            .context("failed to parse Xbox XSTS error response")?;
+        return Err(AuthError::Xbox { code: error.xerr }.into());
+    }
+
+    let response = response
         .error_for_status()
         .context("Xbox XSTS token request returned non-success status")?;
 
@@ -415,13 +1402,14 @@ async fn xbox_xsts_authorize(client: &Client, user_token: &str) -> Result<XboxXs
 
 async fn minecraft_login_with_xbox(
     client: &Client,
+    endpoints: &AuthEndpoints,
     uhs: &str,
     xsts_token: &str,
 ) -> Result<MinecraftLoginResponse> {
     let identity_token = format!("XBL3.0 x={};{}", uhs, xsts_token);
 
     let response = client
-        .post(MINECRAFT_LOGIN_URL)
+        .post(endpoints.minecraft_url("authentication/login_with_xbox"))
         .json(&json!({
             "identityToken": identity_token,
             "ensureLegacyEnabled": true
@@ -440,49 +1428,7 @@ async fn minecraft_login_with_xbox(
     Ok(parsed)
 }
 
-async fn minecraft_entitlements(
-    client: &Client,
-    access_token: &str,
-) -> Result<EntitlementsResponse> {
-    let response = client
-        .get(MINECRAFT_ENTITLEMENTS_URL)
-        .bearer_auth(access_token)
-        .send()
-        .await
-        .context("failed to fetch Minecraft entitlements")?
-        .error_for_status()
-        .context("Minecraft entitlements request returned non-success status")?;
-
-    let parsed = response
-        .json::<EntitlementsResponse>()
-        .await
-        .context("failed to parse Minecraft entitlements response")?;
-
-    Ok(parsed)
-}
-
-async fn minecraft_profile(
-    client: &Client,
-    access_token: &str,
-) -> Result<MinecraftProfileResponse> {
-    let response = client
-        .get(MINECRAFT_PROFILE_URL)
-        .bearer_auth(access_token)
-        .send()
-        .await
-        .context("failed to fetch Minecraft profile")?
-        .error_for_status()
-        .context("Minecraft profile request returned non-success status")?;
-
-    let parsed = response
-        .json::<MinecraftProfileResponse>()
-        .await
-        .context("failed to parse Minecraft profile response")?;
-
-    Ok(parsed)
-}
-
-fn current_timestamp() -> u64 {
+pub(crate) fn current_timestamp() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap_or_else(|_| Duration::from_secs(0))
@@ -492,3 +1438,571 @@ fn current_timestamp() -> u64 {
 fn timestamp_after(duration: Duration) -> u64 {
     current_timestamp().saturating_add(duration.as_secs())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::secret_store::{FallbackStore, MemoryStore};
+    use crate::utils::test_server::{block_on, Request, Response, TestServer};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const PROFILE_ID: &str = "069a79f444e94726a5befca90e38aaf5";
+
+    fn account(access_token: &str) -> AccountRecord {
+        AccountRecord {
+            uuid: PROFILE_ID.to_string(),
+            username: "Notch".to_string(),
+            obtained_at: 0,
+            microsoft: MicrosoftTokens {
+                access_token: "ms-old".to_string(),
+                refresh_token: "refresh-old".to_string(),
+                expires_at: Some(0),
+            },
+            xbox: XboxTokens {
+                user_token: "xbl-old".to_string(),
+                xsts_token: "xsts-old".to_string(),
+                uhs: "1234".to_string(),
+            },
+            minecraft: MinecraftTokens {
+                access_token: access_token.to_string(),
+                expires_at: Some(0),
+                username: "00000000-0000-0000-0000-000000000000".to_string(),
+            },
+            is_active: true,
+            account_type: AccountType::Microsoft,
+            yggdrasil: None,
+            entitlements: None,
+            needs_profile: false,
+        }
+    }
+
+    fn endpoints(server: &TestServer) -> AuthEndpoints {
+        AuthEndpoints {
+            authorize_url: format!("{}/oauth/authorize", server.url()),
+            token_url: format!("{}/oauth/token", server.url()),
+            device_code_url: format!("{}/oauth/devicecode", server.url()),
+            xbox_user_auth_url: format!("{}/xbox/user", server.url()),
+            xbox_xsts_url: format!("{}/xbox/xsts", server.url()),
+            minecraft_services_url: format!("{}/mc", server.url()),
+        }
+    }
+
+    /// Answers the whole sign-in chain like the production services do for `profile_id`.
+    fn services(profile_id: &'static str, token: serde_json::Value) -> TestServer {
+        TestServer::start(move |request: &Request| service_response(profile_id, &token, request))
+    }
+
+    fn service_response(
+        profile_id: &'static str,
+        token: &serde_json::Value,
+        request: &Request,
+    ) -> Response {
+        let path = request.path.as_str();
+        match path {
+            "/oauth/token" => Response::json(200, token.clone()),
+            "/xbox/user" | "/xbox/xsts" => Response::json(
+                200,
+                serde_json::json!({
+                    "Token": if path == "/xbox/user" { "xbl-new" } else { "xsts-new" },
+                    "DisplayClaims": { "xui": [{ "uhs": "1234" }] }
+                }),
+            ),
+            "/mc/authentication/login_with_xbox" => Response::json(
+                200,
+                serde_json::json!({
+                    "username": "00000000-0000-0000-0000-000000000000",
+                    "access_token": "mc-new",
+                    "expires_in": 86400
+                }),
+            ),
+            "/mc/entitlements/mcstore" => Response::json(
+                200,
+                serde_json::json!({
+                    "items": [{ "name": "product_minecraft" }, { "name": "game_minecraft" }]
+                }),
+            ),
+            "/mc/minecraft/profile" => Response::json(
+                200,
+                serde_json::json!({ "id": profile_id, "name": "Notch" }),
+            ),
+            _ => Response::status(404),
+        }
+    }
+
+    fn rotated_token() -> serde_json::Value {
+        serde_json::json!({
+            "access_token": "ms-new",
+            "token_type": "Bearer",
+            "expires_in": 3600,
+            "refresh_token": "refresh-new"
+        })
+    }
+
+    #[test]
+    fn refresh_runs_the_sign_in_chain_again() {
+        let server = services(PROFILE_ID, rotated_token());
+
+        let refreshed = block_on(refresh_tokens(&endpoints(&server), &account("mc-old"))).unwrap();
+
+        assert_eq!(refreshed.uuid, PROFILE_ID);
+        assert!(refreshed.is_active);
+        assert_eq!(refreshed.microsoft.access_token, "ms-new");
+        assert_eq!(refreshed.microsoft.refresh_token, "refresh-new");
+        assert_eq!(refreshed.xbox.user_token, "xbl-new");
+        assert_eq!(refreshed.xbox.xsts_token, "xsts-new");
+        assert_eq!(refreshed.minecraft.access_token, "mc-new");
+        assert!(!refreshed.needs_refresh());
+
+        let token_request = &server.requests_to("/oauth/token")[0];
+        let form = token_request.text();
+        assert!(form.contains("grant_type=refresh_token"));
+        assert!(form.contains("refresh_token=refresh-old"));
+
+        let login = &server.requests_to("/mc/authentication/login_with_xbox")[0];
+        assert_eq!(login.json()["identityToken"], "XBL3.0 x=1234;xsts-new");
+    }
+
+    #[test]
+    fn refresh_keeps_a_refresh_token_that_was_not_rotated() {
+        let server = services(
+            PROFILE_ID,
+            serde_json::json!({
+                "access_token": "ms-new",
+                "token_type": "Bearer",
+                "expires_in": 3600
+            }),
+        );
+
+        let refreshed = block_on(refresh_tokens(&endpoints(&server), &account("mc-old"))).unwrap();
+
+        assert_eq!(refreshed.microsoft.refresh_token, "refresh-old");
+    }
+
+    #[test]
+    fn revoked_refresh_token_is_an_oauth_error() {
+        let server = TestServer::start(|_| {
+            Response::json(
+                400,
+                serde_json::json!({
+                    "error": "invalid_grant",
+                    "error_description": "AADSTS70000: The refresh token was revoked."
+                }),
+            )
+        });
+
+        let err = block_on(refresh_tokens(&endpoints(&server), &account("mc-old"))).unwrap_err();
+
+        match err.downcast_ref::<AuthError>() {
+            Some(AuthError::OAuth { error, .. }) => assert_eq!(error, "invalid_grant"),
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    fn entitlements(names: &[&str]) -> Entitlements {
+        Entitlements {
+            items: names
+                .iter()
+                .map(|name| Entitlement {
+                    name: name.to_string(),
+                    signature: None,
+                })
+                .collect(),
+            ..Entitlements::default()
+        }
+    }
+
+    #[test]
+    fn entitlement_kinds_follow_their_names() {
+        for (name, kind) in [
+            ("product_minecraft", EntitlementKind::ProductMinecraft),
+            ("game_minecraft", EntitlementKind::GameMinecraft),
+            ("product_game_pass_pc", EntitlementKind::GamePass),
+            ("product_game_pass_ultimate", EntitlementKind::GamePass),
+            ("game_pass_pc", EntitlementKind::GamePass),
+            ("product_minecraft_bedrock", EntitlementKind::Other),
+            ("product_dungeons", EntitlementKind::Other),
+        ] {
+            let entitlement = Entitlement {
+                name: name.to_string(),
+                signature: None,
+            };
+            assert_eq!(entitlement.kind(), kind, "{}", name);
+        }
+    }
+
+    #[test]
+    fn ownership_and_game_pass_are_derived_from_the_items() {
+        for (names, owns, via_game_pass) in [
+            (&["product_minecraft", "game_minecraft"][..], true, false),
+            (&["game_minecraft"][..], true, false),
+            (&["product_game_pass_pc"][..], true, true),
+            (
+                &["product_game_pass_ultimate", "game_minecraft"][..],
+                true,
+                true,
+            ),
+            (
+                &["product_minecraft", "product_game_pass_pc"][..],
+                true,
+                false,
+            ),
+            (&["product_minecraft_bedrock"][..], false, false),
+            (&[][..], false, false),
+        ] {
+            let entitlements = entitlements(names);
+            assert_eq!(entitlements.owns_java_edition(), owns, "{:?}", names);
+            assert_eq!(entitlements.via_game_pass(), via_game_pass, "{:?}", names);
+        }
+    }
+
+    #[test]
+    fn offline_uuids_match_vanilla() {
+        assert_eq!(offline_uuid("Notch"), "b50ad385829d3141a2167e7d7539ba7f");
+
+        let account = super::offline_account(" Notch ").unwrap();
+        assert_eq!(account.uuid, "b50ad385829d3141a2167e7d7539ba7f");
+        assert_eq!(account.username, "Notch");
+    }
+
+    #[test]
+    fn offline_usernames_must_be_possible_player_names() {
+        for username in ["", "seventeen_chars__", "no spaces", "dash-ed", "ünïcode"] {
+            assert!(super::offline_account(username).is_err(), "{:?}", username);
+        }
+        for username in ["a", "Notch", "sixteen_chars___"] {
+            assert!(super::offline_account(username).is_ok(), "{:?}", username);
+        }
+    }
+
+    #[test]
+    fn each_failing_step_reports_its_kind() {
+        let no_entitlement = [
+            (
+                "/mc/entitlements/mcstore",
+                Response::json(200, serde_json::json!({ "items": [] })),
+            ),
+            ("/mc/minecraft/profile", Response::status(404)),
+        ];
+        let cases: [(&[(&str, Response)], &str); 6] = [
+            (&[("/xbox/user", Response::status(500))], "service"),
+            (
+                &[(
+                    "/xbox/xsts",
+                    Response::json(401, serde_json::json!({ "XErr": 2148916238u64 })),
+                )],
+                "xbox_child_account",
+            ),
+            (
+                &[("/mc/authentication/login_with_xbox", Response::status(503))],
+                "service",
+            ),
+            (
+                &[("/mc/entitlements/mcstore", Response::status(500))],
+                "service",
+            ),
+            (
+                &[("/mc/minecraft/profile", Response::status(500))],
+                "service",
+            ),
+            (&no_entitlement, "no_entitlement"),
+        ];
+
+        for (failures, kind) in cases {
+            let failures: Vec<(String, Response)> = failures
+                .iter()
+                .map(|(path, response)| (path.to_string(), response.clone()))
+                .collect();
+            let server = TestServer::start(move |request| {
+                match failures.iter().find(|(path, _)| *path == request.path) {
+                    Some((_, response)) => response.clone(),
+                    None => service_response(PROFILE_ID, &rotated_token(), request),
+                }
+            });
+
+            let err =
+                block_on(refresh_tokens(&endpoints(&server), &account("mc-old"))).unwrap_err();
+
+            assert_eq!(AuthError::from_anyhow(&err).kind(), kind, "{:#}", err);
+        }
+    }
+
+    #[test]
+    fn refresh_for_another_profile_is_rejected() {
+        let server = services("853c80ef3c3749fdaa49938b674adae6", rotated_token());
+
+        let err = block_on(refresh_tokens(&endpoints(&server), &account("mc-old"))).unwrap_err();
+
+        assert!(err.to_string().contains("instead of"));
+    }
+
+    #[test]
+    fn concurrent_refreshes_of_an_account_run_once() {
+        let locks = Arc::new(RefreshLocks::default());
+        let store = Arc::new(Mutex::new(account("mc-old")));
+        let refreshes = Arc::new(AtomicUsize::new(0));
+
+        let refresh = |locks: Arc<RefreshLocks>,
+                       store: Arc<Mutex<AccountRecord>>,
+                       refreshes: Arc<AtomicUsize>| async move {
+            let stale = account("mc-old");
+            let stored = || Ok(store.lock().unwrap().clone());
+            locks
+                .refresh_once(&stale, stored, |mut account| async {
+                    refreshes.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    account.minecraft.access_token = "mc-new".to_string();
+                    *store.lock().unwrap() = account.clone();
+                    Ok(account)
+                })
+                .await
+        };
+
+        let results = block_on(async {
+            let first = tokio::spawn(refresh(locks.clone(), store.clone(), refreshes.clone()));
+            let second = tokio::spawn(refresh(locks.clone(), store.clone(), refreshes.clone()));
+            [first.await.unwrap(), second.await.unwrap()]
+        });
+
+        assert_eq!(refreshes.load(Ordering::SeqCst), 1);
+        for result in results {
+            assert_eq!(result.unwrap().minecraft.access_token, "mc-new");
+        }
+    }
+
+    #[test]
+    fn different_accounts_do_not_share_a_lock() {
+        let locks = RefreshLocks::default();
+
+        let first = locks.account("a");
+        let _guard = first.try_lock().unwrap();
+
+        assert!(locks.account("b").try_lock().is_ok());
+        assert!(locks.account("a").try_lock().is_err());
+    }
+
+    fn clear_microsoft_tokens(record: &mut AccountRecord) {
+        record.microsoft = MicrosoftTokens {
+            access_token: String::new(),
+            refresh_token: String::new(),
+            expires_at: None,
+        };
+        record.xbox = XboxTokens {
+            user_token: String::new(),
+            xsts_token: String::new(),
+            uhs: String::new(),
+        };
+    }
+
+    fn yggdrasil_account() -> AccountRecord {
+        let mut record = account("ygg-token");
+        record.uuid = "1b2f0a5e0c4b4a8f9d1e6b3c2a7d9e40".to_string();
+        record.username = "Steve".to_string();
+        clear_microsoft_tokens(&mut record);
+        record.minecraft.expires_at = None;
+        record.is_active = false;
+        record.account_type = AccountType::Yggdrasil;
+        record.yggdrasil = Some(YggdrasilSession {
+            server_url: "https://littleskin.cn/api/yggdrasil".to_string(),
+            client_token: "client-token".to_string(),
+        });
+        record
+    }
+
+    fn offline_account() -> AccountRecord {
+        let mut record = account("");
+        record.uuid = "2c3d4e5f60718293a4b5c6d7e8f90a1b".to_string();
+        record.username = "Alex".to_string();
+        clear_microsoft_tokens(&mut record);
+        record.minecraft.expires_at = None;
+        record.is_active = false;
+        record.account_type = AccountType::Offline;
+        record
+    }
+
+    fn as_json(account: &AccountRecord) -> serde_json::Value {
+        serde_json::to_value(account).unwrap()
+    }
+
+    #[test]
+    fn split_and_join_round_trip() {
+        for record in [account("mc-old"), yggdrasil_account(), offline_account()] {
+            let (metadata, secrets) = split_account(&record);
+            assert_eq!(as_json(&join_account(metadata, secrets)), as_json(&record));
+        }
+    }
+
+    #[test]
+    fn join_marks_microsoft_accounts_without_token_expired() {
+        let mut record = account("");
+        record.minecraft.expires_at = Some(u64::MAX);
+        let (metadata, secrets) = split_account(&record);
+
+        assert_eq!(
+            join_account(metadata, secrets).minecraft.expires_at,
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn persisted_accounts_keep_tokens_in_the_secret_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("accounts.json");
+        let secrets = MemoryStore::default();
+        let store = AccountStore {
+            accounts: vec![account("mc-old"), yggdrasil_account(), offline_account()],
+        };
+
+        persist_account_store_to(&path, &secrets, &store).unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        for token in [
+            "mc-old",
+            "ms-old",
+            "refresh-old",
+            "xsts-old",
+            "client-token",
+        ] {
+            assert!(
+                !contents.contains(token),
+                "{} leaked into accounts.json",
+                token
+            );
+        }
+        let stored = |uuid: &str, token: &str| secrets.get(&secret_key(uuid, token)).unwrap();
+        assert_eq!(
+            stored(PROFILE_ID, "minecraft-access").as_deref(),
+            Some("mc-old")
+        );
+        assert_eq!(
+            stored(PROFILE_ID, "refresh").as_deref(),
+            Some("refresh-old")
+        );
+        assert_eq!(stored(PROFILE_ID, "client-token"), None);
+        assert_eq!(stored(&offline_account().uuid, "minecraft-access"), None);
+
+        let (loaded, migrated) = load_account_store_from(&path, &secrets).unwrap();
+        assert!(!migrated);
+        let loaded: Vec<_> = loaded.accounts.iter().map(as_json).collect();
+        let expected: Vec<_> = store.accounts.iter().map(as_json).collect();
+        assert_eq!(loaded, expected);
+    }
+
+    #[test]
+    fn legacy_accounts_file_is_migrated() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("accounts.json");
+        let secrets = MemoryStore::default();
+        let legacy = vec![account("mc-old"), yggdrasil_account()];
+        fs::write(
+            &path,
+            serde_json::to_string(&serde_json::json!({ "accounts": legacy })).unwrap(),
+        )
+        .unwrap();
+
+        let (store, migrated) = load_account_store_from(&path, &secrets).unwrap();
+        assert!(migrated);
+        assert_eq!(
+            store.accounts.iter().map(as_json).collect::<Vec<_>>(),
+            legacy.iter().map(as_json).collect::<Vec<_>>()
+        );
+
+        persist_account_store_to(&path, &secrets, &store).unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(!contents.contains("mc-old"));
+        assert!(!contents.contains("client-token"));
+
+        let (reloaded, migrated) = load_account_store_from(&path, &secrets).unwrap();
+        assert!(!migrated);
+        assert_eq!(
+            reloaded.accounts.iter().map(as_json).collect::<Vec<_>>(),
+            legacy.iter().map(as_json).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn removed_accounts_lose_their_secrets() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("accounts.json");
+        let secrets = MemoryStore::default();
+        let mut store = AccountStore {
+            accounts: vec![account("mc-old"), yggdrasil_account()],
+        };
+        persist_account_store_to(&path, &secrets, &store).unwrap();
+
+        store.accounts.remove(0);
+        persist_account_store_to(&path, &secrets, &store).unwrap();
+
+        for (name, _) in AccountSecrets::default().tokens_mut() {
+            assert!(secrets
+                .get(&secret_key(PROFILE_ID, name))
+                .unwrap()
+                .is_none());
+        }
+        assert!(secrets
+            .get(&secret_key(&yggdrasil_account().uuid, "client-token"))
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn tokens_too_large_for_the_keyring_fall_back_to_the_file_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("accounts.json");
+        // the Credential Manager limit of 2560 bytes, for UTF-16 encoded values
+        let secrets = FallbackStore::new(MemoryStore::with_limit(1280), MemoryStore::default());
+        let mut large = account(&"m".repeat(2000));
+        large.xbox.xsts_token = "x".repeat(1500);
+        let store = AccountStore {
+            accounts: vec![large],
+        };
+
+        persist_account_store_to(&path, &secrets, &store).unwrap();
+
+        let (loaded, _) = load_account_store_from(&path, &secrets).unwrap();
+        assert_eq!(as_json(&loaded.accounts[0]), as_json(&store.accounts[0]));
+    }
+
+    #[test]
+    fn validated_tokens_are_trusted_for_a_while() {
+        let tokens = ValidatedTokens::default();
+        let account = yggdrasil_account();
+        let calls = AtomicUsize::new(0);
+        let validate = |valid: bool| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            async move { Ok(valid) }
+        };
+
+        assert!(block_on(tokens.check(&account, || validate(true))).unwrap());
+        assert!(block_on(tokens.check(&account, || validate(true))).unwrap());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // a new token has not been confirmed yet
+        let mut refreshed = account.clone();
+        refreshed.minecraft.access_token = "ygg-token-2".to_string();
+        assert!(block_on(tokens.check(&refreshed, || validate(true))).unwrap());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // and neither has one confirmed too long ago
+        tokens.lock().get_mut(&account.uuid).unwrap().1 -= VALIDATION_TTL;
+        assert!(block_on(tokens.check(&refreshed, || validate(true))).unwrap());
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn rejected_tokens_are_not_cached() {
+        let tokens = ValidatedTokens::default();
+        let account = yggdrasil_account();
+        let calls = AtomicUsize::new(0);
+        let validate = |valid: bool| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            async move { Ok(valid) }
+        };
+
+        assert!(block_on(tokens.check(&account, || validate(true))).unwrap());
+        tokens.lock().get_mut(&account.uuid).unwrap().1 -= VALIDATION_TTL;
+        assert!(!block_on(tokens.check(&account, || validate(false))).unwrap());
+        assert!(!block_on(tokens.check(&account, || validate(false))).unwrap());
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert!(block_on(tokens.check(&account, || async { Err(anyhow!("offline")) })).is_err());
+    }
+}
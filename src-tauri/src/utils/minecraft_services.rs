@@ -0,0 +1,572 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use reqwest::multipart::{Form, Part};
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::utils::auth_error::AuthError;
+use crate::utils::minecraft_auth::{AuthEndpoints, Entitlements};
+
+/// How long Mojang makes an account wait between two name changes.
+pub const NAME_CHANGE_COOLDOWN_DAYS: i64 = 30;
+
+/// The authenticated part of the Minecraft services API: profile, name and ownership calls
+/// made with an account's Minecraft access token. Uses the same base URL as the login chain,
+/// so `DECENT_MINECRAFT_SERVICES_URL` points all of them at a fake server at once.
+#[derive(Debug, Clone)]
+pub struct MinecraftServices {
+    client: Client,
+    base_url: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MinecraftProfile {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub skins: Vec<ProfileSkin>,
+    #[serde(default)]
+    pub capes: Vec<ProfileCape>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileSkin {
+    pub id: String,
+    /// `ACTIVE` for the skin currently worn.
+    pub state: String,
+    pub url: String,
+    #[serde(default)]
+    pub variant: Option<SkinVariant>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub texture_key: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProfileCape {
+    pub id: String,
+    /// `ACTIVE` for the cape currently shown, `INACTIVE` for the other owned ones.
+    pub state: String,
+    pub url: String,
+    /// The cape's display name, e.g. `Migrator`.
+    #[serde(default)]
+    pub alias: Option<String>,
+}
+
+impl ProfileCape {
+    pub fn is_active(&self) -> bool {
+        self.state.eq_ignore_ascii_case("active")
+    }
+}
+
+/// The player model a skin is drawn for: `classic` has 4 pixel wide arms, `slim` 3.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SkinVariant {
+    #[default]
+    #[serde(alias = "CLASSIC")]
+    Classic,
+    #[serde(alias = "SLIM")]
+    Slim,
+}
+
+impl SkinVariant {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SkinVariant::Classic => "classic",
+            SkinVariant::Slim => "slim",
+        }
+    }
+}
+
+/// When the profile name was last changed and whether it may be changed now.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct NameChangeInfo {
+    #[serde(default)]
+    pub changed_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub created_at: Option<DateTime<Utc>>,
+    pub name_change_allowed: bool,
+    /// Filled in by the launcher: when the cooldown after the last change ends.
+    #[serde(default, skip_deserializing)]
+    pub next_change_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all(serialize = "snake_case", deserialize = "SCREAMING_SNAKE_CASE"))]
+pub enum NameAvailability {
+    Available,
+    Duplicate,
+    NotAllowed,
+}
+
+#[derive(Debug, Deserialize)]
+struct NameAvailabilityResponse {
+    status: NameAvailability,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    #[serde(default)]
+    details: Option<ErrorDetails>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorDetails {
+    status: String,
+}
+
+impl MinecraftServices {
+    pub fn new(client: Client, base_url: impl Into<String>) -> Self {
+        Self {
+            client,
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(
+            Client::new(),
+            AuthEndpoints::from_env().minecraft_services_url,
+        )
+    }
+
+    pub async fn entitlements(&self, access_token: &str) -> Result<Entitlements> {
+        let response = self
+            .send(self.get("entitlements/mcstore", access_token))
+            .await
+            .context("failed to fetch Minecraft entitlements")?
+            .error_for_status()
+            .context("Minecraft entitlements request returned non-success status")?;
+
+        response
+            .json::<Entitlements>()
+            .await
+            .context("failed to parse Minecraft entitlements response")
+    }
+
+    /// Fetches the profile the token belongs to, or `None` when the account has not created
+    /// one.
+    pub async fn profile(&self, access_token: &str) -> Result<Option<MinecraftProfile>> {
+        let response = self
+            .send(self.get("minecraft/profile", access_token))
+            .await
+            .context("failed to fetch Minecraft profile")?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let profile = parse_profile(response).await?;
+        Ok(Some(profile))
+    }
+
+    pub async fn create_profile(&self, access_token: &str, name: &str) -> Result<MinecraftProfile> {
+        let response = self
+            .send(
+                self.authorized(
+                    self.client.post(self.url("minecraft/profile")),
+                    access_token,
+                )
+                .json(&json!({ "profileName": name })),
+            )
+            .await
+            .context("failed to create Minecraft profile")?;
+
+        let response = name_error(response, name).await?;
+        parse_profile(response).await
+    }
+
+    pub async fn name_change_info(&self, access_token: &str) -> Result<NameChangeInfo> {
+        let response = self
+            .send(self.get("minecraft/profile/namechange", access_token))
+            .await
+            .context("failed to fetch name change information")?
+            .error_for_status()
+            .context("name change information request returned non-success status")?;
+
+        let mut info = response
+            .json::<NameChangeInfo>()
+            .await
+            .context("failed to parse name change information")?;
+
+        // a profile that was never renamed counts from its creation
+        if !info.name_change_allowed {
+            info.next_change_at = info
+                .changed_at
+                .or(info.created_at)
+                .map(|changed_at| changed_at + Duration::days(NAME_CHANGE_COOLDOWN_DAYS));
+        }
+
+        Ok(info)
+    }
+
+    pub async fn name_availability(
+        &self,
+        access_token: &str,
+        name: &str,
+    ) -> Result<NameAvailability> {
+        let path = format!("minecraft/profile/name/{}/available", name);
+        let response = self
+            .send(self.get(&path, access_token))
+            .await
+            .context("failed to check name availability")?
+            .error_for_status()
+            .context("name availability request returned non-success status")?;
+
+        let parsed = response
+            .json::<NameAvailabilityResponse>()
+            .await
+            .context("failed to parse name availability response")?;
+
+        Ok(parsed.status)
+    }
+
+    pub async fn change_name(&self, access_token: &str, name: &str) -> Result<MinecraftProfile> {
+        let path = format!("minecraft/profile/name/{}", name);
+        let response = self
+            .send(self.authorized(self.client.put(self.url(&path)), access_token))
+            .await
+            .context("failed to change profile name")?;
+
+        let response = name_error(response, name).await?;
+        parse_profile(response).await
+    }
+
+    /// Replaces the active skin with `png`, which the caller has already validated.
+    pub async fn upload_skin(
+        &self,
+        access_token: &str,
+        png: Vec<u8>,
+        variant: SkinVariant,
+    ) -> Result<MinecraftProfile> {
+        let file = Part::bytes(png)
+            .file_name("skin.png")
+            .mime_str("image/png")
+            .context("invalid skin mime type")?;
+        let form = Form::new()
+            .text("variant", variant.as_str())
+            .part("file", file);
+
+        let response = self
+            .send(
+                self.authorized(
+                    self.client.post(self.url("minecraft/profile/skins")),
+                    access_token,
+                )
+                .multipart(form),
+            )
+            .await
+            .context("failed to upload skin")?;
+
+        parse_profile(response).await
+    }
+
+    /// Goes back to the default skin for the account.
+    pub async fn reset_skin(&self, access_token: &str) -> Result<MinecraftProfile> {
+        let response = self
+            .send(
+                self.authorized(
+                    self.client
+                        .delete(self.url("minecraft/profile/skins/active")),
+                    access_token,
+                ),
+            )
+            .await
+            .context("failed to reset skin")?;
+
+        parse_profile(response).await
+    }
+
+    pub async fn set_active_cape(
+        &self,
+        access_token: &str,
+        cape_id: &str,
+    ) -> Result<MinecraftProfile> {
+        let response = self
+            .send(
+                self.authorized(
+                    self.client.put(self.url("minecraft/profile/capes/active")),
+                    access_token,
+                )
+                .json(&json!({ "capeId": cape_id })),
+            )
+            .await
+            .context("failed to select cape")?;
+
+        parse_profile(response).await
+    }
+
+    pub async fn hide_cape(&self, access_token: &str) -> Result<MinecraftProfile> {
+        let response = self
+            .send(
+                self.authorized(
+                    self.client
+                        .delete(self.url("minecraft/profile/capes/active")),
+                    access_token,
+                ),
+            )
+            .await
+            .context("failed to hide cape")?;
+
+        parse_profile(response).await
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.base_url, path)
+    }
+
+    fn get(&self, path: &str, access_token: &str) -> RequestBuilder {
+        self.authorized(self.client.get(self.url(path)), access_token)
+    }
+
+    fn authorized(&self, request: RequestBuilder, access_token: &str) -> RequestBuilder {
+        request.bearer_auth(access_token)
+    }
+
+    async fn send(&self, request: RequestBuilder) -> Result<Response> {
+        let response = request.send().await?;
+
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            return Err(AuthError::Network {
+                service: "Minecraft services".to_string(),
+                message: "too many requests, try again in a minute".to_string(),
+            }
+            .into());
+        }
+
+        Ok(response)
+    }
+}
+
+async fn parse_profile(response: Response) -> Result<MinecraftProfile> {
+    response
+        .error_for_status()
+        .context("Minecraft profile request returned non-success status")?
+        .json::<MinecraftProfile>()
+        .await
+        .context("failed to parse Minecraft profile response")
+}
+
+/// Turns a rejected profile name into [`AuthError::ProfileName`]; the reason is in
+/// `details.status`, e.g. `DUPLICATE` or `NOT_ALLOWED`. Rejections without one, such as a
+/// token that lacks permission, stay plain status errors.
+async fn name_error(response: Response, name: &str) -> Result<Response> {
+    if !matches!(
+        response.status(),
+        StatusCode::BAD_REQUEST | StatusCode::FORBIDDEN
+    ) {
+        return Ok(response);
+    }
+
+    let status_error = match response.error_for_status_ref() {
+        Ok(_) => return Ok(response),
+        Err(err) => err,
+    };
+
+    let details = response
+        .json::<ErrorResponse>()
+        .await
+        .ok()
+        .and_then(|error| error.details);
+
+    match details {
+        Some(details) => Err(AuthError::ProfileName {
+            name: name.to_string(),
+            status: details.status,
+        }
+        .into()),
+        None => {
+            Err(anyhow!(status_error)
+                .context("Minecraft profile request returned non-success status"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_server::{block_on, Request, Response as TestResponse, TestServer};
+
+    const PROFILE_ID: &str = "069a79f444e94726a5befca90e38aaf5";
+
+    fn profile_json(name: &str) -> serde_json::Value {
+        json!({
+            "id": PROFILE_ID,
+            "name": name,
+            "skins": [{
+                "id": "skin-1",
+                "state": "ACTIVE",
+                "url": "http://textures.minecraft.net/texture/abc",
+                "variant": "SLIM"
+            }],
+            "capes": [{
+                "id": "cape-1",
+                "state": "INACTIVE",
+                "url": "http://textures.minecraft.net/texture/def",
+                "alias": "Migrator"
+            }]
+        })
+    }
+
+    fn services(
+        handler: impl Fn(&Request) -> TestResponse + Send + Sync + 'static,
+    ) -> (TestServer, MinecraftServices) {
+        let server = TestServer::start(handler);
+        let services = MinecraftServices::new(Client::new(), format!("{}/", server.url()));
+        (server, services)
+    }
+
+    fn name_change(body: serde_json::Value) -> NameChangeInfo {
+        let (_server, services) = services(move |_| TestResponse::json(200, body.clone()));
+        block_on(services.name_change_info("token")).unwrap()
+    }
+
+    #[test]
+    fn profile_is_fetched_with_the_token() {
+        let (server, services) = services(|_| TestResponse::json(200, profile_json("Notch")));
+
+        let profile = block_on(services.profile("token")).unwrap().unwrap();
+
+        assert_eq!(profile.name, "Notch");
+        assert_eq!(profile.skins[0].variant, Some(SkinVariant::Slim));
+        assert!(!profile.capes[0].is_active());
+        let request = &server.requests_to("/minecraft/profile")[0];
+        assert_eq!(request.header("authorization"), Some("Bearer token"));
+    }
+
+    #[test]
+    fn missing_profile_is_none() {
+        let (_server, services) = services(|_| TestResponse::status(404));
+
+        assert!(block_on(services.profile("token")).unwrap().is_none());
+    }
+
+    #[test]
+    fn rate_limits_are_network_errors() {
+        let (_server, services) = services(|_| TestResponse::status(429));
+
+        let err = block_on(services.profile("token")).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<AuthError>(),
+            Some(AuthError::Network { .. })
+        ));
+    }
+
+    #[test]
+    fn rejected_names_carry_the_reason() {
+        let (_server, services) = services(|request| {
+            assert_eq!(request.path, "/minecraft/profile/name/Notch");
+            TestResponse::json(
+                403,
+                json!({ "path": request.path, "details": { "status": "DUPLICATE" } }),
+            )
+        });
+
+        let err = block_on(services.change_name("token", "Notch")).unwrap_err();
+        match err.downcast_ref::<AuthError>() {
+            Some(AuthError::ProfileName { name, status }) => {
+                assert_eq!(name, "Notch");
+                assert_eq!(status, "DUPLICATE");
+            }
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejections_without_details_are_status_errors() {
+        let (_server, services) = services(|_| {
+            TestResponse::json(403, json!({ "errorMessage": "insufficient privileges" }))
+        });
+
+        let err = block_on(services.create_profile("token", "Notch")).unwrap_err();
+        assert!(err.downcast_ref::<AuthError>().is_none());
+        let status = err
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<reqwest::Error>())
+            .and_then(|err| err.status());
+        assert_eq!(status, Some(StatusCode::FORBIDDEN));
+    }
+
+    #[test]
+    fn accepted_names_return_the_profile() {
+        let (server, services) = services(|request| {
+            assert_eq!(request.json()["profileName"], "Notch");
+            TestResponse::json(200, profile_json("Notch"))
+        });
+
+        let profile = block_on(services.create_profile("token", "Notch")).unwrap();
+        assert_eq!(profile.id, PROFILE_ID);
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn next_name_change_follows_the_last_change() {
+        let info = name_change(json!({
+            "changedAt": "2024-03-01T12:00:00Z",
+            "createdAt": "2020-01-01T00:00:00Z",
+            "nameChangeAllowed": false
+        }));
+
+        assert_eq!(
+            info.next_change_at.unwrap().to_rfc3339(),
+            "2024-03-31T12:00:00+00:00"
+        );
+    }
+
+    #[test]
+    fn next_name_change_of_a_never_renamed_profile_follows_its_creation() {
+        let info = name_change(json!({
+            "createdAt": "2024-05-10T08:00:00Z",
+            "nameChangeAllowed": false
+        }));
+
+        assert_eq!(
+            info.next_change_at.unwrap().to_rfc3339(),
+            "2024-06-09T08:00:00+00:00"
+        );
+    }
+
+    #[test]
+    fn allowed_name_changes_have_no_cooldown() {
+        let info = name_change(json!({
+            "changedAt": "2024-03-01T12:00:00Z",
+            "nameChangeAllowed": true
+        }));
+
+        assert!(info.next_change_at.is_none());
+    }
+
+    #[test]
+    fn name_availability_is_parsed() {
+        let (server, services) =
+            services(|_| TestResponse::json(200, json!({ "status": "NOT_ALLOWED" })));
+
+        let availability = block_on(services.name_availability("token", "Notch")).unwrap();
+
+        assert_eq!(availability, NameAvailability::NotAllowed);
+        assert_eq!(
+            server.requests()[0].path,
+            "/minecraft/profile/name/Notch/available"
+        );
+    }
+
+    #[test]
+    fn skins_are_uploaded_as_multipart() {
+        let (server, services) = services(|_| TestResponse::json(200, profile_json("Notch")));
+
+        block_on(services.upload_skin("token", b"png bytes".to_vec(), SkinVariant::Slim)).unwrap();
+
+        let request = &server.requests_to("/minecraft/profile/skins")[0];
+        assert!(request
+            .header("content-type")
+            .unwrap()
+            .starts_with("multipart/form-data"));
+        let body = request.text();
+        assert!(body.contains("name=\"variant\"\r\n\r\nslim"));
+        assert!(body.contains("filename=\"skin.png\""));
+        assert!(body.contains("png bytes"));
+    }
+}
@@ -1,8 +1,40 @@
 use log::error;
-use std::{thread, time};
+use std::{env, thread, time};
 use tauri::{AppHandle, Manager, Runtime};
 
+pub mod assets;
+pub mod auth_error;
+pub mod download;
+pub mod files;
+pub mod java;
+pub mod java_runtime;
+pub mod launch;
+pub mod libraries;
+pub mod loopback;
 pub mod minecraft_auth;
+pub mod minecraft_services;
+pub mod player_lookup;
+pub mod secret_store;
+pub mod settings;
+pub mod skin_library;
+#[cfg(test)]
+pub mod test_fixtures;
+#[cfg(test)]
+pub mod test_server;
+pub mod texture_cache;
+pub mod version_json;
+pub mod versions;
+pub mod yggdrasil;
+
+/// Reads a base URL override from `var`, falling back to `default`. Trailing slashes are
+/// dropped so paths can be appended with `format!("{}/...")`.
+pub fn env_url(var: &str, default: &str) -> String {
+    env::var(var)
+        .ok()
+        .map(|value| value.trim().trim_end_matches('/').to_owned())
+        .filter(|value| !value.is_empty())
+        .unwrap_or_else(|| default.to_string())
+}
 
 #[tauri::command]
 pub fn restart_app(app: AppHandle) {
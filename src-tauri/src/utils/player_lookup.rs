@@ -0,0 +1,421 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use log::debug;
+use reqwest::{Client, StatusCode};
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Serialize, Serializer};
+use tauri::{AppHandle, Manager, Runtime};
+use uuid::Uuid;
+
+use crate::utils::env_url;
+use crate::utils::minecraft_auth::current_timestamp;
+
+pub const MOJANG_API_URL: &str = "https://api.mojang.com";
+
+/// The bulk endpoint refuses requests with more names than this.
+const BULK_LIMIT: usize = 10;
+
+/// Names rarely change hands, so a resolved name is trusted for a while.
+const FOUND_TTL_SECS: u64 = 60 * 60;
+
+/// A name nobody uses may be taken any minute, so misses expire sooner.
+const MISSING_TTL_SECS: u64 = 5 * 60;
+
+/// Username to UUID resolution through Mojang's profile lookup API, with the answers kept in
+/// memory. Registered as managed state.
+#[derive(Default)]
+pub struct PlayerLookup {
+    client: Client,
+    names: Mutex<HashMap<String, CachedName>>,
+}
+
+/// A player's UUID (without dashes) and the current spelling of their name.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PlayerProfile {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone)]
+struct CachedName {
+    profile: Option<PlayerProfile>,
+    fetched_at: u64,
+}
+
+/// Why a player could not be resolved or rendered. Serialised as `{ kind, message }` like
+/// `AuthError`.
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum LookupError {
+    /// No player uses this name, or no profile has this UUID.
+    #[error("player {player} does not exist")]
+    NotFound { player: String },
+    /// The player exists but wears one of the default skins, which have no texture URL.
+    #[error("player {player} has no custom skin")]
+    NoSkin { player: String },
+    #[error("could not reach the Mojang API: {message}")]
+    Network { message: String },
+    /// The skin texture could not be fetched or is not a usable skin.
+    #[error("could not load the skin: {message}")]
+    Texture { message: String },
+    #[error("{0}")]
+    Other(String),
+}
+
+impl LookupError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LookupError::NotFound { .. } => "player_not_found",
+            LookupError::NoSkin { .. } => "no_skin",
+            LookupError::Network { .. } => "network",
+            LookupError::Texture { .. } => "texture",
+            LookupError::Other(_) => "other",
+        }
+    }
+
+    /// Wraps a failure to load a profile's textures, keeping connection problems apart.
+    pub fn texture(err: anyhow::Error) -> Self {
+        let offline = err
+            .chain()
+            .filter_map(|cause| cause.downcast_ref::<reqwest::Error>())
+            .any(|err| err.is_connect() || err.is_timeout());
+
+        if offline {
+            LookupError::Network {
+                message: format!("{:#}", err),
+            }
+        } else {
+            LookupError::Texture {
+                message: format!("{:#}", err),
+            }
+        }
+    }
+}
+
+impl From<reqwest::Error> for LookupError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_connect() || err.is_timeout() || err.is_request() {
+            LookupError::Network {
+                message: err.to_string(),
+            }
+        } else {
+            LookupError::Other(format!("player lookup failed: {}", err))
+        }
+    }
+}
+
+impl Serialize for LookupError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("LookupError", 2)?;
+        state.serialize_field("kind", self.kind())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.end()
+    }
+}
+
+/// Returns `uuid_or_name` as an undashed UUID, looking it up as a username when it is not a
+/// UUID in either the dashed or the undashed form.
+pub async fn resolve_player<R: Runtime>(
+    app: &AppHandle<R>,
+    uuid_or_name: &str,
+) -> Result<String, LookupError> {
+    match parse_uuid(uuid_or_name) {
+        Some(uuid) => Ok(uuid),
+        None => Ok(lookup_player(app, uuid_or_name).await?.id),
+    }
+}
+
+/// Looks up a single player by name.
+pub async fn lookup_player<R: Runtime>(
+    app: &AppHandle<R>,
+    name: &str,
+) -> Result<PlayerProfile, LookupError> {
+    let name = name.trim();
+    lookup_players(app, &[name.to_string()])
+        .await?
+        .remove(&name.to_ascii_lowercase())
+        .ok_or_else(|| LookupError::NotFound {
+            player: name.to_string(),
+        })
+}
+
+/// Looks up several players at once, `BULK_LIMIT` names per request. The result is keyed by
+/// the lowercased name as asked for; names nobody uses are left out.
+pub async fn lookup_players<R: Runtime>(
+    app: &AppHandle<R>,
+    names: &[String],
+) -> Result<HashMap<String, PlayerProfile>, LookupError> {
+    let api_url = env_url("DECENT_MOJANG_API_URL", MOJANG_API_URL);
+    app.state::<PlayerLookup>()
+        .lookup(&api_url, names, current_timestamp())
+        .await
+}
+
+impl PlayerLookup {
+    async fn lookup(
+        &self,
+        api_url: &str,
+        names: &[String],
+        now: u64,
+    ) -> Result<HashMap<String, PlayerProfile>, LookupError> {
+        let mut found = HashMap::new();
+        let mut missing = Vec::new();
+        {
+            let cache = self
+                .names
+                .lock()
+                .map_err(|_| LookupError::Other("player lookup cache lock poisoned".to_string()))?;
+
+            for name in names {
+                let key = name.trim().to_ascii_lowercase();
+                if !is_possible_name(&key) || found.contains_key(&key) || missing.contains(&key) {
+                    continue;
+                }
+
+                match cache.get(&key) {
+                    Some(cached) if cached.is_fresh(now) => {
+                        if let Some(profile) = &cached.profile {
+                            found.insert(key, profile.clone());
+                        }
+                    }
+                    _ => missing.push(key),
+                }
+            }
+        }
+
+        for chunk in missing.chunks(BULK_LIMIT) {
+            let profiles = fetch_profiles(&self.client, api_url, chunk).await?;
+            debug!(
+                "resolved {} of {} player names",
+                profiles.len(),
+                chunk.len()
+            );
+
+            let mut cache = self
+                .names
+                .lock()
+                .map_err(|_| LookupError::Other("player lookup cache lock poisoned".to_string()))?;
+
+            for key in chunk {
+                let profile = profiles
+                    .iter()
+                    .find(|profile| profile.name.eq_ignore_ascii_case(key))
+                    .cloned();
+
+                if let Some(profile) = &profile {
+                    found.insert(key.clone(), profile.clone());
+                }
+                cache.insert(
+                    key.clone(),
+                    CachedName {
+                        profile,
+                        fetched_at: now,
+                    },
+                );
+            }
+        }
+
+        Ok(found)
+    }
+}
+
+/// Accepts a UUID with or without dashes and returns it without them.
+pub fn parse_uuid(value: &str) -> Option<String> {
+    let value = value.trim();
+    // `Uuid::try_parse` also takes braced and URN forms, which are not player ids
+    if value.len() != 32 && value.len() != 36 {
+        return None;
+    }
+
+    Uuid::try_parse(value)
+        .ok()
+        .map(|uuid| uuid.simple().to_string())
+}
+
+impl CachedName {
+    fn is_fresh(&self, now: u64) -> bool {
+        let ttl = if self.profile.is_some() {
+            FOUND_TTL_SECS
+        } else {
+            MISSING_TTL_SECS
+        };
+        now.saturating_sub(self.fetched_at) < ttl
+    }
+}
+
+/// Names are up to 16 letters, digits and underscores; some old ones are shorter than the 3
+/// characters required today. Anything else cannot belong to a player.
+pub fn is_possible_name(name: &str) -> bool {
+    (1..=16).contains(&name.len()) && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+async fn fetch_profiles(
+    client: &Client,
+    api_url: &str,
+    names: &[String],
+) -> Result<Vec<PlayerProfile>, LookupError> {
+    let url = format!("{}/profiles/minecraft", api_url);
+    let response = client.post(&url).json(names).send().await?;
+
+    if response.status() == StatusCode::TOO_MANY_REQUESTS {
+        return Err(LookupError::Network {
+            message: "too many requests, try again in a minute".to_string(),
+        });
+    }
+
+    Ok(response.error_for_status()?.json().await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_server::{block_on, Response, TestServer};
+    use anyhow::Context;
+
+    /// Answers like Mojang for every name except those starting with "nobody".
+    fn profile_server() -> TestServer {
+        TestServer::start(|request| {
+            let names: Vec<String> = serde_json::from_value(request.json()).unwrap();
+            let profiles: Vec<_> = names
+                .iter()
+                .filter(|name| !name.starts_with("nobody"))
+                .map(|name| {
+                    serde_json::json!({
+                        "id": format!("{:0>32}", name.len()),
+                        "name": name.to_ascii_uppercase(),
+                    })
+                })
+                .collect();
+            Response::json(200, serde_json::Value::Array(profiles))
+        })
+    }
+
+    fn lookup(
+        lookup: &PlayerLookup,
+        server: &TestServer,
+        names: &[&str],
+        now: u64,
+    ) -> Result<HashMap<String, PlayerProfile>, LookupError> {
+        let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        block_on(lookup.lookup(server.url(), &names, now))
+    }
+
+    #[test]
+    fn errors_serialise_with_their_kind() {
+        let err = LookupError::NotFound {
+            player: "069a79f444e94726a5befca90e38aaf5".to_string(),
+        };
+
+        assert_eq!(
+            serde_json::to_value(&err).unwrap(),
+            serde_json::json!({
+                "kind": "player_not_found",
+                "message": "player 069a79f444e94726a5befca90e38aaf5 does not exist"
+            })
+        );
+    }
+
+    #[test]
+    fn unreachable_texture_servers_are_network_errors() {
+        // nothing listens on the discard port
+        let result = crate::utils::test_server::block_on(
+            Client::new().get("http://127.0.0.1:9/texture").send(),
+        );
+        let err = anyhow::Error::from(result.unwrap_err()).context("failed to request texture");
+
+        assert_eq!(LookupError::texture(err).kind(), "network");
+    }
+
+    #[test]
+    fn other_texture_failures_are_texture_errors() {
+        let err = serde_json::from_str::<serde_json::Value>("{")
+            .context("failed to parse session profile")
+            .unwrap_err();
+
+        let err = LookupError::texture(err);
+        assert_eq!(err.kind(), "texture");
+        assert!(err
+            .to_string()
+            .starts_with("could not load the skin: failed to parse"));
+    }
+
+    #[test]
+    fn uuids_are_accepted_with_or_without_dashes() {
+        let undashed = "069a79f444e94726a5befca90e38aaf5";
+
+        assert_eq!(parse_uuid(undashed).as_deref(), Some(undashed));
+        assert_eq!(
+            parse_uuid("069A79F4-44E9-4726-A5BE-FCA90E38AAF5").as_deref(),
+            Some(undashed)
+        );
+        assert!(parse_uuid("Notch").is_none());
+        assert!(parse_uuid("{069a79f4-44e9-4726-a5be-fca90e38aaf5}").is_none());
+    }
+
+    #[test]
+    fn lookups_are_split_into_bulk_sized_requests() {
+        let server = profile_server();
+        let names: Vec<String> = (0..25).map(|i| format!("player{}", i)).collect();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+
+        let found = lookup(&PlayerLookup::default(), &server, &names, 0).unwrap();
+
+        assert_eq!(found.len(), 25);
+        let sizes: Vec<usize> = server
+            .requests_to("/profiles/minecraft")
+            .iter()
+            .map(|request| request.json().as_array().unwrap().len())
+            .collect();
+        assert_eq!(sizes, [BULK_LIMIT, BULK_LIMIT, 5]);
+    }
+
+    #[test]
+    fn names_match_regardless_of_case() {
+        let server = profile_server();
+
+        let found = lookup(&PlayerLookup::default(), &server, &["Notch", "nobody"], 0).unwrap();
+
+        assert_eq!(found.len(), 1);
+        assert_eq!(found["notch"].name, "NOTCH");
+    }
+
+    #[test]
+    fn cached_names_are_not_requested_again() {
+        let server = profile_server();
+        let players = PlayerLookup::default();
+
+        lookup(&players, &server, &["Notch", "nobody"], 0).unwrap();
+        let found = lookup(
+            &players,
+            &server,
+            &["NOTCH", "nobody"],
+            MISSING_TTL_SECS - 1,
+        )
+        .unwrap();
+
+        assert_eq!(found["notch"].name, "NOTCH");
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn misses_expire_sooner_than_players() {
+        let server = profile_server();
+        let players = PlayerLookup::default();
+
+        lookup(&players, &server, &["Notch", "nobody"], 0).unwrap();
+        lookup(&players, &server, &["Notch", "nobody"], MISSING_TTL_SECS).unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].json(), serde_json::json!(["nobody"]));
+    }
+
+    #[test]
+    fn rate_limits_are_network_errors() {
+        let server = TestServer::start(|_| Response::status(429));
+
+        let err = lookup(&PlayerLookup::default(), &server, &["Notch"], 0).unwrap_err();
+
+        assert_eq!(err.kind(), "network");
+    }
+}
@@ -0,0 +1,515 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use anyhow::{anyhow, Context, Result};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use log::{info, warn};
+use tauri::{AppHandle, Manager, Runtime};
+
+pub const KEYRING_SERVICE: &str = "decent-client-launcher";
+
+const SECRETS_FILE: &str = "secrets.bin";
+const MASTER_KEY_FILE: &str = "secrets.key";
+const NONCE_LEN: usize = 12;
+
+/// A place to keep credentials outside of the plain JSON files in the app data directory.
+pub trait SecretStore: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn get(&self, key: &str) -> Result<Option<String>>;
+    fn set(&self, key: &str, value: &str) -> Result<()>;
+    fn delete(&self, key: &str) -> Result<()>;
+}
+
+/// The secret store the app was started with, registered as managed state.
+pub struct Secrets(Box<dyn SecretStore>);
+
+impl Secrets {
+    pub fn new(store: impl SecretStore + 'static) -> Self {
+        Self(Box::new(store))
+    }
+
+    pub fn store(&self) -> &dyn SecretStore {
+        self.0.as_ref()
+    }
+}
+
+/// Opens the platform keyring (Secret Service on Linux, Keychain on macOS, Credential Manager
+/// on Windows), falling back to an encrypted file in the app data directory when the keyring
+/// is unavailable, e.g. without a running Secret Service daemon, or refuses a single secret,
+/// e.g. one over Credential Manager's size limit. `DECENT_SECRET_STORE` can force `keyring`,
+/// `file` or `memory`.
+pub fn open<R: Runtime>(app: &AppHandle<R>) -> Result<Secrets> {
+    let requested = env::var("DECENT_SECRET_STORE")
+        .ok()
+        .map(|value| value.trim().to_ascii_lowercase())
+        .filter(|value| !value.is_empty());
+
+    let secrets = match requested.as_deref() {
+        Some("memory") => Secrets::new(MemoryStore::default()),
+        Some("file") => Secrets::new(EncryptedFileStore::open(&app_data_dir(app)?)?),
+        Some("keyring") => Secrets::new(KeyringStore::new(KEYRING_SERVICE)),
+        Some(other) => return Err(anyhow!("unknown secret store '{}'", other)),
+        None => {
+            // probing the keyring talks to D-Bus and may block for a while, so it waits until
+            // the first account actually needs a secret
+            let dir = app_data_dir(app)?;
+            return Ok(Secrets::new(LazyStore::new(move || {
+                let keyring = KeyringStore::new(KEYRING_SERVICE);
+                let store: Box<dyn SecretStore> = match keyring.probe() {
+                    Ok(()) => {
+                        let dir = dir.clone();
+                        let file =
+                            LazyStore::new(move || Ok(Box::new(EncryptedFileStore::open(&dir)?)));
+                        Box::new(FallbackStore::new(keyring, file))
+                    }
+                    Err(err) => {
+                        warn!(
+                            "system keyring unavailable, falling back to an encrypted file: {:#}",
+                            err
+                        );
+                        Box::new(EncryptedFileStore::open(&dir)?)
+                    }
+                };
+                info!("storing account secrets in the {} store", store.name());
+                Ok(store)
+            })));
+        }
+    };
+
+    info!(
+        "storing account secrets in the {} store",
+        secrets.store().name()
+    );
+    Ok(secrets)
+}
+
+fn app_data_dir<R: Runtime>(app: &AppHandle<R>) -> Result<PathBuf> {
+    app.path()
+        .app_data_dir()
+        .context("failed to resolve app data directory")
+}
+
+pub struct KeyringStore {
+    service: String,
+}
+
+impl KeyringStore {
+    pub fn new(service: impl Into<String>) -> Self {
+        Self {
+            service: service.into(),
+        }
+    }
+
+    /// Reads a key that never exists to find out whether the keyring can be reached at all.
+    fn probe(&self) -> Result<()> {
+        self.get("probe").map(|_| ())
+    }
+
+    fn entry(&self, key: &str) -> Result<keyring::Entry> {
+        keyring::Entry::new(&self.service, key)
+            .with_context(|| format!("failed to open keyring entry {}", key))
+    }
+}
+
+impl SecretStore for KeyringStore {
+    fn name(&self) -> &'static str {
+        "keyring"
+    }
+
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        match self.entry(key)?.get_password() {
+            Ok(value) => Ok(Some(value)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(err) => Err(anyhow!(err).context(format!("failed to read secret {}", key))),
+        }
+    }
+
+    fn set(&self, key: &str, value: &str) -> Result<()> {
+        self.entry(key)?
+            .set_password(value)
+            .with_context(|| format!("failed to store secret {}", key))
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        match self.entry(key)?.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(err) => Err(anyhow!(err).context(format!("failed to delete secret {}", key))),
+        }
+    }
+}
+
+type OpenStore = dyn Fn() -> Result<Box<dyn SecretStore>> + Send + Sync;
+
+/// Picks the real store on first use. Opening is retried on the next call when it fails.
+pub struct LazyStore {
+    open: Box<OpenStore>,
+    store: OnceLock<Box<dyn SecretStore>>,
+    opening: Mutex<()>,
+}
+
+impl LazyStore {
+    pub fn new(open: impl Fn() -> Result<Box<dyn SecretStore>> + Send + Sync + 'static) -> Self {
+        Self {
+            open: Box::new(open),
+            store: OnceLock::new(),
+            opening: Mutex::new(()),
+        }
+    }
+
+    fn store(&self) -> Result<&dyn SecretStore> {
+        if let Some(store) = self.store.get() {
+            return Ok(store.as_ref());
+        }
+
+        let _guard = self
+            .opening
+            .lock()
+            .map_err(|_| anyhow!("secret store lock poisoned"))?;
+        if let Some(store) = self.store.get() {
+            return Ok(store.as_ref());
+        }
+
+        let store = (self.open)()?;
+        Ok(self.store.get_or_init(|| store).as_ref())
+    }
+}
+
+impl SecretStore for LazyStore {
+    fn name(&self) -> &'static str {
+        self.store.get().map_or("lazy", |store| store.name())
+    }
+
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        self.store()?.get(key)
+    }
+
+    fn set(&self, key: &str, value: &str) -> Result<()> {
+        self.store()?.set(key, value)
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.store()?.delete(key)
+    }
+}
+
+/// Writes to `primary`, keeping the secrets it refuses in `fallback` instead.
+pub struct FallbackStore<P, F> {
+    primary: P,
+    fallback: F,
+}
+
+impl<P: SecretStore, F: SecretStore> FallbackStore<P, F> {
+    pub fn new(primary: P, fallback: F) -> Self {
+        Self { primary, fallback }
+    }
+}
+
+impl<P: SecretStore, F: SecretStore> SecretStore for FallbackStore<P, F> {
+    fn name(&self) -> &'static str {
+        self.primary.name()
+    }
+
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        match self.primary.get(key)? {
+            Some(value) => Ok(Some(value)),
+            None => self.fallback.get(key),
+        }
+    }
+
+    fn set(&self, key: &str, value: &str) -> Result<()> {
+        match self.primary.set(key, value) {
+            Ok(()) => self.fallback.delete(key),
+            Err(err) => {
+                warn!(
+                    "{} store refused secret {}, keeping it in the {} store: {:#}",
+                    self.primary.name(),
+                    key,
+                    self.fallback.name(),
+                    err
+                );
+                self.fallback.set(key, value)?;
+                self.primary.delete(key)
+            }
+        }
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.primary.delete(key)?;
+        self.fallback.delete(key)
+    }
+}
+
+/// Secrets kept in a single ChaCha20-Poly1305 encrypted file. The key is generated on first
+/// use and stored next to it with owner-only permissions, which protects against the tokens
+/// being copied around with the rest of the data directory but not against local users with
+/// access to the account.
+pub struct EncryptedFileStore {
+    path: PathBuf,
+    cipher: ChaCha20Poly1305,
+    lock: Mutex<()>,
+}
+
+impl EncryptedFileStore {
+    pub fn open(dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir).with_context(|| format!("failed to create directory {:?}", dir))?;
+
+        let key = load_or_create_master_key(&dir.join(MASTER_KEY_FILE))?;
+
+        Ok(Self {
+            path: dir.join(SECRETS_FILE),
+            cipher: ChaCha20Poly1305::new(&Key::from(key)),
+            lock: Mutex::new(()),
+        })
+    }
+
+    fn read_all(&self) -> Result<HashMap<String, String>> {
+        let contents = match fs::read(&self.path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+            Err(err) => return Err(anyhow!(err).context("failed to read secrets file")),
+        };
+
+        if contents.len() < NONCE_LEN {
+            return Err(anyhow!("secrets file is truncated"));
+        }
+
+        let (nonce, ciphertext) = contents.split_at(NONCE_LEN);
+        let nonce: [u8; NONCE_LEN] = nonce.try_into()?;
+        let plaintext = self
+            .cipher
+            .decrypt(&Nonce::from(nonce), ciphertext)
+            .map_err(|_| anyhow!("failed to decrypt secrets file, was the key replaced?"))?;
+
+        serde_json::from_slice(&plaintext).context("failed to parse secrets file")
+    }
+
+    fn write_all(&self, secrets: &HashMap<String, String>) -> Result<()> {
+        let plaintext = serde_json::to_vec(secrets).context("failed to serialize secrets")?;
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext.as_slice())
+            .map_err(|_| anyhow!("failed to encrypt secrets"))?;
+
+        let mut contents = nonce.to_vec();
+        contents.extend_from_slice(&ciphertext);
+        write_private(&self.path, &contents).context("failed to write secrets file")
+    }
+
+    fn update(&self, apply: impl FnOnce(&mut HashMap<String, String>)) -> Result<()> {
+        let _guard = self
+            .lock
+            .lock()
+            .map_err(|_| anyhow!("secrets file lock poisoned"))?;
+        let mut secrets = self.read_all()?;
+        apply(&mut secrets);
+        self.write_all(&secrets)
+    }
+}
+
+impl SecretStore for EncryptedFileStore {
+    fn name(&self) -> &'static str {
+        "encrypted-file"
+    }
+
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        let _guard = self
+            .lock
+            .lock()
+            .map_err(|_| anyhow!("secrets file lock poisoned"))?;
+        Ok(self.read_all()?.remove(key))
+    }
+
+    fn set(&self, key: &str, value: &str) -> Result<()> {
+        self.update(|secrets| {
+            secrets.insert(key.to_string(), value.to_string());
+        })
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.update(|secrets| {
+            secrets.remove(key);
+        })
+    }
+}
+
+/// Keeps secrets for the lifetime of the process only; meant for tests and throwaway setups.
+#[derive(Default)]
+pub struct MemoryStore {
+    secrets: Mutex<HashMap<String, String>>,
+    max_len: Option<usize>,
+}
+
+impl MemoryStore {
+    /// Refuses values longer than `max_len` bytes, like keyrings with a size limit do.
+    #[cfg(test)]
+    pub fn with_limit(max_len: usize) -> Self {
+        Self {
+            max_len: Some(max_len),
+            ..Self::default()
+        }
+    }
+}
+
+impl SecretStore for MemoryStore {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn get(&self, key: &str) -> Result<Option<String>> {
+        let guard = self
+            .secrets
+            .lock()
+            .map_err(|_| anyhow!("memory store lock poisoned"))?;
+        Ok(guard.get(key).cloned())
+    }
+
+    fn set(&self, key: &str, value: &str) -> Result<()> {
+        if self.max_len.is_some_and(|max_len| value.len() > max_len) {
+            return Err(anyhow!("secret {} is too large", key));
+        }
+
+        self.secrets
+            .lock()
+            .map_err(|_| anyhow!("memory store lock poisoned"))?
+            .insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.secrets
+            .lock()
+            .map_err(|_| anyhow!("memory store lock poisoned"))?
+            .remove(key);
+        Ok(())
+    }
+}
+
+fn load_or_create_master_key(path: &Path) -> Result<[u8; 32]> {
+    if let Ok(contents) = fs::read(path) {
+        return contents
+            .try_into()
+            .map_err(|_| anyhow!("master key at {:?} has an unexpected length", path));
+    }
+
+    let mut key = [0u8; 32];
+    OsRng.fill_bytes(&mut key);
+    write_private(path, &key).context("failed to write master key")?;
+    Ok(key)
+}
+
+#[cfg(unix)]
+fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .with_context(|| format!("failed to open {:?}", path))?;
+    file.write_all(contents)
+        .with_context(|| format!("failed to write {:?}", path))
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    fs::write(path, contents).with_context(|| format!("failed to write {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn lazy_store_opens_on_first_use_only() {
+        let opened = Arc::new(AtomicUsize::new(0));
+        let counter = opened.clone();
+        let store = LazyStore::new(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(Box::new(MemoryStore::default()))
+        });
+
+        assert_eq!(opened.load(Ordering::SeqCst), 0);
+        assert_eq!(store.name(), "lazy");
+
+        store.set("account:a", "secret").unwrap();
+        assert_eq!(store.get("account:a").unwrap().as_deref(), Some("secret"));
+        store.delete("account:a").unwrap();
+        assert_eq!(store.get("account:a").unwrap(), None);
+
+        assert_eq!(opened.load(Ordering::SeqCst), 1);
+        assert_eq!(store.name(), "memory");
+    }
+
+    #[test]
+    fn lazy_store_retries_a_failed_open() {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counter = attempts.clone();
+        let store = LazyStore::new(move || {
+            if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                Err(anyhow!("keyring is locked"))
+            } else {
+                Ok(Box::new(MemoryStore::default()))
+            }
+        });
+
+        assert!(store.get("account:a").is_err());
+        assert_eq!(store.get("account:a").unwrap(), None);
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn fallback_store_keeps_refused_secrets() {
+        let store = FallbackStore::new(MemoryStore::with_limit(8), MemoryStore::default());
+
+        store.set("account:a", "short").unwrap();
+        store.set("account:b", "much longer than eight").unwrap();
+        assert_eq!(
+            store.primary.get("account:a").unwrap().as_deref(),
+            Some("short")
+        );
+        assert_eq!(store.primary.get("account:b").unwrap(), None);
+        assert_eq!(
+            store.get("account:b").unwrap().as_deref(),
+            Some("much longer than eight")
+        );
+
+        // a secret that fits again moves back, without a stale copy left behind
+        store.set("account:b", "short").unwrap();
+        assert_eq!(store.fallback.get("account:b").unwrap(), None);
+        assert_eq!(store.get("account:b").unwrap().as_deref(), Some("short"));
+
+        store.set("account:a", "much longer than eight").unwrap();
+        assert_eq!(store.primary.get("account:a").unwrap(), None);
+        store.delete("account:a").unwrap();
+        assert_eq!(store.get("account:a").unwrap(), None);
+    }
+
+    #[test]
+    fn encrypted_file_store_survives_reopening() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let store = EncryptedFileStore::open(dir.path()).unwrap();
+        store.set("account:a", "first").unwrap();
+        store.set("account:b", "second").unwrap();
+        store.delete("account:b").unwrap();
+
+        let reopened = EncryptedFileStore::open(dir.path()).unwrap();
+        assert_eq!(reopened.get("account:a").unwrap().as_deref(), Some("first"));
+        assert_eq!(reopened.get("account:b").unwrap(), None);
+        assert!(!fs::read(dir.path().join(SECRETS_FILE))
+            .unwrap()
+            .windows(5)
+            .any(|window| window == b"first"));
+    }
+}
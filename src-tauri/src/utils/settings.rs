@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, Runtime};
+
+pub const DEFAULT_MIN_MEMORY: u32 = 512;
+pub const DEFAULT_MAX_MEMORY: u32 = 2048;
+
+/// Launch options that can be set globally and overridden per instance. Every field is
+/// optional so an instance only stores what it actually overrides.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct LaunchSettings {
+    /// Initial heap size in MiB.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_memory: Option<u32>,
+    /// Maximum heap size in MiB.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_memory: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jvm_args: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fullscreen: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env: Option<HashMap<String, String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub java_path: Option<PathBuf>,
+    /// Command the game is started through, e.g. `gamemoderun` or `mangohud`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wrapper: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct AccountSettings {
+    /// Hides offline accounts and stops new ones from being created or launched.
+    #[serde(default)]
+    pub disable_offline_accounts: bool,
+    /// The Yggdrasil server used when signing in without naming one, e.g. a skin site's
+    /// `https://example.com/api/yggdrasil`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub yggdrasil_server: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Settings {
+    #[serde(default)]
+    pub launch: LaunchSettings,
+    #[serde(default)]
+    pub accounts: AccountSettings,
+}
+
+impl LaunchSettings {
+    /// Fills every field this instance leaves unset from `defaults`.
+    pub fn or(&self, defaults: &LaunchSettings) -> LaunchSettings {
+        LaunchSettings {
+            min_memory: self.min_memory.or(defaults.min_memory),
+            max_memory: self.max_memory.or(defaults.max_memory),
+            jvm_args: self.jvm_args.clone().or_else(|| defaults.jvm_args.clone()),
+            width: self.width.or(defaults.width),
+            height: self.height.or(defaults.height),
+            fullscreen: self.fullscreen.or(defaults.fullscreen),
+            env: self.env.clone().or_else(|| defaults.env.clone()),
+            java_path: self
+                .java_path
+                .clone()
+                .or_else(|| defaults.java_path.clone()),
+            wrapper: self.wrapper.clone().or_else(|| defaults.wrapper.clone()),
+        }
+    }
+
+    /// Rejects a minimum heap larger than the maximum. Either bound may come from the global
+    /// settings or the defaults, so this is meant for settings that are already merged.
+    pub fn validate_memory(&self) -> Result<()> {
+        let min = self.min_memory.unwrap_or(DEFAULT_MIN_MEMORY);
+        let max = self.max_memory.unwrap_or(DEFAULT_MAX_MEMORY);
+
+        if min > max {
+            return Err(anyhow!(
+                "minimum memory ({} MiB) cannot exceed maximum memory ({} MiB)",
+                min,
+                max
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn resolution(&self) -> Option<(u32, u32)> {
+        self.width.zip(self.height)
+    }
+
+    /// Program and leading arguments of the wrapper command, if one is set.
+    pub fn wrapper_command(&self) -> Option<(String, Vec<String>)> {
+        let mut parts = self
+            .wrapper
+            .as_deref()?
+            .split_whitespace()
+            .map(str::to_string);
+        let program = parts.next()?;
+        Some((program, parts.collect()))
+    }
+}
+
+pub fn read_settings<R: Runtime>(app: &AppHandle<R>) -> Result<Settings> {
+    let path = settings_path(app)?;
+
+    if !path.exists() {
+        return Ok(Settings::default());
+    }
+
+    let contents = fs::read_to_string(&path).context("failed to read settings.json")?;
+    if contents.trim().is_empty() {
+        return Ok(Settings::default());
+    }
+
+    serde_json::from_str(&contents).context("failed to parse settings.json")
+}
+
+pub fn write_settings<R: Runtime>(app: &AppHandle<R>, settings: &Settings) -> Result<()> {
+    let path = settings_path(app)?;
+    let serialized =
+        serde_json::to_string_pretty(settings).context("failed to serialize settings")?;
+    fs::write(path, serialized).context("failed to write settings.json")
+}
+
+fn settings_path<R: Runtime>(app: &AppHandle<R>) -> Result<PathBuf> {
+    let mut data_dir = app
+        .path()
+        .app_data_dir()
+        .context("failed to resolve app data directory")?;
+
+    fs::create_dir_all(&data_dir).context("failed to create app data directory")?;
+    data_dir.push("settings.json");
+    Ok(data_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory(min: Option<u32>, max: Option<u32>) -> LaunchSettings {
+        LaunchSettings {
+            min_memory: min,
+            max_memory: max,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn memory_bounds_are_checked_after_merging() {
+        let global = memory(Some(1024), Some(8192));
+
+        assert!(memory(Some(4096), None)
+            .or(&global)
+            .validate_memory()
+            .is_ok());
+        assert!(memory(None, Some(512))
+            .or(&global)
+            .validate_memory()
+            .is_err());
+        assert!(memory(Some(2048), Some(1024))
+            .or(&global)
+            .validate_memory()
+            .is_err());
+    }
+
+    #[test]
+    fn memory_bounds_fall_back_to_defaults() {
+        assert!(LaunchSettings::default().validate_memory().is_ok());
+        assert!(memory(Some(DEFAULT_MAX_MEMORY + 1), None)
+            .validate_memory()
+            .is_err());
+        assert!(memory(None, Some(DEFAULT_MIN_MEMORY - 1))
+            .validate_memory()
+            .is_err());
+    }
+}
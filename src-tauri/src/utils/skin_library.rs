@@ -0,0 +1,539 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, Runtime};
+use uuid::Uuid;
+
+use crate::utils::files::write_replacing;
+use crate::utils::minecraft_services::SkinVariant;
+
+const INDEX_FILE: &str = "index.json";
+const THUMBNAIL_SUFFIX: &str = ".preview.json";
+
+/// Serialises changes to `index.json`, so two commands editing the library at once do not
+/// drop each other's change. Registered as managed state.
+#[derive(Default)]
+pub struct SkinLibraryLock(Mutex<()>);
+
+/// The skins kept in the launcher's wardrobe: one PNG per preset under `skins/`, next to an
+/// `index.json` describing them.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct SkinLibrary {
+    #[serde(default)]
+    pub presets: Vec<SkinPreset>,
+    /// The preset last applied to each account, keyed by account UUID.
+    #[serde(default)]
+    pub applied: HashMap<String, String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SkinPreset {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub model: SkinVariant,
+    #[serde(default)]
+    pub cape: PresetCape,
+    pub added_at: DateTime<Utc>,
+}
+
+/// What applying a preset does with the account's cape.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase", tag = "type")]
+pub enum PresetCape {
+    /// Leave whatever cape the account is wearing.
+    #[default]
+    Keep,
+    Hide,
+    Wear {
+        id: String,
+    },
+}
+
+impl SkinLibrary {
+    pub fn preset(&self, id: &str) -> Result<&SkinPreset> {
+        self.presets
+            .iter()
+            .find(|preset| preset.id == id)
+            .ok_or_else(|| anyhow!("skin preset {} does not exist", id))
+    }
+
+    fn preset_mut(&mut self, id: &str) -> Result<&mut SkinPreset> {
+        self.presets
+            .iter_mut()
+            .find(|preset| preset.id == id)
+            .ok_or_else(|| anyhow!("skin preset {} does not exist", id))
+    }
+
+    /// The accounts whose last applied preset is `id`.
+    pub fn applied_to(&self, id: &str) -> Vec<String> {
+        self.applied
+            .iter()
+            .filter(|(_, preset)| preset.as_str() == id)
+            .map(|(uuid, _)| uuid.clone())
+            .collect()
+    }
+}
+
+pub fn read_library<R: Runtime>(app: &AppHandle<R>) -> Result<SkinLibrary> {
+    read_index(&library_dir(app)?)
+}
+
+impl SkinLibraryLock {
+    /// Runs `apply` on the library in `dir` and writes the result back, holding the lock in
+    /// between.
+    fn update<T>(
+        &self,
+        dir: &Path,
+        apply: impl FnOnce(&mut SkinLibrary) -> Result<T>,
+    ) -> Result<T> {
+        let _guard = self
+            .0
+            .lock()
+            .map_err(|_| anyhow!("skin library lock poisoned"))?;
+
+        let mut library = read_index(dir)?;
+        let value = apply(&mut library)?;
+        write_index(dir, &library)?;
+        Ok(value)
+    }
+
+    fn add_preset(
+        &self,
+        dir: &Path,
+        name: &str,
+        model: SkinVariant,
+        cape: PresetCape,
+        png: &[u8],
+    ) -> Result<SkinPreset> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(anyhow!("skin presets need a name"));
+        }
+
+        let preset = SkinPreset {
+            id: Uuid::new_v4().simple().to_string(),
+            name: name.to_string(),
+            model,
+            cape,
+            added_at: Utc::now(),
+        };
+
+        let path = skin_file(dir, &preset.id)?;
+        fs::write(&path, png).with_context(|| format!("failed to write {:?}", path))?;
+
+        let added = self.update(dir, |library| {
+            library.presets.push(preset.clone());
+            Ok(())
+        });
+        if added.is_err() {
+            // nothing refers to the file without its entry in the index
+            let _ = fs::remove_file(&path);
+        }
+        added?;
+
+        Ok(preset)
+    }
+
+    fn update_preset(
+        &self,
+        dir: &Path,
+        id: &str,
+        name: Option<String>,
+        model: Option<SkinVariant>,
+        cape: Option<PresetCape>,
+    ) -> Result<SkinPreset> {
+        self.update(dir, |library| {
+            let preset = library.preset_mut(id)?;
+
+            if let Some(name) = name {
+                let name = name.trim();
+                if name.is_empty() {
+                    return Err(anyhow!("skin presets need a name"));
+                }
+                preset.name = name.to_string();
+            }
+            if let Some(model) = model {
+                preset.model = model;
+            }
+            if let Some(cape) = cape {
+                preset.cape = cape;
+            }
+
+            Ok(preset.clone())
+        })
+    }
+
+    fn remove_preset(&self, dir: &Path, id: &str) -> Result<()> {
+        let path = skin_file(dir, id)?;
+        self.update(dir, |library| {
+            library.preset(id)?;
+            library.presets.retain(|preset| preset.id != id);
+            library.applied.retain(|_, preset| preset != id);
+            Ok(())
+        })?;
+
+        if path.exists() {
+            fs::remove_file(&path).with_context(|| format!("failed to remove {:?}", path))?;
+        }
+        remove_thumbnails(dir, id)
+    }
+
+    fn mark_applied(&self, dir: &Path, account_uuid: &str, id: &str) -> Result<()> {
+        self.update(dir, |library| {
+            library
+                .applied
+                .insert(account_uuid.replace('-', ""), id.to_string());
+            Ok(())
+        })
+    }
+}
+
+fn read_index(dir: &Path) -> Result<SkinLibrary> {
+    let path = dir.join(INDEX_FILE);
+
+    if !path.exists() {
+        return Ok(SkinLibrary::default());
+    }
+
+    let contents = fs::read_to_string(&path).context("failed to read skin library index")?;
+    if contents.trim().is_empty() {
+        return Ok(SkinLibrary::default());
+    }
+
+    serde_json::from_str(&contents).context("failed to parse skin library index")
+}
+
+fn write_index(dir: &Path, library: &SkinLibrary) -> Result<()> {
+    let serialized =
+        serde_json::to_string_pretty(library).context("failed to serialize skin library index")?;
+    write_replacing(&dir.join(INDEX_FILE), serialized.as_bytes())
+        .context("failed to write skin library index")
+}
+
+/// Stores `png`, which the caller has validated, as a new preset.
+pub fn add_preset<R: Runtime>(
+    app: &AppHandle<R>,
+    name: &str,
+    model: SkinVariant,
+    cape: PresetCape,
+    png: &[u8],
+) -> Result<SkinPreset> {
+    app.state::<SkinLibraryLock>()
+        .add_preset(&library_dir(app)?, name, model, cape, png)
+}
+
+pub fn update_preset<R: Runtime>(
+    app: &AppHandle<R>,
+    id: &str,
+    name: Option<String>,
+    model: Option<SkinVariant>,
+    cape: Option<PresetCape>,
+) -> Result<SkinPreset> {
+    app.state::<SkinLibraryLock>()
+        .update_preset(&library_dir(app)?, id, name, model, cape)
+}
+
+pub fn remove_preset<R: Runtime>(app: &AppHandle<R>, id: &str) -> Result<()> {
+    app.state::<SkinLibraryLock>()
+        .remove_preset(&library_dir(app)?, id)
+}
+
+/// Remembers that `account_uuid` now wears preset `id`.
+pub fn mark_applied<R: Runtime>(app: &AppHandle<R>, account_uuid: &str, id: &str) -> Result<()> {
+    app.state::<SkinLibraryLock>()
+        .mark_applied(&library_dir(app)?, account_uuid, id)
+}
+
+pub fn read_skin<R: Runtime>(app: &AppHandle<R>, id: &str) -> Result<Vec<u8>> {
+    let path = skin_file(&library_dir(app)?, id)?;
+    fs::read(&path).with_context(|| format!("failed to read {:?}", path))
+}
+
+/// Where the preview of preset `id` rendered for `model` at `scale` is cached. Presets never
+/// change their PNG, so a cached preview stays valid until the preset is removed.
+pub fn thumbnail_path<R: Runtime>(
+    app: &AppHandle<R>,
+    id: &str,
+    model: SkinVariant,
+    scale: u32,
+) -> Result<PathBuf> {
+    thumbnail_file(&library_dir(app)?, id, model, scale)
+}
+
+fn skin_file(dir: &Path, id: &str) -> Result<PathBuf> {
+    check_id(id)?;
+    Ok(dir.join(format!("{}.png", id)))
+}
+
+fn thumbnail_file(dir: &Path, id: &str, model: SkinVariant, scale: u32) -> Result<PathBuf> {
+    check_id(id)?;
+    Ok(dir.join(format!(
+        "{}-{}-x{}{}",
+        id,
+        model.as_str(),
+        scale,
+        THUMBNAIL_SUFFIX
+    )))
+}
+
+fn remove_thumbnails(dir: &Path, id: &str) -> Result<()> {
+    let prefix = format!("{}-", id);
+    let entries = fs::read_dir(dir).with_context(|| format!("failed to read {:?}", dir))?;
+
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with(&prefix) && name.ends_with(THUMBNAIL_SUFFIX) {
+            let path = entry.path();
+            fs::remove_file(&path).with_context(|| format!("failed to remove {:?}", path))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Ids are generated by the launcher, anything else must not reach the file system.
+fn check_id(id: &str) -> Result<()> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(anyhow!("invalid skin preset id '{}'", id));
+    }
+    Ok(())
+}
+
+fn library_dir<R: Runtime>(app: &AppHandle<R>) -> Result<PathBuf> {
+    let dir = app
+        .path()
+        .app_data_dir()
+        .context("failed to resolve app data directory")?
+        .join("skins");
+    fs::create_dir_all(&dir).context("failed to create skin library directory")?;
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn updates_are_written_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock = SkinLibraryLock::default();
+        assert!(read_index(dir.path()).unwrap().presets.is_empty());
+
+        lock.update(dir.path(), |library| {
+            library
+                .applied
+                .insert("account".to_string(), "preset".to_string());
+            Ok(())
+        })
+        .unwrap();
+
+        let library = read_index(dir.path()).unwrap();
+        assert_eq!(library.applied_to("preset"), ["account"]);
+        let names: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, [INDEX_FILE]);
+    }
+
+    #[test]
+    fn failed_updates_leave_the_index_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock = SkinLibraryLock::default();
+        lock.update(dir.path(), |library| {
+            library
+                .applied
+                .insert("account".to_string(), "first".to_string());
+            Ok(())
+        })
+        .unwrap();
+
+        let result: Result<()> = lock.update(dir.path(), |library| {
+            library
+                .applied
+                .insert("account".to_string(), "second".to_string());
+            Err(anyhow!("skin preset second does not exist"))
+        });
+
+        assert!(result.is_err());
+        assert_eq!(read_index(dir.path()).unwrap().applied["account"], "first");
+    }
+
+    #[test]
+    fn concurrent_updates_are_all_kept() {
+        let dir = Arc::new(tempfile::tempdir().unwrap());
+        let lock = Arc::new(SkinLibraryLock::default());
+
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let (dir, lock) = (dir.clone(), lock.clone());
+                thread::spawn(move || {
+                    lock.update(dir.path(), |library| {
+                        library
+                            .applied
+                            .insert(format!("account-{}", i), "preset".to_string());
+                        Ok(())
+                    })
+                    .unwrap();
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(read_index(dir.path()).unwrap().applied.len(), 8);
+    }
+
+    fn presets(dir: &Path) -> Vec<String> {
+        let library = read_index(dir).unwrap();
+        library
+            .presets
+            .iter()
+            .map(|preset| preset.name.clone())
+            .collect()
+    }
+
+    #[test]
+    fn presets_can_be_added_updated_and_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock = SkinLibraryLock::default();
+
+        let preset = lock
+            .add_preset(
+                dir.path(),
+                " Knight ",
+                SkinVariant::Classic,
+                PresetCape::Keep,
+                b"png",
+            )
+            .unwrap();
+        assert_eq!(presets(dir.path()), ["Knight"]);
+        assert_eq!(
+            fs::read(skin_file(dir.path(), &preset.id).unwrap()).unwrap(),
+            b"png"
+        );
+
+        let updated = lock
+            .update_preset(
+                dir.path(),
+                &preset.id,
+                Some("Dark Knight".to_string()),
+                Some(SkinVariant::Slim),
+                Some(PresetCape::Hide),
+            )
+            .unwrap();
+        assert_eq!(updated.name, "Dark Knight");
+        assert_eq!(updated.model, SkinVariant::Slim);
+        assert_eq!(updated.cape, PresetCape::Hide);
+        assert!(lock
+            .update_preset(dir.path(), &preset.id, Some(" ".to_string()), None, None)
+            .is_err());
+        assert_eq!(presets(dir.path()), ["Dark Knight"]);
+
+        lock.remove_preset(dir.path(), &preset.id).unwrap();
+        assert!(presets(dir.path()).is_empty());
+        assert!(!skin_file(dir.path(), &preset.id).unwrap().exists());
+        assert!(lock.remove_preset(dir.path(), &preset.id).is_err());
+    }
+
+    #[test]
+    fn removing_a_preset_forgets_where_it_was_applied() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock = SkinLibraryLock::default();
+        let add = |name| {
+            lock.add_preset(
+                dir.path(),
+                name,
+                SkinVariant::Classic,
+                PresetCape::Keep,
+                b"png",
+            )
+            .unwrap()
+        };
+        let (first, second) = (add("First"), add("Second"));
+        lock.mark_applied(
+            dir.path(),
+            "069a79f4-44e9-4726-a5be-fca90e38aaf5",
+            &first.id,
+        )
+        .unwrap();
+        lock.mark_applied(dir.path(), "853c80ef3c3749fdaa49938b674adae6", &second.id)
+            .unwrap();
+        let thumbnail = thumbnail_file(dir.path(), &first.id, SkinVariant::Classic, 4).unwrap();
+        fs::write(&thumbnail, "{}").unwrap();
+
+        lock.remove_preset(dir.path(), &first.id).unwrap();
+
+        let library = read_index(dir.path()).unwrap();
+        assert!(library.applied_to(&first.id).is_empty());
+        assert_eq!(
+            library.applied_to(&second.id),
+            ["853c80ef3c3749fdaa49938b674adae6"]
+        );
+        assert!(!thumbnail.exists());
+    }
+
+    #[test]
+    fn failed_adds_leave_no_file_behind() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(INDEX_FILE), "not json").unwrap();
+
+        let result = SkinLibraryLock::default().add_preset(
+            dir.path(),
+            "Knight",
+            SkinVariant::Classic,
+            PresetCape::Keep,
+            b"png",
+        );
+
+        assert!(result.is_err());
+        let names: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, [INDEX_FILE]);
+    }
+
+    #[test]
+    fn preset_capes_are_tagged_by_type() {
+        for (cape, json) in [
+            (PresetCape::Keep, serde_json::json!({ "type": "keep" })),
+            (PresetCape::Hide, serde_json::json!({ "type": "hide" })),
+            (
+                PresetCape::Wear {
+                    id: "cape-id".to_string(),
+                },
+                serde_json::json!({ "type": "wear", "id": "cape-id" }),
+            ),
+        ] {
+            assert_eq!(serde_json::to_value(&cape).unwrap(), json);
+            assert_eq!(serde_json::from_value::<PresetCape>(json).unwrap(), cape);
+        }
+    }
+
+    #[test]
+    fn preset_ids_cannot_leave_the_library() {
+        let dir = Path::new("/skins");
+        for id in ["../x", "", "a/b", "x.png"] {
+            assert!(skin_file(dir, id).is_err(), "{:?}", id);
+            assert!(
+                thumbnail_file(dir, id, SkinVariant::Slim, 1).is_err(),
+                "{:?}",
+                id
+            );
+        }
+        assert_eq!(skin_file(dir, "abc123").unwrap(), dir.join("abc123.png"));
+    }
+}
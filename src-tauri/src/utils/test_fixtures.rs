@@ -0,0 +1,21 @@
+//! Loaders for the files under `tests/fixtures`, shared by the test modules.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::utils::version_json::VersionJson;
+
+pub fn fixture_path(relative: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(relative)
+}
+
+/// The client JSON `tests/fixtures/versions/<id>.json`.
+pub fn version_json(id: &str) -> VersionJson {
+    let path = fixture_path(&format!("versions/{}.json", id));
+    let contents = fs::read_to_string(&path)
+        .unwrap_or_else(|err| panic!("failed to read {:?}: {}", path, err));
+    serde_json::from_str(&contents)
+        .unwrap_or_else(|err| panic!("failed to parse {:?}: {}", path, err))
+}
@@ -0,0 +1,500 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use anyhow::{anyhow, Context, Result};
+use base64::Engine;
+use log::{debug, warn};
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tauri::{AppHandle, Manager, Runtime};
+
+use crate::utils::env_url;
+use crate::utils::files::write_replacing;
+use crate::utils::minecraft_auth::current_timestamp;
+
+pub const SESSION_SERVER_URL: &str = "https://sessionserver.mojang.com";
+
+/// How long the textures of a profile are used without asking the session server again.
+const PROFILE_TTL_SECS: u64 = 10 * 60;
+
+/// How long a profile that was not looked up again is kept around for offline use.
+const PROFILE_MAX_AGE_SECS: u64 = 30 * 24 * 60 * 60;
+
+const PROFILES_FILE: &str = "profiles.json";
+
+/// Skin and cape textures on disk under `cache/textures`, plus the textures property of each
+/// profile looked up, kept for [`PROFILE_TTL_SECS`] and persisted so faces render offline.
+/// Registered as managed state.
+#[derive(Default)]
+pub struct TextureCache {
+    client: Client,
+    profiles: Mutex<Option<HashMap<String, CachedProfile>>>,
+}
+
+/// What the `textures` property of a profile points at.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ProfileTextures {
+    pub skin_url: Option<String>,
+    pub cape_url: Option<String>,
+    /// The skin is drawn for the slim (3 pixel arm) model.
+    #[serde(default)]
+    pub slim: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct CachedProfile {
+    #[serde(flatten)]
+    textures: ProfileTextures,
+    /// The session server knows no profile with this UUID.
+    #[serde(default)]
+    unknown: bool,
+    fetched_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_modified: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SessionProfile {
+    #[serde(default)]
+    properties: Vec<SessionProperty>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SessionProperty {
+    name: String,
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TexturesPayload {
+    #[serde(default)]
+    textures: HashMap<String, TextureEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TextureEntry {
+    url: String,
+    #[serde(default)]
+    metadata: Option<TextureMetadata>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TextureMetadata {
+    #[serde(default)]
+    model: Option<String>,
+}
+
+/// Returns the textures of profile `uuid`, or `None` when there is no such profile. Within
+/// the TTL the cached entry is used as is; after it the session server is asked again,
+/// conditionally when it sent validators. If that fails (offline, rate limited) a stale entry
+/// is still better than nothing.
+pub async fn profile_textures<R: Runtime>(
+    app: &AppHandle<R>,
+    uuid: &str,
+) -> Result<Option<ProfileTextures>> {
+    let cache = app.state::<TextureCache>();
+    let uuid = uuid.replace('-', "").to_ascii_lowercase();
+    let cached = cache.with_profiles(app, |profiles| profiles.get(&uuid).cloned())?;
+
+    if let Some(cached) = &cached {
+        if current_timestamp().saturating_sub(cached.fetched_at) < PROFILE_TTL_SECS {
+            return Ok(cached.known_textures());
+        }
+    }
+
+    let base_url = env_url("DECENT_SESSION_SERVER_URL", SESSION_SERVER_URL);
+    let fetched = match fetch_profile(&cache.client, &base_url, &uuid, cached.as_ref()).await {
+        Ok(fetched) => fetched,
+        Err(err) => {
+            return match cached {
+                Some(cached) => {
+                    warn!("using cached textures of {}: {:#}", uuid, err);
+                    Ok(cached.known_textures())
+                }
+                None => Err(err),
+            }
+        }
+    };
+
+    let textures = fetched.known_textures();
+    cache.with_profiles(app, |profiles| {
+        profiles.insert(uuid.clone(), fetched);
+    })?;
+    persist_profiles(app, &cache)?;
+
+    Ok(textures)
+}
+
+/// Drops the cached textures of `uuid`, e.g. after its skin was changed from the launcher.
+pub fn forget_profile<R: Runtime>(app: &AppHandle<R>, uuid: &str) -> Result<()> {
+    let cache = app.state::<TextureCache>();
+    let uuid = uuid.replace('-', "").to_ascii_lowercase();
+    cache.with_profiles(app, |profiles| {
+        profiles.remove(&uuid);
+    })?;
+    persist_profiles(app, &cache)
+}
+
+/// Returns the texture at `url`, downloading it only the first time. Texture URLs name their
+/// content, so a cached file never has to be revalidated.
+pub async fn texture<R: Runtime>(app: &AppHandle<R>, url: &str) -> Result<Vec<u8>> {
+    let path = cache_dir(app)?.join("textures").join(texture_key(url));
+
+    if let Ok(bytes) = fs::read(&path) {
+        return Ok(bytes);
+    }
+
+    let cache = app.state::<TextureCache>();
+    let bytes = cache
+        .client
+        .get(url)
+        .send()
+        .await
+        .with_context(|| format!("failed to request {}", url))?
+        .error_for_status()
+        .with_context(|| format!("{} returned non-success status", url))?
+        .bytes()
+        .await
+        .with_context(|| format!("failed to read {}", url))?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create directory {:?}", parent))?;
+    }
+    write_replacing(&path, &bytes)?;
+
+    Ok(bytes.to_vec())
+}
+
+impl CachedProfile {
+    fn known_textures(&self) -> Option<ProfileTextures> {
+        (!self.unknown).then(|| self.textures.clone())
+    }
+}
+
+impl TextureCache {
+    /// Runs `apply` on the profile entries, loading them from disk on first use.
+    fn with_profiles<R: Runtime, T>(
+        &self,
+        app: &AppHandle<R>,
+        apply: impl FnOnce(&mut HashMap<String, CachedProfile>) -> T,
+    ) -> Result<T> {
+        let mut guard = self
+            .profiles
+            .lock()
+            .map_err(|_| anyhow!("texture cache lock poisoned"))?;
+
+        if guard.is_none() {
+            *guard = Some(load_profiles(app));
+        }
+
+        Ok(apply(guard.get_or_insert_with(HashMap::new)))
+    }
+}
+
+async fn fetch_profile(
+    client: &Client,
+    base_url: &str,
+    uuid: &str,
+    cached: Option<&CachedProfile>,
+) -> Result<CachedProfile> {
+    let url = format!("{}/session/minecraft/profile/{}", base_url, uuid);
+
+    let mut request = client.get(&url);
+    if let Some(cached) = cached {
+        if let Some(etag) = &cached.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &cached.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+
+    let response = request
+        .send()
+        .await
+        .context("failed to request session profile")?;
+
+    if response.status() == StatusCode::NOT_MODIFIED {
+        if let Some(cached) = cached {
+            debug!("textures of {} are unchanged", uuid);
+            return Ok(CachedProfile {
+                fetched_at: current_timestamp(),
+                ..cached.clone()
+            });
+        }
+    }
+
+    // an unknown or deleted profile has no textures; remember that too
+    if matches!(
+        response.status(),
+        StatusCode::NO_CONTENT | StatusCode::NOT_FOUND
+    ) {
+        return Ok(CachedProfile {
+            textures: ProfileTextures::default(),
+            unknown: true,
+            fetched_at: current_timestamp(),
+            etag: None,
+            last_modified: None,
+        });
+    }
+
+    let response = response
+        .error_for_status()
+        .context("session server returned non-success status")?;
+
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    let etag = header(ETAG);
+    let last_modified = header(LAST_MODIFIED);
+
+    let profile = response
+        .json::<SessionProfile>()
+        .await
+        .context("failed to parse session profile")?;
+
+    Ok(CachedProfile {
+        textures: parse_textures(&profile)?,
+        unknown: false,
+        fetched_at: current_timestamp(),
+        etag,
+        last_modified,
+    })
+}
+
+fn parse_textures(profile: &SessionProfile) -> Result<ProfileTextures> {
+    let Some(property) = profile
+        .properties
+        .iter()
+        .find(|property| property.name == "textures")
+    else {
+        return Ok(ProfileTextures::default());
+    };
+
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(&property.value)
+        .context("failed to decode textures property")?;
+    let mut payload: TexturesPayload =
+        serde_json::from_slice(&decoded).context("failed to parse textures property")?;
+
+    let skin = payload.textures.remove("SKIN");
+    let cape = payload.textures.remove("CAPE");
+
+    Ok(ProfileTextures {
+        slim: skin
+            .as_ref()
+            .and_then(|skin| skin.metadata.as_ref())
+            .and_then(|metadata| metadata.model.as_deref())
+            == Some("slim"),
+        skin_url: skin.map(|skin| skin.url),
+        cape_url: cape.map(|cape| cape.url),
+    })
+}
+
+/// Texture URLs end in the hash of the texture; other URLs are hashed to get a file name.
+fn texture_key(url: &str) -> String {
+    let last_segment = url.rsplit('/').next().unwrap_or_default();
+    if last_segment.len() >= 32 && last_segment.chars().all(|c| c.is_ascii_hexdigit()) {
+        return format!("{}.png", last_segment.to_ascii_lowercase());
+    }
+
+    format!("{:x}.png", Sha256::digest(url.as_bytes()))
+}
+
+fn load_profiles<R: Runtime>(app: &AppHandle<R>) -> HashMap<String, CachedProfile> {
+    let path = match cache_dir(app) {
+        Ok(dir) => dir.join(PROFILES_FILE),
+        Err(err) => {
+            warn!("failed to locate texture cache: {:#}", err);
+            return HashMap::new();
+        }
+    };
+
+    match fs::read_to_string(&path) {
+        Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|err| {
+            warn!("discarding unreadable profile cache: {}", err);
+            HashMap::new()
+        }),
+        Err(_) => HashMap::new(),
+    }
+}
+
+fn persist_profiles<R: Runtime>(app: &AppHandle<R>, cache: &TextureCache) -> Result<()> {
+    let dir = cache_dir(app)?;
+    fs::create_dir_all(&dir).with_context(|| format!("failed to create directory {:?}", dir))?;
+
+    // written while the entries are locked, so concurrent updates land in order
+    cache.with_profiles(app, |profiles| {
+        prune_expired(profiles, current_timestamp());
+        let serialized =
+            serde_json::to_string(profiles).context("failed to serialize profile cache")?;
+        write_replacing(&dir.join(PROFILES_FILE), serialized.as_bytes())
+            .context("failed to write profile cache")
+    })?
+}
+
+fn prune_expired(profiles: &mut HashMap<String, CachedProfile>, now: u64) {
+    profiles.retain(|_, profile| now.saturating_sub(profile.fetched_at) < PROFILE_MAX_AGE_SECS);
+}
+
+fn cache_dir<R: Runtime>(app: &AppHandle<R>) -> Result<PathBuf> {
+    Ok(app
+        .path()
+        .app_data_dir()
+        .context("failed to resolve app data directory")?
+        .join("cache"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_server::{block_on, Response, TestServer};
+
+    const UUID: &str = "069a79f444e94726a5befca90e38aaf5";
+
+    fn session_profile(textures: serde_json::Value) -> serde_json::Value {
+        let value = base64::engine::general_purpose::STANDARD.encode(textures.to_string());
+        serde_json::json!({
+            "id": UUID,
+            "name": "Notch",
+            "properties": [{ "name": "textures", "value": value }]
+        })
+    }
+
+    fn fetch(server: &TestServer, cached: Option<&CachedProfile>) -> Result<CachedProfile> {
+        block_on(fetch_profile(&Client::new(), server.url(), UUID, cached))
+    }
+
+    #[test]
+    fn texture_urls_are_keyed_by_their_hash() {
+        let hash = "3B60A1F6D562F52AAEBBF1434F1DE147933A3AFFE0E764FA49EA057536623CD3";
+        assert_eq!(
+            texture_key(&format!("http://textures.minecraft.net/texture/{}", hash)),
+            format!("{}.png", hash.to_ascii_lowercase())
+        );
+
+        let other = texture_key("https://example.com/skins/steve.png");
+        assert_eq!(other.len(), 64 + ".png".len());
+        assert_ne!(other, texture_key("https://example.com/skins/alex.png"));
+    }
+
+    #[test]
+    fn unknown_profiles_have_no_textures() {
+        for status in [204, 404] {
+            let server = TestServer::start(move |_| Response::status(status));
+
+            let fetched = fetch(&server, None).unwrap();
+            assert!(fetched.unknown);
+            assert!(fetched.known_textures().is_none());
+            assert_eq!(
+                server.requests()[0].path,
+                format!("/session/minecraft/profile/{}", UUID)
+            );
+        }
+    }
+
+    #[test]
+    fn textures_are_read_from_the_profile() {
+        let body = session_profile(serde_json::json!({
+            "textures": {
+                "SKIN": {
+                    "url": "http://textures.minecraft.net/texture/abc",
+                    "metadata": { "model": "slim" }
+                },
+                "CAPE": { "url": "http://textures.minecraft.net/texture/def" }
+            }
+        }));
+        let server = TestServer::start(move |_| {
+            let mut response = Response::json(200, body.clone());
+            response
+                .headers
+                .push(("ETag".to_string(), "\"v1\"".to_string()));
+            response
+        });
+
+        let fetched = fetch(&server, None).unwrap();
+        let textures = fetched.known_textures().unwrap();
+        assert_eq!(
+            textures.skin_url.as_deref(),
+            Some("http://textures.minecraft.net/texture/abc")
+        );
+        assert_eq!(
+            textures.cape_url.as_deref(),
+            Some("http://textures.minecraft.net/texture/def")
+        );
+        assert!(textures.slim);
+        assert_eq!(fetched.etag.as_deref(), Some("\"v1\""));
+    }
+
+    #[test]
+    fn players_with_a_default_skin_exist_without_skin_url() {
+        let body = session_profile(serde_json::json!({ "textures": {} }));
+        let server = TestServer::start(move |_| Response::json(200, body.clone()));
+
+        let textures = fetch(&server, None).unwrap().known_textures().unwrap();
+        assert!(textures.skin_url.is_none());
+        assert!(!textures.slim);
+    }
+
+    #[test]
+    fn unchanged_profiles_keep_the_cached_textures() {
+        let server = TestServer::start(|request| {
+            assert_eq!(request.header("if-none-match"), Some("\"v1\""));
+            Response::status(304)
+        });
+        let cached = CachedProfile {
+            textures: ProfileTextures {
+                skin_url: Some("http://textures.minecraft.net/texture/abc".to_string()),
+                cape_url: None,
+                slim: false,
+            },
+            unknown: false,
+            fetched_at: 0,
+            etag: Some("\"v1\"".to_string()),
+            last_modified: None,
+        };
+
+        let fetched = fetch(&server, Some(&cached)).unwrap();
+        assert!(fetched.fetched_at > 0);
+        assert_eq!(fetched.textures.skin_url, cached.textures.skin_url);
+    }
+
+    #[test]
+    fn server_errors_are_not_unknown_profiles() {
+        let server = TestServer::start(|_| Response::status(500));
+
+        assert!(fetch(&server, None).is_err());
+    }
+
+    #[test]
+    fn profiles_not_looked_up_for_long_are_pruned() {
+        let now = PROFILE_MAX_AGE_SECS * 2;
+        let profile = |fetched_at| CachedProfile {
+            textures: ProfileTextures::default(),
+            unknown: false,
+            fetched_at,
+            etag: None,
+            last_modified: None,
+        };
+        let mut profiles = HashMap::from([
+            ("recent".to_string(), profile(now - PROFILE_TTL_SECS * 2)),
+            ("old".to_string(), profile(now - PROFILE_MAX_AGE_SECS)),
+        ]);
+
+        prune_expired(&mut profiles, now);
+
+        assert!(profiles.contains_key("recent"));
+        assert!(!profiles.contains_key("old"));
+    }
+}
@@ -0,0 +1,692 @@
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+
+use log::warn;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::utils::versions::VersionType;
+
+/// Compiled `os.version` patterns. Rules are evaluated for every library and argument of every
+/// launch while only a handful of distinct patterns exist, so each is compiled once.
+static OS_VERSION_PATTERNS: LazyLock<Mutex<HashMap<String, Option<Regex>>>> =
+    LazyLock::new(Default::default);
+
+/// A per-version client JSON as published by Mojang (or produced by a mod loader installer).
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct VersionJson {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inherits_from: Option<String>,
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<VersionType>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub main_class: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub minecraft_arguments: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<Arguments>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub asset_index: Option<AssetIndexRef>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub assets: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub downloads: Option<VersionDownloads>,
+    #[serde(default)]
+    pub libraries: Vec<Library>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub java_version: Option<JavaVersion>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logging: Option<Logging>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release_time: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compliance_level: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Arguments {
+    #[serde(default)]
+    pub game: Vec<Argument>,
+    #[serde(default)]
+    pub jvm: Vec<Argument>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum Argument {
+    Plain(String),
+    Conditional {
+        rules: Vec<Rule>,
+        value: ArgumentValue,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum ArgumentValue {
+    Single(String),
+    Many(Vec<String>),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetIndexRef {
+    pub id: String,
+    pub sha1: String,
+    pub size: u64,
+    #[serde(default)]
+    pub total_size: Option<u64>,
+    pub url: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Download {
+    pub sha1: String,
+    pub size: u64,
+    pub url: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct VersionDownloads {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client: Option<Download>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_mappings: Option<Download>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server: Option<Download>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_mappings: Option<Download>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Library {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub downloads: Option<LibraryDownloads>,
+    /// Maven repository base used by loader libraries that carry no `downloads` block.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rules: Option<Vec<Rule>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub natives: Option<HashMap<String, String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extract: Option<ExtractRules>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct LibraryDownloads {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifact: Option<Artifact>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub classifiers: Option<HashMap<String, Artifact>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Artifact {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    pub sha1: String,
+    pub size: u64,
+    pub url: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ExtractRules {
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct JavaVersion {
+    pub component: String,
+    pub major_version: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Logging {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client: Option<LoggingConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LoggingConfig {
+    pub argument: String,
+    pub file: LoggingFile,
+    #[serde(rename = "type")]
+    pub kind: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LoggingFile {
+    pub id: String,
+    pub sha1: String,
+    pub size: u64,
+    pub url: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Rule {
+    pub action: RuleAction,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os: Option<OsRule>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub features: Option<HashMap<String, bool>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    Allow,
+    Disallow,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct OsRule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Regular expression matched against the OS version string.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arch: Option<String>,
+}
+
+/// The platform and launcher features that `rules` are evaluated against.
+#[derive(Debug, Clone)]
+pub struct RuleContext {
+    pub os_name: String,
+    pub os_version: String,
+    pub arch: String,
+    pub features: HashMap<String, bool>,
+}
+
+impl RuleContext {
+    pub fn current() -> Self {
+        Self {
+            os_name: current_os_name().to_string(),
+            os_version: current_os_version(),
+            arch: current_arch().to_string(),
+            features: HashMap::new(),
+        }
+    }
+
+    pub fn with_feature(mut self, name: &str, enabled: bool) -> Self {
+        self.features.insert(name.to_string(), enabled);
+        self
+    }
+
+    /// Follows the vanilla launcher: with no rules everything is allowed, otherwise the
+    /// action of the last matching rule wins and nothing matching means disallowed.
+    pub fn allows(&self, rules: &[Rule]) -> bool {
+        if rules.is_empty() {
+            return true;
+        }
+
+        let mut allowed = false;
+        for rule in rules {
+            if self.matches(rule) {
+                allowed = rule.action == RuleAction::Allow;
+            }
+        }
+
+        allowed
+    }
+
+    fn matches(&self, rule: &Rule) -> bool {
+        if let Some(os) = &rule.os {
+            if let Some(name) = &os.name {
+                if name != &self.os_name {
+                    return false;
+                }
+            }
+
+            if let Some(arch) = &os.arch {
+                if arch != &self.arch {
+                    return false;
+                }
+            }
+
+            if let Some(pattern) = &os.version {
+                if !matches_os_version(pattern, &self.os_version) {
+                    return false;
+                }
+            }
+        }
+
+        if let Some(features) = &rule.features {
+            for (feature, expected) in features {
+                let enabled = self.features.get(feature).copied().unwrap_or(false);
+                if enabled != *expected {
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+}
+
+/// An invalid pattern never matches.
+fn matches_os_version(pattern: &str, os_version: &str) -> bool {
+    let mut patterns = OS_VERSION_PATTERNS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    patterns
+        .entry(pattern.to_string())
+        .or_insert_with(|| Regex::new(pattern).ok())
+        .as_ref()
+        .is_some_and(|regex| regex.is_match(os_version))
+}
+
+impl Argument {
+    /// Returns the argument values that apply in `context`.
+    pub fn values(&self, context: &RuleContext) -> Vec<String> {
+        match self {
+            Argument::Plain(value) => vec![value.clone()],
+            Argument::Conditional { rules, value } if context.allows(rules) => match value {
+                ArgumentValue::Single(value) => vec![value.clone()],
+                ArgumentValue::Many(values) => values.clone(),
+            },
+            Argument::Conditional { .. } => Vec::new(),
+        }
+    }
+}
+
+impl Library {
+    pub fn applies(&self, context: &RuleContext) -> bool {
+        self.rules
+            .as_deref()
+            .map(|rules| context.allows(rules))
+            .unwrap_or(true)
+    }
+}
+
+impl VersionJson {
+    /// Merges this version over the one it `inheritsFrom`. Scalar fields from the child win,
+    /// libraries are listed child-first and argument lists are appended to the parent's.
+    pub fn merge(self, parent: VersionJson) -> VersionJson {
+        let arguments = match (parent.arguments, self.arguments) {
+            (Some(mut parent_args), Some(child_args)) => {
+                parent_args.game.extend(child_args.game);
+                parent_args.jvm.extend(child_args.jvm);
+                Some(parent_args)
+            }
+            (parent_args, child_args) => child_args.or(parent_args),
+        };
+
+        let mut libraries = self.libraries;
+        libraries.extend(parent.libraries);
+
+        VersionJson {
+            id: self.id,
+            inherits_from: None,
+            kind: self.kind.or(parent.kind),
+            main_class: self.main_class.or(parent.main_class),
+            minecraft_arguments: self.minecraft_arguments.or(parent.minecraft_arguments),
+            arguments,
+            asset_index: self.asset_index.or(parent.asset_index),
+            assets: self.assets.or(parent.assets),
+            downloads: self.downloads.or(parent.downloads),
+            libraries,
+            java_version: self.java_version.or(parent.java_version),
+            logging: self.logging.or(parent.logging),
+            release_time: self.release_time.or(parent.release_time),
+            time: self.time.or(parent.time),
+            compliance_level: self.compliance_level.or(parent.compliance_level),
+        }
+    }
+
+    /// The asset index id, falling back to the legacy `assets` field.
+    pub fn assets_id(&self) -> Option<&str> {
+        self.asset_index
+            .as_ref()
+            .map(|index| index.id.as_str())
+            .or(self.assets.as_deref())
+    }
+}
+
+pub fn current_os_name() -> &'static str {
+    match std::env::consts::OS {
+        "macos" => "osx",
+        other => other,
+    }
+}
+
+pub fn current_arch() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86" => "x86",
+        "x86_64" => "x86_64",
+        "aarch64" => "arm64",
+        other => other,
+    }
+}
+
+/// The version `os.version` rules are matched against, in the form Java reports it: the
+/// kernel release on Linux, `10.0` for Windows 10 and 11, and the product version on macOS.
+/// Empty when it cannot be read, which leaves every `os.version` rule unmatched.
+fn current_os_version() -> String {
+    static OS_VERSION: LazyLock<String> = LazyLock::new(|| {
+        read_os_version().unwrap_or_else(|| {
+            warn!("failed to read the OS version, os.version rules will not match");
+            String::new()
+        })
+    });
+    OS_VERSION.clone()
+}
+
+#[cfg(target_os = "linux")]
+fn read_os_version() -> Option<String> {
+    std::fs::read_to_string("/proc/sys/kernel/osrelease")
+        .ok()
+        .map(|release| release.trim().to_string())
+}
+
+#[cfg(target_os = "macos")]
+fn read_os_version() -> Option<String> {
+    std::fs::read_to_string("/System/Library/CoreServices/SystemVersion.plist")
+        .ok()
+        .and_then(|plist| plist_product_version(&plist))
+}
+
+#[cfg(windows)]
+fn read_os_version() -> Option<String> {
+    #[repr(C)]
+    struct OsVersionInfo {
+        size: u32,
+        major: u32,
+        minor: u32,
+        build: u32,
+        platform: u32,
+        service_pack: [u16; 128],
+    }
+
+    #[link(name = "ntdll")]
+    extern "system" {
+        fn RtlGetVersion(info: *mut OsVersionInfo) -> i32;
+    }
+
+    // unlike GetVersionEx, RtlGetVersion is not capped at 6.2 for unmanifested executables
+    let mut info = OsVersionInfo {
+        size: std::mem::size_of::<OsVersionInfo>() as u32,
+        major: 0,
+        minor: 0,
+        build: 0,
+        platform: 0,
+        service_pack: [0; 128],
+    };
+    // SAFETY: `info` is a correctly sized OSVERSIONINFOW with its size field set
+    let status = unsafe { RtlGetVersion(&mut info) };
+    (status == 0).then(|| format!("{}.{}", info.major, info.minor))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn read_os_version() -> Option<String> {
+    None
+}
+
+/// Reads `ProductVersion` from the XML of `SystemVersion.plist`.
+#[cfg(any(target_os = "macos", test))]
+fn plist_product_version(plist: &str) -> Option<String> {
+    let after_key = &plist[plist.find("<key>ProductVersion</key>")?..];
+    let start = after_key.find("<string>")? + "<string>".len();
+    let end = after_key[start..].find("</string>")? + start;
+    Some(after_key[start..end].trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_fixtures::version_json as fixture;
+
+    fn context(os_name: &str, os_version: &str, arch: &str) -> RuleContext {
+        RuleContext {
+            os_name: os_name.to_string(),
+            os_version: os_version.to_string(),
+            arch: arch.to_string(),
+            features: HashMap::new(),
+        }
+    }
+
+    fn rules(value: serde_json::Value) -> Vec<Rule> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn jvm_arguments(version: &VersionJson, context: &RuleContext) -> Vec<String> {
+        version
+            .arguments
+            .as_ref()
+            .unwrap()
+            .jvm
+            .iter()
+            .flat_map(|argument| argument.values(context))
+            .collect()
+    }
+
+    #[test]
+    fn parses_legacy_version() {
+        let version = fixture("1.8.9");
+
+        assert!(version.arguments.is_none());
+        assert!(version
+            .minecraft_arguments
+            .as_deref()
+            .unwrap()
+            .starts_with("--username ${auth_player_name}"));
+        assert_eq!(version.assets_id(), Some("1.8"));
+        assert_eq!(version.kind, Some(VersionType::Release));
+
+        let platform = &version.libraries[2];
+        assert_eq!(
+            platform.natives.as_ref().unwrap().get("linux").unwrap(),
+            "natives-linux"
+        );
+        assert_eq!(platform.extract.as_ref().unwrap().exclude, ["META-INF/"]);
+    }
+
+    #[test]
+    fn parses_modern_version() {
+        let version = fixture("1.21");
+        let arguments = version.arguments.as_ref().unwrap();
+
+        assert!(version.minecraft_arguments.is_none());
+        assert_eq!(arguments.game.len(), 20);
+        assert_eq!(arguments.jvm.len(), 9);
+        assert_eq!(
+            version.java_version.unwrap().component,
+            "java-runtime-delta"
+        );
+    }
+
+    #[test]
+    fn empty_rules_allow() {
+        assert!(context("linux", "", "x86_64").allows(&[]));
+    }
+
+    #[test]
+    fn no_matching_rule_disallows() {
+        let rules = rules(serde_json::json!([{ "action": "allow", "os": { "name": "osx" } }]));
+
+        assert!(!context("linux", "", "x86_64").allows(&rules));
+        assert!(context("osx", "", "arm64").allows(&rules));
+    }
+
+    #[test]
+    fn last_matching_rule_wins() {
+        let lwjgl = &fixture("1.8.9").libraries[1];
+
+        assert!(lwjgl.applies(&context("linux", "", "x86_64")));
+        assert!(lwjgl.applies(&context("windows", "10.0", "x86_64")));
+        assert!(!lwjgl.applies(&context("osx", "14.5", "arm64")));
+
+        let rules = rules(serde_json::json!([
+            { "action": "disallow", "os": { "name": "linux" } },
+            { "action": "allow" }
+        ]));
+        assert!(context("linux", "", "x86_64").allows(&rules));
+    }
+
+    #[test]
+    fn os_name_selects_libraries() {
+        let version = fixture("1.21");
+        let names = |context: &RuleContext| -> Vec<String> {
+            version
+                .libraries
+                .iter()
+                .filter(|library| library.applies(context))
+                .map(|library| library.name.clone())
+                .collect()
+        };
+
+        assert_eq!(
+            names(&context("linux", "", "x86_64")),
+            [
+                "com.mojang:brigadier:1.2.9",
+                "org.lwjgl:lwjgl:3.3.3:natives-linux"
+            ]
+        );
+        assert_eq!(
+            names(&context("windows", "10.0", "x86_64")),
+            [
+                "com.mojang:brigadier:1.2.9",
+                "org.lwjgl:lwjgl:3.3.3:natives-windows"
+            ]
+        );
+    }
+
+    #[test]
+    fn os_version_and_arch_select_arguments() {
+        let version = fixture("1.21");
+
+        let windows_10 = jvm_arguments(&version, &context("windows", "10.0", "x86"));
+        assert!(windows_10.contains(&"-Dos.name=Windows 10".to_string()));
+        assert!(windows_10.contains(&"-Xss1M".to_string()));
+
+        let windows_11 = jvm_arguments(&version, &context("windows", "11.0", "x86_64"));
+        assert!(!windows_11.contains(&"-Dos.name=Windows 10".to_string()));
+        assert!(!windows_11.contains(&"-Xss1M".to_string()));
+        assert!(windows_11[0].starts_with("-XX:HeapDumpPath="));
+
+        let osx = jvm_arguments(&version, &context("osx", "14.5", "arm64"));
+        assert_eq!(osx[0], "-XstartOnFirstThread");
+    }
+
+    #[test]
+    fn invalid_version_pattern_never_matches() {
+        let rules = rules(serde_json::json!([
+            { "action": "allow", "os": { "version": "^(10" } }
+        ]));
+
+        assert!(!context("windows", "10.0", "x86_64").allows(&rules));
+        assert!(!context("windows", "10.0", "x86_64").allows(&rules));
+    }
+
+    #[test]
+    fn features_must_match() {
+        let version = fixture("1.21");
+        let game = &version.arguments.as_ref().unwrap().game;
+        let linux = context("linux", "", "x86_64");
+
+        assert!(game[18].values(&linux).is_empty());
+        assert_eq!(
+            game[19].values(&linux.with_feature("has_custom_resolution", true)),
+            [
+                "--width",
+                "${resolution_width}",
+                "--height",
+                "${resolution_height}"
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_prefers_child_and_orders_lists() {
+        let merged = fixture("fabric-loader-0.16.0-1.21").merge(fixture("1.21"));
+
+        assert_eq!(merged.id, "fabric-loader-0.16.0-1.21");
+        assert!(merged.inherits_from.is_none());
+        assert_eq!(
+            merged.main_class.as_deref(),
+            Some("net.fabricmc.loader.impl.launch.knot.KnotClient")
+        );
+        assert_eq!(
+            merged.release_time.as_deref(),
+            Some("2024-07-20T12:00:00+00:00")
+        );
+        assert!(merged.downloads.unwrap().client.is_some());
+        assert_eq!(
+            merged.logging.unwrap().client.unwrap().file.id,
+            "client-1.12.xml"
+        );
+
+        let libraries: Vec<&str> = merged
+            .libraries
+            .iter()
+            .map(|library| library.name.as_str())
+            .collect();
+        assert_eq!(
+            libraries,
+            [
+                "net.fabricmc:sponge-mixin:0.15.0+mixin.0.8.7",
+                "net.fabricmc:fabric-loader:0.16.0",
+                "com.mojang:brigadier:1.2.9",
+                "org.lwjgl:lwjgl:3.3.3:natives-linux",
+                "org.lwjgl:lwjgl:3.3.3:natives-windows",
+            ]
+        );
+
+        let arguments = merged.arguments.unwrap();
+        assert_eq!(arguments.game.len(), 20);
+        assert_eq!(arguments.jvm.len(), 10);
+        assert!(matches!(
+            arguments.jvm.last(),
+            Some(Argument::Plain(value)) if value.starts_with("-DFabricMcEmu=")
+        ));
+    }
+
+    #[test]
+    fn merge_keeps_legacy_arguments_of_parent() {
+        let child: VersionJson = serde_json::from_value(serde_json::json!({
+            "id": "1.8.9-forge",
+            "inheritsFrom": "1.8.9",
+            "mainClass": "net.minecraft.launchwrapper.Launch"
+        }))
+        .unwrap();
+
+        let merged = child.merge(fixture("1.8.9"));
+
+        assert!(merged.arguments.is_none());
+        assert_eq!(
+            merged.minecraft_arguments,
+            fixture("1.8.9").minecraft_arguments
+        );
+        assert_eq!(
+            merged.main_class.as_deref(),
+            Some("net.minecraft.launchwrapper.Launch")
+        );
+    }
+
+    #[test]
+    fn product_version_is_read_from_the_plist() {
+        let plist = r#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0">
+<dict>
+	<key>ProductBuildVersion</key>
+	<string>23F79</string>
+	<key>ProductName</key>
+	<string>macOS</string>
+	<key>ProductVersion</key>
+	<string>14.5</string>
+</dict>
+</plist>"#;
+
+        assert_eq!(plist_product_version(plist).as_deref(), Some("14.5"));
+        assert_eq!(plist_product_version("<plist></plist>"), None);
+    }
+}
@@ -0,0 +1,562 @@
+use std::fs;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, Context, Result};
+use log::{debug, warn};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use tauri::{AppHandle, Manager, Runtime};
+
+use crate::utils::env_url;
+use crate::utils::version_json::VersionJson;
+
+pub const PISTON_META_URL: &str = "https://piston-meta.mojang.com";
+
+const VERSION_MANIFEST_PATH: &str = "mc/game/version_manifest_v2.json";
+const VERSION_MANIFEST_FILE: &str = "version_manifest_v2.json";
+const MANIFEST_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+/// A version missing from a manifest younger than this is not worth another download, so
+/// asking for unknown ids over and over does not hammer the meta server.
+const MISS_REFRESH_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const MAX_INHERITANCE_DEPTH: usize = 8;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum VersionType {
+    Release,
+    Snapshot,
+    OldBeta,
+    OldAlpha,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct VersionManifest {
+    pub latest: LatestVersions,
+    pub versions: Vec<VersionEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LatestVersions {
+    pub release: String,
+    pub snapshot: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct VersionEntry {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: VersionType,
+    pub url: String,
+    pub time: String,
+    pub release_time: String,
+    pub sha1: String,
+    #[serde(default)]
+    pub compliance_level: u32,
+}
+
+/// Where the version manifest is fetched from. Defaults to Mojang's piston-meta
+/// host, overridable through `DECENT_META_URL` (e.g. to point at a local fixture server).
+#[derive(Debug, Clone)]
+pub struct ManifestSource {
+    base_url: String,
+}
+
+impl ManifestSource {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(env_url("DECENT_META_URL", PISTON_META_URL))
+    }
+
+    pub fn manifest_url(&self) -> String {
+        format!("{}/{}", self.base_url, VERSION_MANIFEST_PATH)
+    }
+}
+
+impl Default for ManifestSource {
+    fn default() -> Self {
+        Self::new(PISTON_META_URL)
+    }
+}
+
+impl VersionType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VersionType::Release => "release",
+            VersionType::Snapshot => "snapshot",
+            VersionType::OldBeta => "old_beta",
+            VersionType::OldAlpha => "old_alpha",
+        }
+    }
+}
+
+impl VersionManifest {
+    pub fn find(&self, id: &str) -> Option<&VersionEntry> {
+        self.versions.iter().find(|version| version.id == id)
+    }
+
+    pub fn filter(&self, types: &[VersionType]) -> Vec<VersionEntry> {
+        self.versions
+            .iter()
+            .filter(|version| types.is_empty() || types.contains(&version.kind))
+            .cloned()
+            .collect()
+    }
+}
+
+pub async fn load_manifest<R: Runtime>(
+    app: &AppHandle<R>,
+    force_refresh: bool,
+) -> Result<VersionManifest> {
+    let cache_dir = versions_dir(app)?;
+    fetch_manifest(
+        &Client::new(),
+        &ManifestSource::from_env(),
+        &cache_dir,
+        force_refresh,
+    )
+    .await
+}
+
+pub async fn find_version<R: Runtime>(app: &AppHandle<R>, id: &str) -> Result<VersionEntry> {
+    find_in_manifest(
+        &Client::new(),
+        &ManifestSource::from_env(),
+        &versions_dir(app)?,
+        id,
+    )
+    .await
+}
+
+/// Looks `id` up in the manifest, refreshing it once when the cached copy may predate a
+/// freshly published version.
+async fn find_in_manifest(
+    client: &Client,
+    source: &ManifestSource,
+    cache_dir: &Path,
+    id: &str,
+) -> Result<VersionEntry> {
+    let unknown = || anyhow!("unknown Minecraft version '{}'", id);
+    let manifest = fetch_manifest(client, source, cache_dir, false).await?;
+
+    if let Some(entry) = manifest.find(id) {
+        return Ok(entry.clone());
+    }
+    if is_younger_than(
+        &cache_dir.join(VERSION_MANIFEST_FILE),
+        MISS_REFRESH_INTERVAL,
+    ) {
+        return Err(unknown());
+    }
+
+    let manifest = fetch_manifest(client, source, cache_dir, true).await?;
+    manifest.find(id).cloned().ok_or_else(unknown)
+}
+
+/// Returns the manifest from `cache_dir` while it is fresh, otherwise downloads it from
+/// `source`. A stale cache is still used when the network request fails.
+pub async fn fetch_manifest(
+    client: &Client,
+    source: &ManifestSource,
+    cache_dir: &Path,
+    force_refresh: bool,
+) -> Result<VersionManifest> {
+    let cache_path = cache_dir.join(VERSION_MANIFEST_FILE);
+
+    if !force_refresh && is_younger_than(&cache_path, MANIFEST_CACHE_TTL) {
+        match read_cached_manifest(&cache_path) {
+            Ok(manifest) => return Ok(manifest),
+            Err(err) => warn!("ignoring unreadable version manifest cache: {:#}", err),
+        }
+    }
+
+    match download_manifest(client, source).await {
+        Ok(contents) => {
+            let manifest = serde_json::from_str::<VersionManifest>(&contents)
+                .context("failed to parse version manifest")?;
+
+            fs::create_dir_all(cache_dir).context("failed to create versions directory")?;
+            fs::write(&cache_path, contents).context("failed to cache version manifest")?;
+
+            Ok(manifest)
+        }
+        Err(err) if cache_path.exists() => {
+            warn!(
+                "failed to refresh version manifest, using cached copy: {:#}",
+                err
+            );
+            read_cached_manifest(&cache_path)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Loads the client JSON for `id` with its `inheritsFrom` chain merged in. Versions that
+/// already exist under `versions/<id>/<id>.json` (e.g. written by a loader installer) are
+/// used as-is, anything else is downloaded through the manifest and cached there.
+pub async fn resolve_version_json<R: Runtime>(app: &AppHandle<R>, id: &str) -> Result<VersionJson> {
+    let versions_dir = versions_dir(app)?;
+    let client = Client::new();
+    let (client, versions_dir) = (&client, versions_dir.as_path());
+
+    resolve_inheritance(id, |id| async move {
+        load_version_json(app, client, versions_dir, &id).await
+    })
+    .await
+}
+
+/// Follows the `inheritsFrom` chain of `id`, fetching every version through `load`, and
+/// merges it from the root down.
+pub async fn resolve_inheritance<F, Fut>(id: &str, mut load: F) -> Result<VersionJson>
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = Result<VersionJson>>,
+{
+    let mut chain = vec![load(id.to_string()).await?];
+
+    while let Some(parent_id) = chain.last().and_then(|v| v.inherits_from.clone()) {
+        if chain.len() >= MAX_INHERITANCE_DEPTH || chain.iter().any(|v| v.id == parent_id) {
+            return Err(anyhow!(
+                "version '{}' has a cyclic or too deep inheritsFrom chain",
+                id
+            ));
+        }
+
+        chain.push(load(parent_id).await?);
+    }
+
+    let mut resolved = chain.pop().expect("chain contains at least one version");
+    while let Some(child) = chain.pop() {
+        resolved = child.merge(resolved);
+    }
+
+    Ok(resolved)
+}
+
+pub fn version_json_path(versions_dir: &Path, id: &str) -> PathBuf {
+    versions_dir.join(id).join(format!("{}.json", id))
+}
+
+async fn load_version_json<R: Runtime>(
+    app: &AppHandle<R>,
+    client: &Client,
+    versions_dir: &Path,
+    id: &str,
+) -> Result<VersionJson> {
+    let path = version_json_path(versions_dir, id);
+
+    if path.exists() {
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read version JSON for {}", id))?;
+        return serde_json::from_str(&contents)
+            .with_context(|| format!("failed to parse version JSON for {}", id));
+    }
+
+    let entry = find_version(app, id).await?;
+    debug!("fetching version JSON for {} from {}", id, entry.url);
+
+    let contents = client
+        .get(&entry.url)
+        .send()
+        .await
+        .with_context(|| format!("failed to request version JSON for {}", id))?
+        .error_for_status()
+        .context("version JSON request returned non-success status")?
+        .bytes()
+        .await
+        .context("failed to read version JSON response")?;
+
+    let digest = format!("{:x}", Sha1::digest(&contents));
+    if !digest.eq_ignore_ascii_case(&entry.sha1) {
+        return Err(anyhow!(
+            "version JSON for {} failed verification (expected sha1 {}, got {})",
+            id,
+            entry.sha1,
+            digest
+        ));
+    }
+
+    let version = serde_json::from_slice::<VersionJson>(&contents)
+        .with_context(|| format!("failed to parse version JSON for {}", id))?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("failed to create version directory")?;
+    }
+    fs::write(&path, &contents).context("failed to cache version JSON")?;
+
+    Ok(version)
+}
+
+pub fn versions_dir<R: Runtime>(app: &AppHandle<R>) -> Result<PathBuf> {
+    let data_dir = app
+        .path()
+        .app_data_dir()
+        .context("failed to resolve app data directory")?;
+
+    Ok(data_dir.join("versions"))
+}
+
+async fn download_manifest(client: &Client, source: &ManifestSource) -> Result<String> {
+    let url = source.manifest_url();
+    debug!("fetching version manifest from {}", url);
+
+    client
+        .get(&url)
+        .send()
+        .await
+        .context("failed to request version manifest")?
+        .error_for_status()
+        .context("version manifest request returned non-success status")?
+        .text()
+        .await
+        .context("failed to read version manifest response")
+}
+
+fn read_cached_manifest(path: &Path) -> Result<VersionManifest> {
+    let contents = fs::read_to_string(path).context("failed to read cached version manifest")?;
+    serde_json::from_str(&contents).context("failed to parse cached version manifest")
+}
+
+fn is_younger_than(path: &Path, max_age: Duration) -> bool {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_some_and(|age| age < max_age)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_fixtures::version_json as fixture;
+    use crate::utils::test_server::{block_on, Response, TestServer};
+    use std::collections::HashMap;
+    use std::fs::File;
+
+    fn manifest_json(release: &str) -> String {
+        serde_json::json!({
+            "latest": { "release": release, "snapshot": release },
+            "versions": [{
+                "id": release,
+                "type": "release",
+                "url": format!("https://example.invalid/{}.json", release),
+                "time": "2024-06-13T08:24:03+00:00",
+                "releaseTime": "2024-06-13T08:24:03+00:00",
+                "sha1": "0000000000000000000000000000000000000000",
+                "complianceLevel": 1
+            }]
+        })
+        .to_string()
+    }
+
+    fn manifest_server(release: &'static str) -> TestServer {
+        TestServer::start(move |_| Response::ok(manifest_json(release)))
+    }
+
+    fn write_cache(dir: &Path, release: &str, age: Duration) {
+        let path = dir.join(VERSION_MANIFEST_FILE);
+        fs::write(&path, manifest_json(release)).unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() - age)
+            .unwrap();
+    }
+
+    fn fetch(server_url: &str, dir: &Path, force_refresh: bool) -> Result<VersionManifest> {
+        block_on(fetch_manifest(
+            &Client::new(),
+            &ManifestSource::new(server_url),
+            dir,
+            force_refresh,
+        ))
+    }
+
+    fn find(server_url: &str, dir: &Path, id: &str) -> Result<VersionEntry> {
+        block_on(find_in_manifest(
+            &Client::new(),
+            &ManifestSource::new(server_url),
+            dir,
+            id,
+        ))
+    }
+
+    #[test]
+    fn missing_version_refreshes_an_older_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = manifest_server("1.21");
+        write_cache(dir.path(), "1.20.6", MISS_REFRESH_INTERVAL * 2);
+
+        assert_eq!(find(server.url(), dir.path(), "1.21").unwrap().id, "1.21");
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn unknown_versions_do_not_refresh_a_recent_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = manifest_server("1.21");
+        write_cache(dir.path(), "1.20.6", MISS_REFRESH_INTERVAL * 2);
+
+        for _ in 0..3 {
+            let err = find(server.url(), dir.path(), "bogus").unwrap_err();
+            assert_eq!(err.to_string(), "unknown Minecraft version 'bogus'");
+        }
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn fresh_cache_is_used_without_a_request() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = manifest_server("1.21");
+        write_cache(dir.path(), "1.20.6", Duration::from_secs(60));
+
+        let manifest = fetch(server.url(), dir.path(), false).unwrap();
+
+        assert_eq!(manifest.latest.release, "1.20.6");
+        assert!(server.requests().is_empty());
+    }
+
+    #[test]
+    fn expired_cache_is_refreshed() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = manifest_server("1.21");
+        write_cache(
+            dir.path(),
+            "1.20.6",
+            MANIFEST_CACHE_TTL + Duration::from_secs(60),
+        );
+
+        let manifest = fetch(server.url(), dir.path(), false).unwrap();
+
+        assert_eq!(manifest.latest.release, "1.21");
+        assert_eq!(server.requests_to("/mc/game/").len(), 1);
+        let cached = read_cached_manifest(&dir.path().join(VERSION_MANIFEST_FILE)).unwrap();
+        assert_eq!(cached.latest.release, "1.21");
+    }
+
+    #[test]
+    fn force_refresh_skips_a_fresh_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = manifest_server("1.21");
+        write_cache(dir.path(), "1.20.6", Duration::from_secs(60));
+
+        let manifest = fetch(server.url(), dir.path(), true).unwrap();
+
+        assert_eq!(manifest.latest.release, "1.21");
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn stale_cache_is_used_when_the_request_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = TestServer::start(|_| Response::status(503));
+        write_cache(dir.path(), "1.20.6", MANIFEST_CACHE_TTL * 24);
+
+        let manifest = fetch(server.url(), dir.path(), false).unwrap();
+
+        assert_eq!(manifest.latest.release, "1.20.6");
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn failed_request_without_cache_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = TestServer::start(|_| Response::status(503));
+
+        assert!(fetch(server.url(), dir.path(), false).is_err());
+        assert!(!dir.path().join(VERSION_MANIFEST_FILE).exists());
+    }
+
+    fn version(id: &str, inherits_from: Option<&str>) -> VersionJson {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "inheritsFrom": inherits_from,
+        }))
+        .unwrap()
+    }
+
+    fn resolve(id: &str, versions: Vec<VersionJson>) -> Result<VersionJson> {
+        let versions: HashMap<String, VersionJson> = versions
+            .into_iter()
+            .map(|version| (version.id.clone(), version))
+            .collect();
+
+        block_on(resolve_inheritance(id, |id| {
+            let version = versions
+                .get(&id)
+                .cloned()
+                .ok_or_else(|| anyhow!("unknown version {}", id));
+            async move { version }
+        }))
+    }
+
+    #[test]
+    fn inherited_version_is_merged_over_its_parent() {
+        let resolved = resolve(
+            "fabric-loader-0.16.0-1.21",
+            vec![fixture("fabric-loader-0.16.0-1.21"), fixture("1.21")],
+        )
+        .unwrap();
+
+        assert_eq!(resolved.id, "fabric-loader-0.16.0-1.21");
+        assert!(resolved.inherits_from.is_none());
+        assert_eq!(
+            resolved.main_class.as_deref(),
+            Some("net.fabricmc.loader.impl.launch.knot.KnotClient")
+        );
+        assert_eq!(resolved.assets_id(), Some("17"));
+        assert_eq!(resolved.java_version.unwrap().major_version, 21);
+        assert_eq!(resolved.libraries.len(), 5);
+    }
+
+    #[test]
+    fn version_without_parent_resolves_to_itself() {
+        let resolved = resolve("1.8.9", vec![fixture("1.8.9")]).unwrap();
+
+        assert_eq!(resolved.id, "1.8.9");
+        assert_eq!(resolved.libraries.len(), 3);
+    }
+
+    #[test]
+    fn missing_parent_is_an_error() {
+        let err = resolve(
+            "fabric-loader-0.16.0-1.21",
+            vec![fixture("fabric-loader-0.16.0-1.21")],
+        )
+        .unwrap_err();
+
+        assert!(err.to_string().contains("unknown version 1.21"));
+    }
+
+    #[test]
+    fn cyclic_chain_is_rejected() {
+        let err = resolve("a", vec![version("a", Some("b")), version("b", Some("a"))]).unwrap_err();
+
+        assert!(err.to_string().contains("cyclic or too deep"));
+    }
+
+    #[test]
+    fn chain_depth_is_limited() {
+        let chain = |length: usize| -> Vec<VersionJson> {
+            (0..length)
+                .map(|i| {
+                    let parent = (i + 1 < length).then(|| format!("v{}", i + 1));
+                    version(&format!("v{}", i), parent.as_deref())
+                })
+                .collect()
+        };
+
+        assert!(resolve("v0", chain(MAX_INHERITANCE_DEPTH)).is_ok());
+        let err = resolve("v0", chain(MAX_INHERITANCE_DEPTH + 1)).unwrap_err();
+        assert!(err.to_string().contains("cyclic or too deep"));
+    }
+}
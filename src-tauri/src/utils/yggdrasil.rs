@@ -0,0 +1,647 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::utils::auth_error::AuthError;
+use crate::utils::minecraft_auth::{
+    current_timestamp, AccountRecord, AccountType, MicrosoftTokens, MinecraftTokens, XboxTokens,
+};
+
+pub const AUTHLIB_INJECTOR_META_URL: &str =
+    "https://authlib-injector.yushi.moe/artifact/latest.json";
+
+/// authlib-injector's API Location Indication header, pointing from a friendly URL (e.g. the
+/// skin site's homepage) to the actual API root.
+const API_LOCATION_HEADER: &str = "x-authlib-injector-api-location";
+
+/// Which Yggdrasil server an account belongs to, together with the client token the server
+/// ties its access tokens to.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct YggdrasilSession {
+    pub server_url: String,
+    pub client_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AuthResponse {
+    access_token: String,
+    client_token: String,
+    #[serde(default)]
+    available_profiles: Vec<GameProfile>,
+    selected_profile: Option<GameProfile>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct GameProfile {
+    id: String,
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ErrorResponse {
+    error: String,
+    #[serde(default)]
+    error_message: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct InjectorArtifact {
+    version: String,
+    download_url: String,
+    checksums: InjectorChecksums,
+}
+
+#[derive(Debug, Deserialize)]
+struct InjectorChecksums {
+    sha256: String,
+}
+
+/// Follows the API Location Indication of `url` if the server sends one and returns the API
+/// root the `authserver` endpoints live under.
+pub async fn resolve_api_root(client: &Client, url: &str) -> Result<String> {
+    let url = url.trim().trim_end_matches('/');
+    let url = if url.starts_with("http://") || url.starts_with("https://") {
+        url.to_string()
+    } else {
+        format!("https://{}", url)
+    };
+
+    let response = client
+        .get(&url)
+        .send()
+        .await
+        .with_context(|| format!("failed to reach Yggdrasil server {}", url))?;
+
+    let location = response
+        .headers()
+        .get(API_LOCATION_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
+    match location {
+        Some(location) => {
+            let resolved = response
+                .url()
+                .join(&location)
+                .with_context(|| format!("invalid API location '{}'", location))?;
+            Ok(resolved.as_str().trim_end_matches('/').to_string())
+        }
+        None => Ok(url),
+    }
+}
+
+/// Signs in with a username (or e-mail) and password. Accounts owning several profiles need
+/// `profile` to pick one of them by name.
+pub async fn authenticate(
+    client: &Client,
+    api_root: &str,
+    username: &str,
+    password: &str,
+    profile: Option<&str>,
+) -> Result<AccountRecord> {
+    let client_token = Uuid::new_v4().simple().to_string();
+
+    let response: AuthResponse = post(
+        client,
+        api_root,
+        "authserver/authenticate",
+        json!({
+            "agent": { "name": "Minecraft", "version": 1 },
+            "username": username,
+            "password": password,
+            "clientToken": client_token,
+            "requestUser": false,
+        }),
+    )
+    .await?
+    .context("Yggdrasil server sent an empty authentication response")?;
+
+    let session = YggdrasilSession {
+        server_url: api_root.to_string(),
+        client_token: response.client_token.clone(),
+    };
+
+    if let Some(selected) = response.selected_profile.clone() {
+        return Ok(account_from(session, response.access_token, selected));
+    }
+
+    let chosen = match (profile, response.available_profiles.as_slice()) {
+        (_, []) => return Err(anyhow!("this account has no game profile yet")),
+        (None, [only]) => only.clone(),
+        (Some(name), profiles) => profiles
+            .iter()
+            .find(|candidate| candidate.name.eq_ignore_ascii_case(name))
+            .cloned()
+            .ok_or_else(|| anyhow!("this account has no profile named {}", name))?,
+        (None, profiles) => {
+            let names: Vec<&str> = profiles.iter().map(|p| p.name.as_str()).collect();
+            return Err(anyhow!(
+                "this account has several profiles, choose one of: {}",
+                names.join(", ")
+            ));
+        }
+    };
+
+    // binding a profile to the token is done through a refresh
+    let account = account_from(session, response.access_token, chosen.clone());
+    refresh_with_profile(client, &account, Some(chosen)).await
+}
+
+/// Exchanges the account's access token for a new one.
+pub async fn refresh(client: &Client, account: &AccountRecord) -> Result<AccountRecord> {
+    refresh_with_profile(client, account, None).await
+}
+
+/// Whether the server still accepts the account's access token.
+pub async fn validate(client: &Client, account: &AccountRecord) -> Result<bool> {
+    let session = session_of(account)?;
+    let result: Result<Option<serde_json::Value>> = post(
+        client,
+        &session.server_url,
+        "authserver/validate",
+        json!({
+            "accessToken": account.minecraft.access_token,
+            "clientToken": session.client_token,
+        }),
+    )
+    .await;
+
+    match result {
+        Ok(_) => Ok(true),
+        Err(err)
+            if err
+                .chain()
+                .any(|cause| matches!(cause.downcast_ref(), Some(AuthError::Yggdrasil { .. }))) =>
+        {
+            Ok(false)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Revokes the account's access token, e.g. when the account is removed.
+pub async fn invalidate(client: &Client, account: &AccountRecord) -> Result<()> {
+    let session = session_of(account)?;
+    post::<serde_json::Value>(
+        client,
+        &session.server_url,
+        "authserver/invalidate",
+        json!({
+            "accessToken": account.minecraft.access_token,
+            "clientToken": session.client_token,
+        }),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Makes sure a verified authlib-injector jar is present in `dir` and returns its path. When
+/// the release metadata cannot be fetched a previously downloaded jar is used instead.
+pub async fn ensure_authlib_injector(client: &Client, dir: &Path) -> Result<PathBuf> {
+    let meta_url = env::var("DECENT_AUTHLIB_INJECTOR_URL")
+        .ok()
+        .map(|value| value.trim().to_owned())
+        .filter(|value| !value.is_empty())
+        .unwrap_or_else(|| AUTHLIB_INJECTOR_META_URL.to_string());
+
+    let artifact = match fetch_artifact(client, &meta_url).await {
+        Ok(artifact) => artifact,
+        Err(err) => {
+            return match installed_injector(dir) {
+                Some(jar) => {
+                    warn!(
+                        "failed to check for authlib-injector updates, using {:?}: {:#}",
+                        jar, err
+                    );
+                    Ok(jar)
+                }
+                None => Err(err.context("failed to fetch authlib-injector metadata")),
+            }
+        }
+    };
+
+    let jar = dir.join(format!("authlib-injector-{}.jar", artifact.version));
+    if jar.is_file() {
+        return Ok(jar);
+    }
+
+    info!("downloading authlib-injector {}", artifact.version);
+
+    let bytes = client
+        .get(&artifact.download_url)
+        .send()
+        .await
+        .context("failed to download authlib-injector")?
+        .error_for_status()
+        .context("authlib-injector download returned non-success status")?
+        .bytes()
+        .await
+        .context("failed to read authlib-injector download")?;
+
+    let digest = format!("{:x}", Sha256::digest(&bytes));
+    if !digest.eq_ignore_ascii_case(&artifact.checksums.sha256) {
+        return Err(anyhow!(
+            "authlib-injector failed verification (expected sha256 {}, got {})",
+            artifact.checksums.sha256,
+            digest
+        ));
+    }
+
+    fs::create_dir_all(dir).with_context(|| format!("failed to create directory {:?}", dir))?;
+    fs::write(&jar, &bytes).with_context(|| format!("failed to write {:?}", jar))?;
+
+    Ok(jar)
+}
+
+async fn refresh_with_profile(
+    client: &Client,
+    account: &AccountRecord,
+    profile: Option<GameProfile>,
+) -> Result<AccountRecord> {
+    let session = session_of(account)?;
+
+    let mut body = json!({
+        "accessToken": account.minecraft.access_token,
+        "clientToken": session.client_token,
+        "requestUser": false,
+    });
+    if let Some(profile) = &profile {
+        body["selectedProfile"] = json!(profile);
+    }
+
+    let response: AuthResponse = post(client, &session.server_url, "authserver/refresh", body)
+        .await?
+        .context("Yggdrasil server sent an empty refresh response")?;
+
+    let selected = response
+        .selected_profile
+        .or(profile)
+        .unwrap_or_else(|| GameProfile {
+            id: account.uuid.clone(),
+            name: account.username.clone(),
+        });
+
+    let mut refreshed = account_from(
+        YggdrasilSession {
+            server_url: session.server_url.clone(),
+            client_token: response.client_token,
+        },
+        response.access_token,
+        selected,
+    );
+    refreshed.is_active = account.is_active;
+    Ok(refreshed)
+}
+
+fn session_of(account: &AccountRecord) -> Result<&YggdrasilSession> {
+    account
+        .yggdrasil
+        .as_ref()
+        .ok_or_else(|| anyhow!("account {} is not a Yggdrasil account", account.username))
+}
+
+fn account_from(
+    session: YggdrasilSession,
+    access_token: String,
+    profile: GameProfile,
+) -> AccountRecord {
+    AccountRecord {
+        uuid: profile.id,
+        username: profile.name.clone(),
+        obtained_at: current_timestamp(),
+        microsoft: MicrosoftTokens {
+            access_token: String::new(),
+            refresh_token: String::new(),
+            expires_at: None,
+        },
+        xbox: XboxTokens {
+            user_token: String::new(),
+            xsts_token: String::new(),
+            uhs: String::new(),
+        },
+        minecraft: MinecraftTokens {
+            access_token,
+            expires_at: None,
+            username: profile.name,
+        },
+        is_active: false,
+        account_type: AccountType::Yggdrasil,
+        yggdrasil: Some(session),
+        entitlements: None,
+        needs_profile: false,
+    }
+}
+
+/// POSTs `body` to `<api_root>/<path>`, returning `None` for `204 No Content` and turning
+/// Yggdrasil error bodies into [`AuthError::Yggdrasil`].
+async fn post<T: serde::de::DeserializeOwned>(
+    client: &Client,
+    api_root: &str,
+    path: &str,
+    body: serde_json::Value,
+) -> Result<Option<T>> {
+    let url = format!("{}/{}", api_root.trim_end_matches('/'), path);
+    let response = client
+        .post(&url)
+        .json(&body)
+        .send()
+        .await
+        .with_context(|| format!("failed to request {}", url))?;
+
+    let status = response.status();
+    if status == StatusCode::NO_CONTENT {
+        return Ok(None);
+    }
+
+    if !status.is_success() {
+        let error = response
+            .json::<ErrorResponse>()
+            .await
+            .unwrap_or_else(|_| ErrorResponse {
+                error: status.to_string(),
+                error_message: None,
+            });
+        return Err(AuthError::Yggdrasil {
+            message: error.error_message.unwrap_or_else(|| error.error.clone()),
+            error: error.error,
+        }
+        .into());
+    }
+
+    let parsed = response
+        .json::<T>()
+        .await
+        .with_context(|| format!("failed to parse response from {}", url))?;
+
+    Ok(Some(parsed))
+}
+
+async fn fetch_artifact(client: &Client, url: &str) -> Result<InjectorArtifact> {
+    client
+        .get(url)
+        .send()
+        .await
+        .with_context(|| format!("failed to request {}", url))?
+        .error_for_status()
+        .with_context(|| format!("{} returned non-success status", url))?
+        .json::<InjectorArtifact>()
+        .await
+        .with_context(|| format!("failed to parse response from {}", url))
+}
+
+fn installed_injector(dir: &Path) -> Option<PathBuf> {
+    fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("authlib-injector-") && name.ends_with(".jar"))
+        })
+        .max_by_key(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_server::{block_on, Request, Response, TestServer};
+
+    const STEVE: &str = "1b2f0a5e0c4b4a8f9d1e6b3c2a7d9e40";
+    const ALEX: &str = "2c3d4e5f60718293a4b5c6d7e8f90a1b";
+
+    fn forbidden() -> Response {
+        Response::json(
+            403,
+            json!({
+                "error": "ForbiddenOperationException",
+                "errorMessage": "Invalid token."
+            }),
+        )
+    }
+
+    /// A skin site with its API under `/api/yggdrasil`, accepting the password `hunter2` and
+    /// handing out `token-1`, then `token-2` after a refresh.
+    fn yggdrasil_server(profiles: serde_json::Value) -> TestServer {
+        TestServer::start(move |request: &Request| {
+            let path = request.path.as_str();
+            match path {
+                "/" => {
+                    let mut response = Response::ok("skin site");
+                    response.headers.push((
+                        "X-Authlib-Injector-API-Location".to_string(),
+                        "/api/yggdrasil/".to_string(),
+                    ));
+                    response
+                }
+                "/api/yggdrasil/authserver/authenticate" => {
+                    let body = request.json();
+                    if body["password"] != "hunter2" {
+                        return forbidden();
+                    }
+                    let mut response = json!({
+                        "accessToken": "token-1",
+                        "clientToken": body["clientToken"],
+                        "availableProfiles": profiles,
+                    });
+                    if let [only] = profiles.as_array().unwrap().as_slice() {
+                        response["selectedProfile"] = only.clone();
+                    }
+                    Response::json(200, response)
+                }
+                "/api/yggdrasil/authserver/refresh" => {
+                    let body = request.json();
+                    if body["accessToken"] != "token-1" {
+                        return forbidden();
+                    }
+                    Response::json(
+                        200,
+                        json!({
+                            "accessToken": "token-2",
+                            "clientToken": body["clientToken"],
+                            "selectedProfile": body.get("selectedProfile"),
+                        }),
+                    )
+                }
+                "/api/yggdrasil/authserver/validate" => {
+                    if request.json()["accessToken"] == "token-2" {
+                        Response::status(204)
+                    } else {
+                        forbidden()
+                    }
+                }
+                _ => Response::status(404),
+            }
+        })
+    }
+
+    fn profiles(ids: &[(&str, &str)]) -> serde_json::Value {
+        ids.iter()
+            .map(|(id, name)| json!({ "id": id, "name": name }))
+            .collect()
+    }
+
+    #[test]
+    fn api_location_header_is_followed() {
+        let server = yggdrasil_server(profiles(&[]));
+
+        let root = block_on(resolve_api_root(&Client::new(), server.url())).unwrap();
+        assert_eq!(root, format!("{}/api/yggdrasil", server.url()));
+
+        let direct = format!("{}/api/yggdrasil/", server.url());
+        let root = block_on(resolve_api_root(&Client::new(), &direct)).unwrap();
+        assert_eq!(root, format!("{}/api/yggdrasil", server.url()));
+    }
+
+    #[test]
+    fn authenticate_uses_the_selected_profile() {
+        let server = yggdrasil_server(profiles(&[(STEVE, "Steve")]));
+        let root = format!("{}/api/yggdrasil", server.url());
+
+        let account = block_on(authenticate(
+            &Client::new(),
+            &root,
+            "steve@example.com",
+            "hunter2",
+            None,
+        ))
+        .unwrap();
+
+        assert_eq!(account.uuid, STEVE);
+        assert_eq!(account.username, "Steve");
+        assert_eq!(account.minecraft.access_token, "token-1");
+        assert_eq!(account.account_type, AccountType::Yggdrasil);
+        let session = account.yggdrasil.unwrap();
+        assert_eq!(session.server_url, root);
+        let sent = &server.requests_to("/api/yggdrasil/authserver/authenticate")[0];
+        assert_eq!(sent.json()["clientToken"], session.client_token);
+        assert!(server
+            .requests_to("/api/yggdrasil/authserver/refresh")
+            .is_empty());
+    }
+
+    #[test]
+    fn authenticate_binds_the_chosen_profile_through_a_refresh() {
+        let server = yggdrasil_server(profiles(&[(STEVE, "Steve"), (ALEX, "Alex")]));
+        let root = format!("{}/api/yggdrasil", server.url());
+
+        let account = block_on(authenticate(
+            &Client::new(),
+            &root,
+            "shared@example.com",
+            "hunter2",
+            Some("alex"),
+        ))
+        .unwrap();
+
+        assert_eq!(account.uuid, ALEX);
+        assert_eq!(account.minecraft.access_token, "token-2");
+        let refresh = &server.requests_to("/api/yggdrasil/authserver/refresh")[0];
+        assert_eq!(refresh.json()["selectedProfile"]["id"], ALEX);
+    }
+
+    #[test]
+    fn authenticate_asks_to_choose_between_profiles() {
+        let server = yggdrasil_server(profiles(&[(STEVE, "Steve"), (ALEX, "Alex")]));
+        let root = format!("{}/api/yggdrasil", server.url());
+
+        let err = block_on(authenticate(
+            &Client::new(),
+            &root,
+            "shared@example.com",
+            "hunter2",
+            None,
+        ))
+        .unwrap_err();
+        assert!(err.to_string().contains("Steve, Alex"), "{:#}", err);
+
+        let err = block_on(authenticate(
+            &Client::new(),
+            &root,
+            "shared@example.com",
+            "hunter2",
+            Some("Herobrine"),
+        ))
+        .unwrap_err();
+        assert!(err.to_string().contains("no profile named Herobrine"));
+    }
+
+    #[test]
+    fn wrong_password_is_a_yggdrasil_error() {
+        let server = yggdrasil_server(profiles(&[(STEVE, "Steve")]));
+        let root = format!("{}/api/yggdrasil", server.url());
+
+        let err = block_on(authenticate(
+            &Client::new(),
+            &root,
+            "steve@example.com",
+            "wrong",
+            None,
+        ))
+        .unwrap_err();
+
+        match err.downcast_ref::<AuthError>() {
+            Some(AuthError::Yggdrasil { error, message }) => {
+                assert_eq!(error, "ForbiddenOperationException");
+                assert_eq!(message, "Invalid token.");
+            }
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn refresh_and_validate_follow_the_token() {
+        let server = yggdrasil_server(profiles(&[(STEVE, "Steve")]));
+        let root = format!("{}/api/yggdrasil", server.url());
+        let client = Client::new();
+
+        let mut account = block_on(authenticate(
+            &client,
+            &root,
+            "steve@example.com",
+            "hunter2",
+            None,
+        ))
+        .unwrap();
+        account.is_active = true;
+        assert!(!block_on(validate(&client, &account)).unwrap());
+
+        let refreshed = block_on(refresh(&client, &account)).unwrap();
+        assert_eq!(refreshed.uuid, STEVE);
+        assert_eq!(refreshed.minecraft.access_token, "token-2");
+        assert!(refreshed.is_active);
+        assert!(block_on(validate(&client, &refreshed)).unwrap());
+
+        // the old token was used up by the refresh
+        assert!(block_on(refresh(&client, &refreshed)).is_err());
+    }
+
+    #[test]
+    fn validate_reports_unreachable_servers() {
+        let server = yggdrasil_server(profiles(&[(STEVE, "Steve")]));
+        let root = format!("{}/api/yggdrasil", server.url());
+        let mut account = block_on(authenticate(
+            &Client::new(),
+            &root,
+            "steve@example.com",
+            "hunter2",
+            None,
+        ))
+        .unwrap();
+
+        // nothing listens on the discard port
+        account.yggdrasil.as_mut().unwrap().server_url = "http://127.0.0.1:9".to_string();
+        assert!(block_on(validate(&Client::new(), &account)).is_err());
+    }
+}